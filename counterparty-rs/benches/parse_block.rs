@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+use bitcoin::{consensus::deserialize, Block};
+use counterparty_rs::bench::{
+    parse_block, parse_block_streaming, parse_block_with_prev_txs, Config, Height, Network,
+    ParseWorker, Stopper,
+};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use crossbeam_channel::bounded;
+
+const HEIGHT: Height = Height(900_000);
+
+const FIXTURES: [(&str, &str); 4] = [
+    ("empty", include_str!("data/empty.hex")),
+    ("p2pkh_heavy", include_str!("data/p2pkh_heavy.hex")),
+    (
+        "multisig_data_heavy",
+        include_str!("data/multisig_data_heavy.hex"),
+    ),
+    ("taproot_reveal", include_str!("data/taproot_reveal.hex")),
+];
+
+fn fixtures() -> Vec<(&'static str, Vec<u8>, Block)> {
+    FIXTURES
+        .iter()
+        .map(|(name, hex)| {
+            let bytes = hex::decode(hex.trim()).expect("fixture is not valid hex");
+            let block = deserialize(&bytes).expect("fixture is not a valid block");
+            (*name, bytes, block)
+        })
+        .collect()
+}
+
+fn config() -> Config {
+    // No RPC endpoint: previous output lookups fail immediately, so only parsing is timed.
+    let mut config = Config::with_defaults(Network::Mainnet);
+    config.enable_all_protocol_changes = true;
+    config
+}
+
+// Each fixture is measured twice, once per throughput unit, so criterion reports both
+// time per transaction and bytes per second.
+fn bench_parse_block(c: &mut Criterion) {
+    let config = config();
+    let fixtures = fixtures();
+
+    let mut group = c.benchmark_group("parse_block_per_tx");
+    for (name, _, block) in &fixtures {
+        group.throughput(Throughput::Elements(block.txdata.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), block, |b, block| {
+            b.iter(|| parse_block(black_box(block.clone()), &config, HEIGHT, true))
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("parse_block_bytes");
+    for (name, bytes, block) in &fixtures {
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), block, |b, block| {
+            b.iter(|| parse_block(black_box(block.clone()), &config, HEIGHT, true))
+        });
+    }
+    group.finish();
+
+    // With an empty prev-tx map no lookups are attempted at all, so this is the parse
+    // time without the failed RPC calls above.
+    let prev_txs = HashMap::new();
+    let mut group = c.benchmark_group("parse_block_with_prev_txs_per_tx");
+    for (name, _, block) in &fixtures {
+        group.throughput(Throughput::Elements(block.txdata.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), block, |b, block| {
+            b.iter(|| {
+                parse_block_with_prev_txs(
+                    black_box(block.clone()),
+                    &config,
+                    HEIGHT,
+                    true,
+                    &prev_txs,
+                )
+            })
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("parse_block_streaming_per_tx");
+    for (name, _, block) in &fixtures {
+        group.throughput(Throughput::Elements(block.txdata.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), block, |b, block| {
+            b.iter(|| {
+                parse_block_streaming(black_box(block.clone()), &config, HEIGHT, true)
+                    .map(|transactions| transactions.count())
+            })
+        });
+    }
+    group.finish();
+}
+
+// All fixtures through the parse stage of a pipeline, with `n` parsers, until they come
+// back out in height order. Starting and stopping the pool is part of each iteration.
+fn bench_parse_worker(c: &mut Criterion) {
+    let config = config();
+    let blocks: Vec<_> = fixtures().into_iter().map(|(_, _, block)| block).collect();
+
+    let mut group = c.benchmark_group("parse_worker");
+    group.throughput(Throughput::Elements(blocks.len() as u64));
+    for n in [1, 4] {
+        group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, &n| {
+            b.iter(|| {
+                let (tx_in, rx_in) = bounded(blocks.len());
+                let (tx_out, rx_out) = bounded(blocks.len());
+                let stopper = Stopper::new();
+                let handles = ParseWorker::new(config.clone(), n)
+                    .start(HEIGHT.0, rx_in, tx_out, stopper.clone())
+                    .expect("parse pool failed to start");
+                for (i, block) in blocks.iter().enumerate() {
+                    let height = Height(HEIGHT.0 + i as u32);
+                    tx_in
+                        .send(Box::new((height, block.clone())))
+                        .expect("parse pool stopped");
+                }
+                for _ in &blocks {
+                    black_box(rx_out.recv().expect("parse pool stopped"));
+                }
+                stopper.stop().expect("failed to stop parse pool");
+                for handle in handles {
+                    let _ = handle.join();
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse_block, bench_parse_worker);
+criterion_main!(benches);
@@ -1,97 +1,331 @@
 use std::cmp::min;
-use std::collections::HashMap;
-use std::iter::repeat;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ops::Range;
 use std::thread::JoinHandle;
 
 use crate::b58::b58_encode;
-use crate::utils::{script_to_address, script_to_address_legacy};
+use crate::utils::{rc4, script_to_address, script_to_address_legacy};
 use bitcoin::{
+    block::Header,
     consensus::serialize,
     hashes::{hex::prelude::*, ripemd160, sha256, sha256d::Hash as Sha256dHash, Hash},
-    opcodes::all::{
-        OP_CHECKMULTISIG, OP_CHECKSIG, OP_EQUAL, OP_HASH160, OP_PUSHNUM_1, OP_PUSHNUM_2,
-        OP_PUSHNUM_3, OP_RETURN,
+    opcodes::{
+        all::{
+            OP_CHECKMULTISIG, OP_CHECKSIG, OP_CHECKSIGADD, OP_CHECKSIGVERIFY, OP_ENDIF, OP_EQUAL,
+            OP_HASH160, OP_IF, OP_NOTIF, OP_PUSHNUM_1, OP_PUSHNUM_2, OP_PUSHNUM_3, OP_RETURN,
+        },
+        Opcode, OP_FALSE,
     },
     script::Instruction::{Op, PushBytes},
-    Block, BlockHash, Script, TxOut, Txid,
+    taproot::ControlBlock,
+    Block, BlockHash, OutPoint, Script, ScriptBuf, TxIn, TxOut, Txid, WitnessVersion,
 };
 
-use crossbeam_channel::{bounded, select, unbounded, Receiver, Sender};
-use crypto::rc4::Rc4;
-use crypto::symmetriccipher::SynchronousStreamCipher;
+use crossbeam_channel::{
+    bounded, select, unbounded, Receiver, SendTimeoutError, Sender, TrySendError,
+};
+use uuid::Uuid;
 
 use crate::indexer::block::VinOutput;
-use crate::indexer::rpc_client::{BatchRpcClient, BATCH_CLIENT};
+use crate::indexer::rpc_client::{
+    BatchRpcClient, BatchRpcError, CacheStats, NetworkInfo, BATCH_CLIENT,
+};
 
-use std::sync::Arc;
+use lru::LruCache;
+use pyo3::{
+    types::{PyAnyMethods, PyBytes, PyDict},
+    IntoPy, PyObject, Python,
+};
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
 
 use serde_cbor::Value;
 
 use super::{
     block::{
-        Block as CrateBlock, ParsedVouts, PotentialDispenser, ToBlock, Transaction, Vin, Vout,
+        Block as CrateBlock, DataSource, ParsedVouts, PotentialDispenser, ScriptType, ToBlock,
+        Transaction, Vin, Vout, VoutWarning,
     },
-    config::{Config, Mode},
-    stopper::Stopper,
+    config::{Config, Mode, RequestQueuePolicy},
+    database::DatabaseOps,
+    stopper::{Done, Stopper},
     types::{
         entry::{
             BlockAtHeightHasHash, BlockAtHeightSpentOutputInTx,
+            ElectrumScriptHashHasOutputsInBlockAtHeight, PotentialDispenserAtOutput,
+            RawBlockBytesAtHeight, RevealCommitsToTxInBlockAtHeight,
             ScriptHashHasOutputsInBlockAtHeight, ToEntry, TxInBlockAtHeight, WritableEntry,
         },
         error::Error,
-        pipeline::{BlockHasEntries, BlockHasPrevBlockHash},
+        height::Height,
+        pipeline::BlockHasEntries,
     },
-    workers::new_worker_pool,
+    utils::electrum_scripthash,
+    workers::{new_worker_pool, parser::ParseWorker},
 };
 
+// Index of the tapscript in a reveal input's witness. Walking from the end: an optional
+// BIP341 annex (a last item starting with 0x50), the control block, then the script,
+// preceded by the signature.
+fn reveal_script_position(len: usize, has_annex: bool) -> Option<usize> {
+    let len = if has_annex { len - 1 } else { len };
+    (len == 3).then(|| len - 2)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaprootControlBlock {
+    pub leaf_version: u8,
+    pub internal_key: [u8; 32],
+    pub merkle_path: Vec<[u8; 32]>,
+}
+
+// Decodes the control block that follows the tapscript in a script-path spend.
+pub fn parse_taproot_control_block(bytes: &[u8]) -> Result<TaprootControlBlock, Error> {
+    let control_block = ControlBlock::decode(bytes)
+        .map_err(|e| Error::ParseVout(format!("Invalid taproot control block: {}", e)))?;
+    Ok(TaprootControlBlock {
+        leaf_version: control_block.leaf_version.to_consensus(),
+        internal_key: control_block.internal_key.serialize(),
+        merkle_path: control_block
+            .merkle_branch
+            .iter()
+            .map(|node| node.to_byte_array())
+            .collect(),
+    })
+}
+
 impl BlockHasEntries for Block {
-    fn get_entries(&self, mode: Mode, height: u32) -> Vec<Box<dyn ToEntry>> {
+    fn get_entries(
+        &self,
+        config: &Config,
+        height: Height,
+        parsed: &CrateBlock,
+    ) -> Vec<Box<dyn ToEntry>> {
+        let height = height.into();
         let hash = self.block_hash().as_byte_array().to_owned();
         let mut entries: Vec<Box<dyn ToEntry>> =
             vec![Box::new(WritableEntry::new(BlockAtHeightHasHash {
                 height,
                 hash,
             }))];
-        if mode == Mode::Fetcher {
-            return entries;
+        if config.store_raw_blocks {
+            entries.push(Box::new(WritableEntry::new(RawBlockBytesAtHeight {
+                height,
+                bytes: serialize(self),
+            })));
         }
-        let mut script_hashes = HashMap::new();
-        for tx in self.txdata.iter() {
+        match config.mode {
+            Mode::Indexer => {}
+            Mode::SpendTracker => {
+                entries.extend(
+                    self.txdata
+                        .iter()
+                        .flat_map(|tx| spent_output_entries(tx, height)),
+                );
+                return entries;
+            }
+            Mode::Fetcher | Mode::Headers => return entries,
+        }
+        // Outputs paying the same script share one entry per block.
+        let mut script_hashes = BTreeSet::new();
+        let mut electrum_script_hashes = BTreeSet::new();
+        for (tx, parsed_tx) in self.txdata.iter().zip(parsed.transactions.iter()) {
             let entry = TxInBlockAtHeight {
                 txid: tx.compute_txid().to_byte_array(),
                 height,
             };
             entries.push(Box::new(WritableEntry::new(entry)));
-            for i in tx.input.iter() {
-                let entry = BlockAtHeightSpentOutputInTx {
-                    txid: i.previous_output.txid.to_byte_array(),
-                    vout: i.previous_output.vout,
+            // A reveal spends its commit with its first input.
+            let is_reveal_tx = parsed_tx
+                .parsed_vouts
+                .as_ref()
+                .is_ok_and(|parsed_vouts| parsed_vouts.is_reveal_tx);
+            if let Some(commit) = tx.input.first().filter(|_| is_reveal_tx) {
+                let entry = RevealCommitsToTxInBlockAtHeight {
+                    reveal_txid: tx.compute_txid().to_byte_array(),
+                    commit_txid: commit.previous_output.txid.to_byte_array(),
                     height,
                 };
                 entries.push(Box::new(WritableEntry::new(entry)));
             }
-            for o in tx.output.iter() {
-                let script_hash = o.script_pubkey.script_hash().as_byte_array().to_owned();
-                script_hashes.entry(script_hash).or_insert_with(|| {
-                    let entry = ScriptHashHasOutputsInBlockAtHeight {
-                        script_hash,
+            entries.extend(spent_output_entries(tx, height));
+            // `potential_dispensers` runs parallel to the outputs.
+            let potential_dispensers = parsed_tx
+                .parsed_vouts
+                .as_ref()
+                .ok()
+                .filter(|_| config.potential_dispenser_index)
+                .map(|parsed_vouts| parsed_vouts.potential_dispensers.as_slice())
+                .unwrap_or_default();
+            for (vout, pd) in potential_dispensers.iter().enumerate() {
+                if let Some(PotentialDispenser {
+                    destination: Some(destination),
+                    value: Some(value),
+                }) = pd
+                {
+                    let entry = PotentialDispenserAtOutput {
+                        txid: tx.compute_txid().to_byte_array(),
+                        vout: vout as u32,
+                        destination: destination.clone(),
+                        value: *value,
                         height,
                     };
                     entries.push(Box::new(WritableEntry::new(entry)));
-                });
+                }
             }
+            for o in tx.output.iter() {
+                script_hashes.insert(o.script_pubkey.script_hash().to_byte_array());
+                if config.electrum_scripthash_index {
+                    electrum_script_hashes.insert(electrum_scripthash(&o.script_pubkey));
+                }
+            }
+        }
+        for script_hash in script_hashes {
+            let entry = ScriptHashHasOutputsInBlockAtHeight {
+                script_hash,
+                height,
+            };
+            entries.push(Box::new(WritableEntry::new(entry)));
+        }
+        for script_hash in electrum_script_hashes {
+            let entry = ElectrumScriptHashHasOutputsInBlockAtHeight {
+                script_hash,
+                height,
+            };
+            entries.push(Box::new(WritableEntry::new(entry)));
         }
         entries
     }
 }
 
+fn spent_output_entries(
+    tx: &bitcoin::Transaction,
+    height: u32,
+) -> impl Iterator<Item = Box<dyn ToEntry>> + '_ {
+    tx.input.iter().map(move |i| {
+        let entry = BlockAtHeightSpentOutputInTx {
+            txid: i.previous_output.txid.to_byte_array(),
+            vout: i.previous_output.vout,
+            height,
+        };
+        Box::new(WritableEntry::new(entry)) as Box<dyn ToEntry>
+    })
+}
+
 fn arc4_decrypt(key: &[u8], data: &[u8]) -> Vec<u8> {
-    let mut rc4 = Rc4::new(key);
-    let mut result: Vec<u8> = repeat(0).take(data.len()).collect();
-    rc4.process(data, &mut result);
-    result
+    rc4(key, data)
+}
+
+// ARC4 under one key. The keystream is kept and only regenerated when longer data comes
+// along, so decrypting many outputs under the same key runs the key schedule once.
+struct Arc4 {
+    key: Vec<u8>,
+    keystream: Vec<u8>,
+}
+
+impl Arc4 {
+    fn new(key: Vec<u8>) -> Self {
+        Arc4 {
+            key,
+            keystream: Vec::new(),
+        }
+    }
+
+    fn decrypt(&mut self, data: &[u8]) -> Vec<u8> {
+        if self.keystream.len() < data.len() {
+            self.keystream = rc4(&self.key, &vec![0; data.len()]);
+        }
+        data.iter()
+            .zip(&self.keystream)
+            .map(|(byte, key_byte)| byte ^ key_byte)
+            .collect()
+    }
+}
+
+// The messages batched in `data`, split on `separator`. Every separator starts a new
+// message, so the split is exact: separators at either end or back to back give empty
+// messages. Without a separator the data is a single message.
+fn split_messages(data: &[u8], separator: Option<&[u8]>) -> Vec<Vec<u8>> {
+    if data.is_empty() {
+        return vec![];
+    }
+    let Some(separator) = separator.filter(|separator| !separator.is_empty()) else {
+        return vec![data.to_vec()];
+    };
+    let mut messages = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+    while i + separator.len() <= data.len() {
+        if data[i..].starts_with(separator) {
+            messages.push(data[start..i].to_vec());
+            i += separator.len();
+            start = i;
+        } else {
+            i += 1;
+        }
+    }
+    messages.push(data[start..].to_vec());
+    messages
+}
+
+// The message in a decoded payload, or None when it doesn't carry `prefix`. OP_RETURN
+// and scriptSig payloads are the prefix then the message. Checksig and multisig
+// payloads carry a length byte first, counting the prefix and message; it is clamped to
+// the payload, and one too short to cover the prefix leaves an empty message. No other
+// source carries a prefix.
+fn extract_message_payload(source: DataSource, decrypted: &[u8], prefix: &[u8]) -> Option<Vec<u8>> {
+    match source {
+        DataSource::OpReturn | DataSource::ScriptSig => {
+            decrypted.strip_prefix(prefix).map(<[u8]>::to_vec)
+        }
+        DataSource::Checksig | DataSource::Multisig => {
+            let (&len, rest) = decrypted.split_first()?;
+            rest.strip_prefix(prefix)?;
+            let data_len = min(len as usize, rest.len());
+            Some(
+                rest.get(prefix.len()..data_len)
+                    .unwrap_or_default()
+                    .to_vec(),
+            )
+        }
+        DataSource::TaprootReveal | DataSource::P2wshReveal | DataSource::Mixed => None,
+    }
+}
+
+// The message under the first configured prefix a payload carries, with that prefix's
+// index in `config.prefixes`.
+fn message_payload(
+    config: &Config,
+    source: DataSource,
+    decrypted: &[u8],
+) -> Option<(Vec<u8>, usize)> {
+    config
+        .prefixes
+        .iter()
+        .enumerate()
+        .find_map(|(index, prefix)| {
+            extract_message_payload(source, decrypted, prefix).map(|data| (data, index))
+        })
 }
 
+// Data pushed into an input's scriptSig, found by decrypting each push with the
+// transaction key and looking for a prefix. Returned with the index of that prefix.
+fn extract_data_from_script_sig(
+    config: &Config,
+    key: &[u8],
+    script_sig: &Script,
+) -> Option<(Vec<u8>, usize)> {
+    script_sig
+        .instructions()
+        .find_map(|instruction| match instruction {
+            Ok(PushBytes(pb)) if !pb.is_empty() => message_payload(
+                config,
+                DataSource::ScriptSig,
+                &arc4_decrypt(key, pb.as_bytes()),
+            ),
+            _ => None,
+        })
+}
 
 fn is_valid_segwit_script_legacy(script: &Script) -> bool {
     if let Some(Ok(PushBytes(pb))) = script.instructions().next() {
@@ -117,9 +351,69 @@ fn is_valid_segwit_script(script: &Script) -> bool {
     false
 }
 
-enum ParseOutput {
+// Witness versions 2-16 are not yet defined by consensus but are valid destinations.
+fn is_future_witness_program(script: &Script) -> bool {
+    script
+        .witness_version()
+        .is_some_and(|version| version.to_num() >= 2)
+}
+
+fn is_bare_marker(script: &Script) -> bool {
+    matches!(
+        script.instructions().collect::<Vec<_>>().as_slice(),
+        [Ok(Op(OP_RETURN)), Ok(PushBytes(pb))] if pb.as_bytes() == b"CNTRPRTY"
+    )
+}
+
+// How many single byte insertions, deletions or substitutions turn `a` into `b`.
+fn edit_distance(a: &[u8], b: &[u8]) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, x) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(x != y);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+// An OP_RETURN push within two edits of the reveal marker, ignoring case, that isn't
+// the marker itself: padded, truncated, lowercased or mistyped. Never used to decide
+// whether a transaction is a reveal.
+fn marker_variant(script: &Script) -> Option<&[u8]> {
+    match script.instructions().collect::<Vec<_>>().as_slice() {
+        [Ok(Op(OP_RETURN)), Ok(PushBytes(pb))] => {
+            let bytes = pb.as_bytes();
+            (bytes != b"CNTRPRTY"
+                && bytes.len().abs_diff(8) <= 2
+                && edit_distance(&bytes.to_ascii_uppercase(), b"CNTRPRTY") <= 2)
+                .then_some(bytes)
+        }
+        _ => None,
+    }
+}
+
+// Mirrors the branch order in `parse_vout` for outputs that yielded data.
+fn data_source_for_script(script: &Script) -> DataSource {
+    if script.is_op_return() {
+        DataSource::OpReturn
+    } else if script.instructions().last() == Some(Ok(Op(OP_CHECKMULTISIG))) {
+        DataSource::Multisig
+    } else {
+        DataSource::Checksig
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseOutput {
     Destination(String),
-    Data(Vec<u8>),
+    // The data, with the index in `config.prefixes` of the prefix it was found under.
+    Data(Vec<u8>, Option<usize>),
+    // Outputs that carry neither data nor a destination, e.g. an empty OP_RETURN.
+    Skip,
 }
 
 impl ParseOutput {
@@ -128,14 +422,282 @@ impl ParseOutput {
     }
 }
 
+impl std::fmt::Display for ParseOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseOutput::Destination(destination) => write!(f, "destination {}", destination),
+            ParseOutput::Data(data, _) => {
+                write!(
+                    f,
+                    "data ({} bytes) {}",
+                    data.len(),
+                    data.to_lower_hex_string()
+                )
+            }
+            ParseOutput::Skip => write!(f, "skipped"),
+        }
+    }
+}
+
+impl IntoPy<PyObject> for ParseOutput {
+    #[allow(clippy::unwrap_used)]
+    fn into_py(self, py: Python<'_>) -> PyObject {
+        let dict = PyDict::new_bound(py);
+        match self {
+            ParseOutput::Destination(destination) => {
+                dict.set_item("type", "destination").unwrap();
+                dict.set_item("destination", destination).unwrap();
+            }
+            ParseOutput::Data(data, prefix) => {
+                dict.set_item("type", "data").unwrap();
+                dict.set_item("data", PyBytes::new_bound(py, &data))
+                    .unwrap();
+                dict.set_item("prefix", prefix).unwrap();
+            }
+            ParseOutput::Skip => dict.set_item("type", "skip").unwrap(),
+        }
+        dict.unbind().into()
+    }
+}
+
+// Appended to parse errors so unrecognized scripts can be diagnosed from the logs alone.
+fn script_details(config: &Config, script: &Script) -> String {
+    if !config.verbose_parse_errors {
+        return String::new();
+    }
+    format!(
+        " | script: {}, asm: {}",
+        script.as_bytes().to_lower_hex_string(),
+        script.to_asm_string()
+    )
+}
+
+fn checksig_pubkey(script: &Script) -> Option<Vec<u8>> {
+    let instructions: Vec<_> = script.instructions().collect();
+    if instructions.len() < 3 {
+        return None;
+    }
+    let pb = match instructions.get(2) {
+        Some(Ok(instruction)) => match instruction {
+            Op(OP_PUSHNUM_1) => vec![1],
+            PushBytes(bytes) => bytes.as_bytes().to_vec(),
+            Op(op) => vec![op.to_u8()],
+        },
+        Some(Err(_)) => vec![],
+        None => vec![],
+    };
+    Some(pb)
+}
+
+fn checksig_destination(config: &Config, pb: Vec<u8>) -> String {
+    b58_encode(
+        config
+            .address_version
+            .clone()
+            .into_iter()
+            .chain(pb)
+            .collect::<Vec<_>>()
+            .as_slice(),
+    )
+}
+
+// Returns the number of required signatures and the pubkeys that may carry data.
+// `extended` also accepts the larger forms `extended_multisig_pubkeys` reads.
+fn multisig_pubkeys(script: &Script, extended: bool) -> Option<(u32, Vec<Vec<u8>>)> {
+    let (signatures_required, pbs) = match script.instructions().collect::<Vec<_>>().as_slice() {
+        [Ok(PushBytes(_pk0_pb)), Ok(PushBytes(pk1_pb)), Ok(PushBytes(pk2_pb)), Ok(PushBytes(_pk3_pb)), Ok(Op(OP_CHECKMULTISIG))] => {
+            (1, vec![*pk1_pb, *pk2_pb])
+        }
+        [Ok(Op(OP_PUSHNUM_1)), Ok(PushBytes(pk1_pb)), Ok(PushBytes(pk2_pb)), Ok(Op(OP_PUSHNUM_2)), Ok(Op(OP_CHECKMULTISIG))] => {
+            (1, vec![*pk1_pb, *pk2_pb])
+        }
+        [Ok(Op(OP_PUSHNUM_2)), Ok(PushBytes(pk1_pb)), Ok(PushBytes(pk2_pb)), Ok(Op(OP_PUSHNUM_2)), Ok(Op(OP_CHECKMULTISIG))] => {
+            (2, vec![*pk1_pb, *pk2_pb])
+        }
+        // legacy edge case
+        [Ok(Op(OP_PUSHNUM_3)), Ok(PushBytes(pk1_pb)), Ok(PushBytes(pk2_pb)), Ok(Op(OP_PUSHNUM_2)), Ok(Op(OP_CHECKMULTISIG))] => {
+            (3, vec![*pk1_pb, *pk2_pb])
+        }
+        [Ok(Op(OP_PUSHNUM_1)), Ok(PushBytes(pk1_pb)), Ok(PushBytes(pk2_pb)), Ok(PushBytes(pk3_pb)), Ok(Op(OP_PUSHNUM_3)), Ok(Op(OP_CHECKMULTISIG))] => {
+            (1, vec![*pk1_pb, *pk2_pb, *pk3_pb])
+        }
+        [Ok(PushBytes(_pk0_pb)), Ok(PushBytes(pk1_pb)), Ok(PushBytes(pk2_pb)), Ok(PushBytes(pk3_pb)), Ok(PushBytes(_pk4_pb)), Ok(Op(OP_CHECKMULTISIG))] => {
+            (2, vec![*pk1_pb, *pk2_pb, *pk3_pb])
+        }
+        [Ok(Op(OP_PUSHNUM_2)), Ok(PushBytes(pk1_pb)), Ok(PushBytes(pk2_pb)), Ok(PushBytes(pk3_pb)), Ok(Op(OP_PUSHNUM_3)), Ok(Op(OP_CHECKMULTISIG))] => {
+            (2, vec![*pk1_pb, *pk2_pb, *pk3_pb])
+        }
+        [Ok(Op(OP_PUSHNUM_3)), Ok(PushBytes(pk1_pb)), Ok(PushBytes(pk2_pb)), Ok(PushBytes(pk3_pb)), Ok(Op(OP_PUSHNUM_3)), Ok(Op(OP_CHECKMULTISIG))] => {
+            (3, vec![*pk1_pb, *pk2_pb, *pk3_pb])
+        }
+        _ if extended => return extended_multisig_pubkeys(script),
+        _ => return None,
+    };
+    Some((
+        signatures_required,
+        pbs.into_iter().map(|pb| pb.as_bytes().to_vec()).collect(),
+    ))
+}
+
+// The number OP_PUSHNUM_1 to OP_PUSHNUM_16 push.
+fn pushnum(op: Opcode) -> Option<u8> {
+    let b = op.to_u8();
+    matches!(b, 0x51..=0x60).then(|| b - 0x50)
+}
+
+// A bare `m <pubkey>... n OP_CHECKMULTISIG` with 4 to 15 pubkeys. Like the 3 pubkey
+// form, all but the last pubkey carry data.
+fn extended_multisig_pubkeys(script: &Script) -> Option<(u32, Vec<Vec<u8>>)> {
+    let instructions = script.instructions().collect::<Result<Vec<_>, _>>().ok()?;
+    let [Op(m), pubkeys @ .., Op(n), Op(OP_CHECKMULTISIG)] = instructions.as_slice() else {
+        return None;
+    };
+    let (m, n) = (pushnum(*m)?, pushnum(*n)?);
+    if !(4..=15).contains(&n) || usize::from(n) != pubkeys.len() || m > n {
+        return None;
+    }
+    let pubkeys = pubkeys
+        .iter()
+        .map(|instruction| match instruction {
+            PushBytes(pb) => Some(pb.as_bytes().to_vec()),
+            Op(_) => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some((m.into(), pubkeys))
+}
+
+// Decrypts the data carried by all but the last pubkey.
+fn multisig_payload(arc4: &mut Arc4, chunks: &[Vec<u8>]) -> Option<Vec<u8>> {
+    let mut enc_bytes = Vec::new();
+    for chunk in chunks.iter().take(chunks.len() - 1) {
+        // (No data in last pubkey.)
+        if chunk.len() < 2 {
+            return None;
+        }
+        enc_bytes.extend(chunk[1..chunk.len() - 1].to_vec()); // Skip sign byte and nonce byte.
+    }
+    Some(arc4.decrypt(&enc_bytes))
+}
+
+fn multisig_destination(config: &Config, signatures_required: u32, chunks: Vec<Vec<u8>>) -> String {
+    let mut pub_key_hashes = chunks
+        .into_iter()
+        .map(|chunk| {
+            b58_encode(
+                &config
+                    .multisig_member_address_version
+                    .clone()
+                    .into_iter()
+                    .chain(
+                        ripemd160::Hash::hash(sha256::Hash::hash(&chunk).as_byte_array())
+                            .as_byte_array()
+                            .to_vec(),
+                    )
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect::<Vec<_>>();
+    pub_key_hashes.sort();
+    let pub_key_hashes_n_s = pub_key_hashes.len().to_string();
+    [signatures_required.to_string()]
+        .into_iter()
+        .chain(pub_key_hashes.into_iter().chain([pub_key_hashes_n_s]))
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+// The protocol changes `parse_vout` checks, resolved once for a block height.
+struct OutputRules<'a> {
+    config: &'a Config,
+    segwit: bool,
+    p2sh_addresses: bool,
+    p2sh_dispensers: bool,
+    correct_segwit_txids: bool,
+    taproot_support: bool,
+    future_segwit: bool,
+    p2wsh_data: bool,
+    plaintext_op_return: bool,
+    non_push_op_return: bool,
+    p2pk_destinations: bool,
+    extended_multisig: bool,
+    op_return_anchor: bool,
+    segwit_v0_program_length: bool,
+    empty_op_return: bool,
+    taproot_program_length: bool,
+}
+
+impl<'a> OutputRules<'a> {
+    fn new(config: &'a Config, height: Height) -> Self {
+        OutputRules {
+            config,
+            segwit: config.segwit_supported(height),
+            p2sh_addresses: config.p2sh_address_supported(height),
+            p2sh_dispensers: config.p2sh_dispensers_supported(height),
+            correct_segwit_txids: config.correct_segwit_txids_enabled(height),
+            taproot_support: config.taproot_support_enabled(height),
+            future_segwit: config.future_segwit_enabled(height),
+            p2wsh_data: config.p2wsh_data_enabled(height),
+            plaintext_op_return: config.plaintext_op_return_enabled(height),
+            non_push_op_return: config.non_push_op_return_enabled(height),
+            p2pk_destinations: config.p2pk_destinations_enabled(height),
+            extended_multisig: config.extended_multisig_enabled(height),
+            op_return_anchor: config.op_return_anchor_enabled(height),
+            segwit_v0_program_length: config.segwit_v0_program_length_enabled(height),
+            empty_op_return: config.empty_op_return_enabled(height),
+            taproot_program_length: config.taproot_program_length_enabled(height),
+        }
+    }
+}
+
 fn parse_vout(
     config: &Config,
     key: Vec<u8>,
-    height: u32,
+    height: Height,
+    txid: String,
+    vi: usize,
+    vout: &TxOut,
+) -> Result<(ParseOutput, Option<PotentialDispenser>), Error> {
+    parse_vout_with(
+        &OutputRules::new(config, height),
+        &mut Arc4::new(key),
+        txid,
+        vi,
+        vout,
+    )
+}
+
+fn parse_vout_with(
+    rules: &OutputRules,
+    arc4: &mut Arc4,
+    txid: String,
+    vi: usize,
+    vout: &TxOut,
+) -> Result<(ParseOutput, Option<PotentialDispenser>), Error> {
+    let (output, potential_dispenser) = parse_vout_script(rules, arc4, txid, vi, vout)?;
+    let Some(normalizer) = &rules.config.address_normalizer else {
+        return Ok((output, potential_dispenser));
+    };
+    let output = match output {
+        ParseOutput::Destination(destination) => {
+            ParseOutput::Destination(normalizer.normalize(&destination))
+        }
+        output => output,
+    };
+    let potential_dispenser = potential_dispenser.map(|pd| PotentialDispenser {
+        destination: pd.destination.map(|d| normalizer.normalize(&d)),
+        ..pd
+    });
+    Ok((output, potential_dispenser))
+}
+
+fn parse_vout_script(
+    rules: &OutputRules,
+    arc4: &mut Arc4,
     txid: String,
     vi: usize,
     vout: &TxOut,
 ) -> Result<(ParseOutput, Option<PotentialDispenser>), Error> {
+    let config = rules.config;
     let value = vout.value.to_sat();
     let is_p2sh = matches!(
         vout.script_pubkey
@@ -145,17 +707,60 @@ fn parse_vout(
         [Ok(Op(OP_HASH160)), Ok(PushBytes(_)), Ok(Op(OP_EQUAL))]
     );
     if vout.script_pubkey.is_op_return() {
+        // A zero-value bare OP_RETURN is an anchor for package relay, there to be spent
+        // by a child fee-bumping the transaction. It carries no data.
+        if rules.op_return_anchor
+            && value == 0
+            && vout.script_pubkey.as_bytes() == [OP_RETURN.to_u8()]
+        {
+            return Ok((
+                ParseOutput::Skip,
+                Some(PotentialDispenser {
+                    destination: None,
+                    value: None,
+                }),
+            ));
+        }
+        // An OP_RETURN followed by anything but a single push, such as `OP_RETURN OP_1
+        // OP_2`, is valid but nonstandard and carries no data. Malformed pushes still fail.
+        if rules.non_push_op_return
+            && vout
+                .script_pubkey
+                .instructions()
+                .collect::<Result<Vec<_>, _>>()
+                .is_ok_and(|instructions| {
+                    !matches!(instructions.as_slice(), [Op(OP_RETURN), PushBytes(_)])
+                })
+        {
+            return Ok((
+                ParseOutput::Skip,
+                Some(PotentialDispenser {
+                    destination: None,
+                    value: None,
+                }),
+            ));
+        }
         if let [Ok(Op(OP_RETURN)), Ok(PushBytes(pb))] = vout
             .script_pubkey
             .instructions()
             .collect::<Vec<_>>()
             .as_slice()
         {
-            if config.taproot_support_enabled(height) {
+            // An empty push carries no data. Before the flag it failed as invalid below.
+            if rules.empty_op_return && pb.is_empty() {
+                return Ok((
+                    ParseOutput::Skip,
+                    Some(PotentialDispenser {
+                        destination: None,
+                        value: None,
+                    }),
+                ));
+            }
+            if rules.taproot_support || rules.p2wsh_data {
                 let bytes = pb.as_bytes();
                 if bytes == b"CNTRPRTY" {
                     return Ok((
-                        ParseOutput::Data(bytes.to_vec()),
+                        ParseOutput::Data(bytes.to_vec(), None),
                         Some(PotentialDispenser {
                             destination: None,
                             value: None,
@@ -163,10 +768,22 @@ fn parse_vout(
                     ));
                 }
             }
-            let bytes = arc4_decrypt(&key, pb.as_bytes());
-            if bytes.starts_with(&config.prefix) {
+            if let Some((data, index)) =
+                message_payload(config, DataSource::OpReturn, pb.as_bytes())
+                    .filter(|_| rules.plaintext_op_return)
+            {
+                return Ok((
+                    ParseOutput::Data(data, Some(index)),
+                    Some(PotentialDispenser {
+                        destination: None,
+                        value: None,
+                    }),
+                ));
+            }
+            let bytes = arc4.decrypt(pb.as_bytes());
+            if let Some((data, index)) = message_payload(config, DataSource::OpReturn, &bytes) {
                 return Ok((
-                    ParseOutput::Data(bytes[config.prefix.len()..].to_vec()),
+                    ParseOutput::Data(data, Some(index)),
                     Some(PotentialDispenser {
                         destination: None,
                         value: None,
@@ -180,43 +797,42 @@ fn parse_vout(
         )));
 
     } else if vout.script_pubkey.instructions().last() == Some(Ok(Op(OP_CHECKSIG))) {
-        let instructions: Vec<_> = vout.script_pubkey.instructions().collect();
-        if instructions.len() < 3 {
-            return Err(Error::ParseVout(format!(
-                "Encountered invalid OP_CHECKSIG script | tx: {}, vout: {}",
-                txid, vi
-            )));
-        }
-        let pb = match instructions.get(2) {
-            Some(Ok(instruction)) => match instruction {
-                Op(OP_PUSHNUM_1) => vec![1],
-                PushBytes(bytes) => bytes.as_bytes().to_vec(),
-                Op(op) => vec![op.to_u8()],
+        let pb = match checksig_pubkey(&vout.script_pubkey) {
+            Some(pb) => pb,
+            None => match vout.script_pubkey.p2pk_public_key() {
+                // A bare `<pubkey> OP_CHECKSIG` pays the pubkey's P2PKH address.
+                Some(pubkey) if rules.p2pk_destinations => {
+                    let destination =
+                        checksig_destination(config, pubkey.pubkey_hash().to_byte_array().to_vec());
+                    return Ok((
+                        ParseOutput::Destination(destination.clone()),
+                        Some(PotentialDispenser {
+                            destination: Some(destination),
+                            value: Some(value),
+                        }),
+                    ));
+                }
+                _ => {
+                    return Err(Error::ParseVout(format!(
+                        "Encountered invalid OP_CHECKSIG script | tx: {}, vout: {}{}",
+                        txid,
+                        vi,
+                        script_details(config, &vout.script_pubkey)
+                    )))
+                }
             },
-            Some(Err(_)) => vec![],
-            None => vec![],
         };
-        let bytes = arc4_decrypt(&key, &pb);
-        if bytes.len() >= config.prefix.len() && bytes[1..=config.prefix.len()] == config.prefix {
-            let data_len = bytes[0] as usize;
-            let data = bytes[1..=data_len].to_vec();
+        let bytes = arc4.decrypt(&pb);
+        if let Some((data, index)) = message_payload(config, DataSource::Checksig, &bytes) {
             return Ok((
-                ParseOutput::Data(data[config.prefix.len()..].to_vec()),
+                ParseOutput::Data(data, Some(index)),
                 Some(PotentialDispenser {
                     destination: None,
                     value: Some(value),
                 }),
             ));
         } else {
-            let destination = b58_encode(
-                config
-                    .address_version
-                    .clone()
-                    .into_iter()
-                    .chain(pb)
-                    .collect::<Vec<_>>()
-                    .as_slice(),
-            );
+            let destination = checksig_destination(config, pb);
             return Ok((
                 ParseOutput::Destination(destination.clone()),
                 Some(PotentialDispenser {
@@ -226,126 +842,28 @@ fn parse_vout(
             ));
         }
     } else if vout.script_pubkey.instructions().last() == Some(Ok(Op(OP_CHECKMULTISIG))) {
-        let mut chunks = Vec::new();
-        #[allow(unused_assignments)]
-        let mut signatures_required = 0;
-        match vout
-            .script_pubkey
-            .instructions()
-            .collect::<Vec<_>>()
-            .as_slice()
-        {
-            [Ok(PushBytes(_pk0_pb)), Ok(PushBytes(pk1_pb)), Ok(PushBytes(pk2_pb)), Ok(PushBytes(_pk3_pb)), Ok(Op(OP_CHECKMULTISIG))] =>
-            {
-                signatures_required = 1;
-                for pb in [pk1_pb, pk2_pb] {
-                    chunks.push(pb.as_bytes().to_vec());
-                }
-            }
-            [Ok(Op(OP_PUSHNUM_1)), Ok(PushBytes(pk1_pb)), Ok(PushBytes(pk2_pb)), Ok(Op(OP_PUSHNUM_2)), Ok(Op(OP_CHECKMULTISIG))] =>
-            {
-                signatures_required = 1;
-                for pb in [pk1_pb, pk2_pb] {
-                    chunks.push(pb.as_bytes().to_vec());
-                }
-            }
-            [Ok(Op(OP_PUSHNUM_2)), Ok(PushBytes(pk1_pb)), Ok(PushBytes(pk2_pb)), Ok(Op(OP_PUSHNUM_2)), Ok(Op(OP_CHECKMULTISIG))] =>
-            {
-                signatures_required = 2;
-                for pb in [pk1_pb, pk2_pb] {
-                    chunks.push(pb.as_bytes().to_vec());
-                }
-            }
-            // legacy edge case
-            [Ok(Op(OP_PUSHNUM_3)), Ok(PushBytes(pk1_pb)), Ok(PushBytes(pk2_pb)), Ok(Op(OP_PUSHNUM_2)), Ok(Op(OP_CHECKMULTISIG))] =>
-            {
-                signatures_required = 3;
-                for pb in [pk1_pb, pk2_pb] {
-                    chunks.push(pb.as_bytes().to_vec());
-                }
-            }
-            [Ok(Op(OP_PUSHNUM_1)), Ok(PushBytes(pk1_pb)), Ok(PushBytes(pk2_pb)), Ok(PushBytes(pk3_pb)), Ok(Op(OP_PUSHNUM_3)), Ok(Op(OP_CHECKMULTISIG))] =>
-            {
-                signatures_required = 1;
-                for pb in [pk1_pb, pk2_pb, pk3_pb] {
-                    chunks.push(pb.as_bytes().to_vec());
-                }
-            }
-            [Ok(PushBytes(_pk0_pb)), Ok(PushBytes(pk1_pb)), Ok(PushBytes(pk2_pb)), Ok(PushBytes(pk3_pb)), Ok(PushBytes(_pk4_pb)), Ok(Op(OP_CHECKMULTISIG))] =>
-            {
-                signatures_required = 2;
-                for pb in [pk1_pb, pk2_pb, pk3_pb] {
-                    chunks.push(pb.as_bytes().to_vec());
-                }
-            }
-            [Ok(Op(OP_PUSHNUM_2)), Ok(PushBytes(pk1_pb)), Ok(PushBytes(pk2_pb)), Ok(PushBytes(pk3_pb)), Ok(Op(OP_PUSHNUM_3)), Ok(Op(OP_CHECKMULTISIG))] =>
-            {
-                signatures_required = 2;
-                for pb in [pk1_pb, pk2_pb, pk3_pb] {
-                    chunks.push(pb.as_bytes().to_vec());
-                }
-            }
-            [Ok(Op(OP_PUSHNUM_3)), Ok(PushBytes(pk1_pb)), Ok(PushBytes(pk2_pb)), Ok(PushBytes(pk3_pb)), Ok(Op(OP_PUSHNUM_3)), Ok(Op(OP_CHECKMULTISIG))] =>
-            {
-                signatures_required = 3;
-                for pb in [pk1_pb, pk2_pb, pk3_pb] {
-                    chunks.push(pb.as_bytes().to_vec());
-                }
-            }
-            _ => {
-                return Err(Error::ParseVout(format!(
-                    "Encountered invalid OP_MULTISIG script | tx: {}, vout: {}",
-                    txid, vi
-                )));
-            }
-        }
-        let mut enc_bytes = Vec::new();
-        for chunk in chunks.iter().take(chunks.len() - 1) {
-            // (No data in last pubkey.)
-            if chunk.len() < 2 {
-                return Err(Error::ParseVout(format!(
-                    "Encountered invalid OP_MULTISIG script | tx: {}, vout: {}",
-                    txid, vi
-                )));
-            }
-            enc_bytes.extend(chunk[1..chunk.len() - 1].to_vec()); // Skip sign byte and nonce byte.
-        }
-        let bytes = arc4_decrypt(&key, &enc_bytes);
-        if bytes.len() >= config.prefix.len() && bytes[1..=config.prefix.len()] == config.prefix {
-            let chunk_len = min(bytes[0] as usize, bytes.len() - 1);
-            let chunk = bytes[1..=chunk_len].to_vec();
+        let invalid_multisig = || {
+            Error::ParseVout(format!(
+                "Encountered invalid OP_MULTISIG script | tx: {}, vout: {}{}",
+                txid,
+                vi,
+                script_details(config, &vout.script_pubkey)
+            ))
+        };
+        let (signatures_required, chunks) =
+            multisig_pubkeys(&vout.script_pubkey, rules.extended_multisig)
+                .ok_or_else(invalid_multisig)?;
+        let bytes = multisig_payload(arc4, &chunks).ok_or_else(invalid_multisig)?;
+        if let Some((data, index)) = message_payload(config, DataSource::Multisig, &bytes) {
             return Ok((
-                ParseOutput::Data(chunk[config.prefix.len()..].to_vec()),
+                ParseOutput::Data(data, Some(index)),
                 Some(PotentialDispenser {
                     destination: None,
                     value: Some(value),
                 }),
             ));
         } else {
-            let mut pub_key_hashes = chunks
-                .into_iter()
-                .map(|chunk| {
-                    b58_encode(
-                        &config
-                            .address_version
-                            .clone()
-                            .into_iter()
-                            .chain(
-                                ripemd160::Hash::hash(sha256::Hash::hash(&chunk).as_byte_array())
-                                    .as_byte_array()
-                                    .to_vec(),
-                            )
-                            .collect::<Vec<_>>(),
-                    )
-                })
-                .collect::<Vec<_>>();
-            pub_key_hashes.sort();
-            let pub_key_hashes_n_s = pub_key_hashes.len().to_string();
-            let destination = [signatures_required.to_string()]
-                .into_iter()
-                .chain(pub_key_hashes.into_iter().chain([pub_key_hashes_n_s]))
-                .collect::<Vec<_>>()
-                .join("_");
+            let destination = multisig_destination(config, signatures_required, chunks);
             return Ok((
                 ParseOutput::Destination(destination.clone()),
                 Some(PotentialDispenser {
@@ -354,7 +872,7 @@ fn parse_vout(
                 }),
             ));
         }
-    } else if is_p2sh && config.p2sh_address_supported(height) {
+    } else if is_p2sh && rules.p2sh_addresses {
         if let [Ok(Op(OP_HASH160)), Ok(PushBytes(pb)), Ok(Op(OP_EQUAL))] = vout
             .script_pubkey
             .instructions()
@@ -373,7 +891,7 @@ fn parse_vout(
                 destination: None,
                 value: None,
             });
-            if config.p2sh_dispensers_supported(height) {
+            if rules.p2sh_dispensers {
                 potential_dispenser = Some(PotentialDispenser {
                     destination: Some(destination.clone()),
                     value: Some(value),
@@ -382,45 +900,397 @@ fn parse_vout(
             return Ok((ParseOutput::Destination(destination), potential_dispenser));
         }
         return Err(Error::ParseVout(format!(
-            "Encountered invalid P2SH script | tx: {}, vout: {}",
-            txid, vi
+            "Encountered invalid P2SH script | tx: {}, vout: {}{}",
+            txid,
+            vi,
+            script_details(config, &vout.script_pubkey)
         )));
-    } else if (config.segwit_supported(height) && is_valid_segwit_script_legacy(&vout.script_pubkey)) || 
-                (config.taproot_support_enabled(height) && is_valid_segwit_script(&vout.script_pubkey)) || 
-                (config.taproot_support_enabled(height) && vout.script_pubkey.is_p2tr()) {
-        
-         let destination = if config.taproot_support_enabled(height) {
-            script_to_address(
-                vout.script_pubkey.as_bytes().to_vec(),
-                config.network.to_string().as_str(),
-            )
-        } else {
-            script_to_address_legacy(
-                vout.script_pubkey.as_bytes().to_vec(),
-                config.network.to_string().as_str(),
-            )
+    } else if (rules.segwit && is_valid_segwit_script_legacy(&vout.script_pubkey)) || 
+                (rules.taproot_support && is_valid_segwit_script(&vout.script_pubkey)) || 
+                (rules.taproot_support && vout.script_pubkey.is_p2tr()) {
+        // A v1 program is only a taproot output as a 32 byte x-only key. Other lengths
+        // still encode to a bech32m address, but not one a P2TR spend can come from.
+        if rules.taproot_program_length
+            && vout.script_pubkey.witness_version() == Some(WitnessVersion::V1)
+            && !vout.script_pubkey.is_p2tr()
+        {
+            return Err(Error::ParseVout(format!(
+                "Invalid taproot program length | tx: {}, vout: {}{}",
+                txid,
+                vi,
+                script_details(config, &vout.script_pubkey)
+            )));
+        }
+        // Likewise a v0 program is only P2WPKH or P2WSH at 20 or 32 bytes. Before this
+        // check, the legacy encoding truncated other lengths to 20 bytes.
+        if rules.segwit_v0_program_length
+            && vout.script_pubkey.witness_version() == Some(WitnessVersion::V0)
+            && !matches!(vout.script_pubkey.len(), 22 | 34)
+        {
+            return Err(Error::ParseVout(format!(
+                "Non-standard segwit v0 program length | tx: {}, vout: {}{}",
+                txid,
+                vi,
+                script_details(config, &vout.script_pubkey)
+            )));
+        }
+         let destination = if rules.taproot_support {
+            script_to_address(
+                vout.script_pubkey.as_bytes().to_vec(),
+                config.network.to_string().as_str(),
+            )
+        } else {
+            script_to_address_legacy(
+                vout.script_pubkey.as_bytes().to_vec(),
+                config.network.to_string().as_str(),
+            )
         }
         .map_err(|e| Error::ParseVout(format!("Segwit script to address failed: {}", e)))?;
         let mut potential_dispenser = Some(PotentialDispenser {
             destination: None,
             value: None,
         });
-        if config.correct_segwit_txids_enabled(height) {
+        if rules.correct_segwit_txids {
             potential_dispenser = Some(PotentialDispenser {
                 destination: Some(destination.clone()),
                 value: Some(value),
             });
         }
         return Ok((ParseOutput::Destination(destination), potential_dispenser));
+    } else if rules.future_segwit && is_future_witness_program(&vout.script_pubkey) {
+        let destination = script_to_address(
+            vout.script_pubkey.as_bytes().to_vec(),
+            config.network.to_string().as_str(),
+        )
+        .map_err(|e| Error::ParseVout(format!("Witness program to address failed: {}", e)))?;
+        Ok((
+            ParseOutput::Destination(destination.clone()),
+            Some(PotentialDispenser {
+                destination: Some(destination),
+                value: Some(value),
+            }),
+        ))
     } else {
         return Err(Error::ParseVout(format!(
-            "Unrecognized output type | tx: {}, vout: {}",
-            txid, vi
+            "Unrecognized output type | tx: {}, vout: {}{}",
+            txid,
+            vi,
+            script_details(config, &vout.script_pubkey)
         )));
     }
 }
 
-fn extract_data_from_witness(script: &Script) -> Result<Vec<u8>, Error> {
+/// Classifies each output as `parse_vout` would, for tools that look at outputs outside
+/// their transactions, such as a UTXO snapshot. As in `debug_classify`, there is no
+/// spending transaction to derive the ARC4 key from, so encoded payloads are decrypted
+/// with an all-zero key. Errors number the outputs by their position in `outputs`.
+pub fn classify_outputs(
+    config: &Config,
+    height: Height,
+    outputs: &[TxOut],
+) -> Vec<Result<ParseOutput, Error>> {
+    let rules = OutputRules::new(config, height);
+    let mut arc4 = Arc4::new(vec![0; 32]);
+    outputs
+        .iter()
+        .enumerate()
+        .map(|(vi, vout)| {
+            parse_vout_with(&rules, &mut arc4, String::new(), vi, vout).map(|(output, _)| output)
+        })
+        .collect()
+}
+
+#[cfg(feature = "debug-tools")]
+#[derive(Debug)]
+struct OutputCandidate {
+    output: ParseOutput,
+    accepted: bool,
+    reason: String,
+}
+
+#[cfg(feature = "debug-tools")]
+#[derive(Debug)]
+struct VerboseClassification {
+    chosen: ParseOutput,
+    alternatives: Vec<OutputCandidate>,
+}
+
+// Research helper: reports every interpretation `parse_vout` weighed for bare checksig
+// and multisig outputs, where the prefix check decides between data and destination.
+// Not used for consensus.
+#[cfg(feature = "debug-tools")]
+fn classify_output_verbose(
+    config: &Config,
+    key: Vec<u8>,
+    height: Height,
+    txid: String,
+    vi: usize,
+    vout: &TxOut,
+) -> Result<VerboseClassification, Error> {
+    let (chosen, _) = parse_vout(config, key.clone(), height, txid, vi, vout)?;
+    let script = &vout.script_pubkey;
+    let interpretations = if script.is_op_return() {
+        None
+    } else if script.instructions().last() == Some(Ok(Op(OP_CHECKSIG))) {
+        checksig_pubkey(script)
+            .map(|pb| (arc4_decrypt(&key, &pb), checksig_destination(config, pb)))
+    } else if script.instructions().last() == Some(Ok(Op(OP_CHECKMULTISIG))) {
+        multisig_pubkeys(script, config.extended_multisig_enabled(height)).and_then(
+            |(signatures_required, chunks)| {
+                multisig_payload(&mut Arc4::new(key.clone()), &chunks).map(|payload| {
+                    (
+                        payload,
+                        multisig_destination(config, signatures_required, chunks),
+                    )
+                })
+            },
+        )
+    } else {
+        None
+    };
+
+    let alternatives = match interpretations {
+        Some((payload, destination)) => {
+            let is_data = !chosen.is_destination();
+            let (data, prefix) = match &chosen {
+                ParseOutput::Data(data, prefix) => (data.clone(), *prefix),
+                _ => (payload, None),
+            };
+            vec![
+                OutputCandidate {
+                    output: ParseOutput::Data(data, prefix),
+                    accepted: is_data,
+                    reason: if is_data {
+                        "decrypted pubkey data starts with the prefix".into()
+                    } else {
+                        "decrypted pubkey data does not start with the prefix".into()
+                    },
+                },
+                OutputCandidate {
+                    output: ParseOutput::Destination(destination),
+                    accepted: !is_data,
+                    reason: if is_data {
+                        "pubkeys carry prefixed data".into()
+                    } else {
+                        "pubkeys do not carry prefixed data".into()
+                    },
+                },
+            ]
+        }
+        None => vec![OutputCandidate {
+            output: chosen.clone(),
+            accepted: true,
+            reason: "script has a single interpretation".into(),
+        }],
+    };
+
+    Ok(VerboseClassification {
+        chosen,
+        alternatives,
+    })
+}
+
+/// Multi-line report of how `parse_vout` classifies a raw scriptPubKey at `height`.
+/// There is no spending transaction to derive the ARC4 key from, so encoded payloads are
+/// decrypted with an all-zero key.
+#[cfg(feature = "debug-tools")]
+pub fn debug_classify(hex: &str, config: &Config, height: Height) -> String {
+    let script_pubkey = match hex::decode(hex) {
+        Ok(bytes) => bitcoin::ScriptBuf::from_bytes(bytes),
+        Err(e) => return format!("invalid script hex: {}", e),
+    };
+    let vout = TxOut {
+        value: bitcoin::Amount::ZERO,
+        script_pubkey,
+    };
+    let mut report = vec![
+        format!("script: {}", hex),
+        format!("asm: {}", vout.script_pubkey.to_asm_string()),
+        format!("height: {}", height),
+    ];
+    match classify_output_verbose(config, vec![0; 32], height, "debug".into(), 0, &vout) {
+        Ok(classification) => {
+            report.push(format!("result: {}", classification.chosen));
+            report.push("path:".into());
+            for candidate in classification.alternatives {
+                report.push(format!(
+                    "  [{}] {}: {}",
+                    if candidate.accepted {
+                        "accepted"
+                    } else {
+                        "rejected"
+                    },
+                    candidate.output,
+                    candidate.reason
+                ));
+            }
+        }
+        Err(e) => report.push(format!("error: {}", e)),
+    }
+    report.join("\n")
+}
+
+type ScriptInstruction<'a> = Result<bitcoin::script::Instruction<'a>, bitcoin::script::Error>;
+
+fn is_op(instruction: &ScriptInstruction, opcode: Opcode) -> bool {
+    matches!(instruction, Ok(Op(op)) if *op == opcode)
+}
+
+// OP_FALSE is decoded as an empty push.
+fn is_false(instruction: &ScriptInstruction) -> bool {
+    matches!(instruction, Ok(PushBytes(pb)) if pb.is_empty()) || is_op(instruction, OP_FALSE)
+}
+
+// Position of the OP_ENDIF closing an OP_IF whose body starts at `start`, skipping over
+// nested conditionals.
+fn matching_endif(instructions: &[ScriptInstruction], start: usize) -> Option<usize> {
+    let mut depth = 1;
+    for (i, instruction) in instructions.iter().enumerate().skip(start) {
+        if is_op(instruction, OP_IF) || is_op(instruction, OP_NOTIF) {
+            depth += 1;
+        } else if is_op(instruction, OP_ENDIF) {
+            depth -= 1;
+            if depth == 0 {
+                return Some(i);
+            }
+        }
+    }
+    None
+}
+
+fn is_checksig(instruction: &ScriptInstruction) -> bool {
+    is_op(instruction, OP_CHECKSIG)
+        || is_op(instruction, OP_CHECKSIGVERIFY)
+        || is_op(instruction, OP_CHECKSIGADD)
+}
+
+// How envelopes are read at a given height.
+#[derive(Debug, Clone, Copy)]
+struct EnvelopeRules {
+    cbor_body: bool,
+    matched_endif: bool,
+    body_separator: bool,
+    split_batches: bool,
+    type_id_range: bool,
+}
+
+impl EnvelopeRules {
+    fn new(config: &Config, height: Height) -> Self {
+        EnvelopeRules {
+            cbor_body: config.cbor_inscription_body_enabled(height),
+            matched_endif: config.matched_envelope_endif_enabled(height),
+            body_separator: config.envelope_body_separator_enabled(height),
+            split_batches: config.batch_inscriptions_enabled(height),
+            type_id_range: config.message_type_id_range_enabled(height),
+        }
+    }
+
+    // Every rule in effect, for decoding outside of consensus.
+    fn latest() -> Self {
+        EnvelopeRules {
+            cbor_body: true,
+            matched_endif: true,
+            body_separator: true,
+            split_batches: true,
+            type_id_range: true,
+        }
+    }
+}
+
+// Splits an envelope script into the instructions between each OP_FALSE OP_IF and its
+// matching OP_ENDIF. Batch inscriptions carry several envelopes; whatever surrounds
+// them, such as the `<pubkey> OP_CHECKSIG`, is skipped.
+fn envelope_spans(instructions: &[ScriptInstruction], rules: EnvelopeRules) -> Vec<Range<usize>> {
+    if !rules.matched_endif {
+        return fixed_offset_spans(instructions);
+    }
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while i + 1 < instructions.len() {
+        if is_false(&instructions[i]) && is_op(&instructions[i + 1], OP_IF) {
+            let Some(end) = matching_endif(instructions, i + 2) else {
+                break;
+            };
+            spans.push(i + 2..end);
+            i = end + 1;
+        } else {
+            i += 1;
+        }
+    }
+    spans
+}
+
+// Before envelopes were matched by opcode, the last three instructions were taken to be
+// `OP_ENDIF <pubkey> OP_CHECKSIG` and the first envelope ran up to the first OP_ENDIF
+// ahead of them.
+fn fixed_offset_spans(instructions: &[ScriptInstruction]) -> Vec<Range<usize>> {
+    let end = instructions.len() - 3;
+    let first_end = instructions[2..end]
+        .iter()
+        .position(|instruction| is_op(instruction, OP_ENDIF))
+        .map_or(end, |i| i + 2);
+    let mut spans = Vec::new();
+    spans.push(2..first_end);
+    let mut i = first_end + 1;
+    while i + 2 <= end {
+        if is_false(&instructions[i]) && is_op(&instructions[i + 1], OP_IF) {
+            let Some(len) = instructions[i + 2..=end]
+                .iter()
+                .position(|instruction| is_op(instruction, OP_ENDIF))
+            else {
+                break;
+            };
+            spans.push(i + 2..i + 2 + len);
+            i += len + 3;
+        } else {
+            i += 1;
+        }
+    }
+    spans
+}
+
+fn envelope_bodies<'a, 'b>(
+    instructions: &'b [ScriptInstruction<'a>],
+    rules: EnvelopeRules,
+) -> Vec<&'b [ScriptInstruction<'a>]> {
+    envelope_spans(instructions, rules)
+        .into_iter()
+        .map(|span| &instructions[span])
+        .collect()
+}
+
+// Only the first envelope is data for `parse_transaction`; later ones in a batch are
+// ignored. Before batches were split, everything from the first envelope to the end of
+// the last one was read as a single body.
+fn extract_data_from_witness(script: &Script, rules: EnvelopeRules) -> Result<Vec<u8>, Error> {
+    let instructions = envelope_instructions(script, rules)?;
+    let spans = envelope_spans(&instructions, rules);
+    let span = if rules.split_batches {
+        spans[0].clone()
+    } else if rules.matched_endif {
+        2..spans[spans.len() - 1].end
+    } else {
+        2..instructions.len() - 3
+    };
+    extract_data_from_envelope(&instructions[span], rules)
+}
+
+/// Every inscription in a batch reveal, in script order. Envelopes that fail to decode
+/// are skipped. Not used for consensus.
+pub fn extract_all_inscriptions(script: &Script) -> Vec<Vec<u8>> {
+    match envelope_instructions(script, EnvelopeRules::latest()) {
+        Ok(instructions) => envelope_bodies(&instructions, EnvelopeRules::latest())
+            .into_iter()
+            .filter_map(|body| extract_data_from_envelope(body, EnvelopeRules::latest()).ok())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn envelope_instructions(
+    script: &Script,
+    rules: EnvelopeRules,
+) -> Result<Vec<ScriptInstruction<'_>>, Error> {
     let instructions: Vec<_> = script.instructions().collect();
     
     // Check if we have enough instructions for a valid envelope script
@@ -428,26 +1298,108 @@ fn extract_data_from_witness(script: &Script) -> Result<Vec<u8>, Error> {
         return Err(Error::ParseVout("Invalid witness script: too few instructions".to_string()));
     }
     
-    // Verify it's an envelope script with empty push bytes as equivalent to OP_FALSE
-    let is_envelope = match (&instructions[0], &instructions[1], instructions.last()) {
-        (Ok(PushBytes(pb)), Ok(Op(op2)), Some(Ok(Op(op3)))) if pb.is_empty() => {
-            format!("{:?}", op2).contains("OP_IF") && format!("{:?}", op3).contains("OP_CHECKSIG")
-        },
-        (Ok(Op(op1)), Ok(Op(op2)), Some(Ok(Op(op3)))) => {
-            (format!("{:?}", op1).contains("OP_FALSE") || format!("{:?}", op1).contains("OP_0")) && 
-            format!("{:?}", op2).contains("OP_IF") && 
-            format!("{:?}", op3).contains("OP_CHECKSIG")
-        },
-        _ => false
-    };
-    
+    // The script opens with an OP_FALSE OP_IF envelope, and its matching OP_ENDIF is
+    // followed by a signature check. Any opcode of the checksig family counts. Before
+    // envelopes were matched by opcode, the signature check had to come last and the
+    // OP_IF was matched by name, which OP_IFDUP also passes.
+    let is_envelope = is_false(&instructions[0])
+        && if rules.matched_endif {
+            is_op(&instructions[1], OP_IF)
+                && matching_endif(&instructions, 2)
+                    .is_some_and(|end| instructions[end + 1..].iter().any(is_checksig))
+        } else {
+            matches!(&instructions[1], Ok(Op(op)) if format!("{:?}", op).contains("OP_IF"))
+                && instructions.last().is_some_and(is_checksig)
+        };
+
     if !is_envelope {
         return Err(Error::ParseVout("Not an envelope script".to_string()));
     }
-    
+    Ok(instructions)
+}
+
+// Metadata larger than this is decoded straight from its pushes rather than from a
+// joined copy of them.
+const STREAMED_METADATA_THRESHOLD: usize = 16 * 1024;
+
+// Reads a run of pushes as one stream, without joining them.
+struct ChunkReader<'a> {
+    chunks: std::slice::Iter<'a, &'a [u8]>,
+    current: &'a [u8],
+}
+
+impl<'a> ChunkReader<'a> {
+    fn new(chunks: &'a [&'a [u8]]) -> Self {
+        ChunkReader {
+            chunks: chunks.iter(),
+            current: &[],
+        }
+    }
+}
+
+impl std::io::Read for ChunkReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.current.is_empty() {
+            match self.chunks.next() {
+                Some(chunk) => self.current = chunk,
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.current.len());
+        buf[..n].copy_from_slice(&self.current[..n]);
+        self.current = &self.current[n..];
+        Ok(n)
+    }
+}
+
+// Decodes ord metadata split over `chunks`. Truncated metadata fails with an EOF error
+// and trailing bytes after the document fail as well, however it is decoded.
+fn decode_metadata(chunks: &[&[u8]]) -> Result<Value, serde_cbor::Error> {
+    let len: usize = chunks.iter().map(|chunk| chunk.len()).sum();
+    if len > STREAMED_METADATA_THRESHOLD {
+        serde_cbor::from_reader(ChunkReader::new(chunks))
+    } else {
+        serde_cbor::from_slice(&chunks.concat())
+    }
+}
+
+// The type byte for a CBOR message type id. With `type_id_range`, an id that doesn't fit
+// is rejected; before it, the id was truncated to its low byte.
+fn type_id_byte(id: i128, rules: EnvelopeRules) -> Option<u8> {
+    if rules.type_id_range {
+        u8::try_from(id).ok()
+    } else {
+        Some(id as u8)
+    }
+}
+
+// A CBOR array led by the message type id, repacked as the type id byte followed by the
+// CBOR of the remaining fields.
+fn cbor_message(bytes: &[u8], rules: EnvelopeRules) -> Option<Vec<u8>> {
+    match serde_cbor::from_slice::<Value>(bytes).ok()? {
+        Value::Array(mut arr) if !arr.is_empty() => {
+            let type_id = match arr.remove(0) {
+                Value::Integer(id) => type_id_byte(id, rules)?,
+                _ => return None,
+            };
+            let mut message = vec![type_id];
+            message.extend(serde_cbor::to_vec(&Value::Array(arr)).ok()?);
+            Some(message)
+        }
+        _ => None,
+    }
+}
+
+// Decodes the instructions between an envelope's OP_IF and OP_ENDIF. With `cbor_body`,
+// an ord inscription without metadata whose content type is `application/cbor` carries
+// the message in its body instead; a body that doesn't decode is taken as raw bytes.
+fn extract_data_from_envelope(
+    body: &[ScriptInstruction],
+    rules: EnvelopeRules,
+) -> Result<Vec<u8>, Error> {
     // Check if this is an "ord" inscription
-    let is_ord = instructions.len() >= 7 && 
-        match (&instructions.get(2), &instructions.get(3)) {
+    let is_ord = body.len() >= 2 && 
+        match (&body.first(), &body.get(1)) {
             (Some(Ok(PushBytes(pb1))), Some(Ok(PushBytes(pb2)))) => {
                 pb1.as_bytes() == b"ord" && 
                 (pb2.as_bytes().len() == 1 && pb2.as_bytes()[0] == 7) // 7 for metaprotocol
@@ -457,7 +1409,7 @@ fn extract_data_from_witness(script: &Script) -> Result<Vec<u8>, Error> {
 
     if is_ord {
         // Extract mime_type from the script (index 4)
-        let mime_type = match &instructions.get(6) {
+        let mime_type = match &body.get(4) {
             Some(Ok(PushBytes(pb))) => {
                 match std::str::from_utf8(pb.as_bytes()) {
                     Ok(mime) => mime.to_string(),
@@ -471,12 +1423,12 @@ fn extract_data_from_witness(script: &Script) -> Result<Vec<u8>, Error> {
         let mut metadata_chunks = Vec::new();
         let mut description_chunks = Vec::new();
         
-        let mut i = 7; // Skip protocol prefix elements
+        let mut i = 5; // Skip protocol prefix elements
         let mut current_section = "none";
         
         // Process all instructions to collect metadata and description
-        while i < instructions.len() - 3 { // Skip last 3 instructions: op_endif and checksig
-            match &instructions[i] {
+        while i < body.len() {
+            match &body[i] {
                 Ok(PushBytes(marker)) => {
                     let marker_bytes = marker.as_bytes();
                     if marker_bytes.len() == 1 && marker_bytes[0] == 5 {
@@ -502,9 +1454,9 @@ fn extract_data_from_witness(script: &Script) -> Result<Vec<u8>, Error> {
 
             // Collect the chunk if we're in a data section
             if current_section != "none" {
-                if let Ok(PushBytes(data)) = &instructions[i] {
+                if let Ok(PushBytes(data)) = &body[i] {
                     if current_section == "metadata" {
-                        metadata_chunks.push(data.as_bytes().to_vec());
+                        metadata_chunks.push(data.as_bytes());
                     } else if current_section == "description" {
                         description_chunks.push(data.as_bytes().to_vec());
                     }
@@ -514,12 +1466,6 @@ fn extract_data_from_witness(script: &Script) -> Result<Vec<u8>, Error> {
             i += 1;
         }
         
-        // Combine all metadata chunks
-        let mut combined_metadata = Vec::new();
-        for chunk in metadata_chunks {
-            combined_metadata.extend_from_slice(&chunk);
-        }
-        
         // Combine all description chunks
         let mut combined_description = Vec::new();
         for chunk in &description_chunks {
@@ -530,9 +1476,9 @@ fn extract_data_from_witness(script: &Script) -> Result<Vec<u8>, Error> {
         let description_value = Value::Bytes(combined_description);
         
         // If we have metadata, use it directly
-        if !combined_metadata.is_empty() {
+        if metadata_chunks.iter().any(|chunk| !chunk.is_empty()) {
             // First try to decode existing CBOR data
-            match serde_cbor::from_slice::<Value>(&combined_metadata) {
+            match decode_metadata(&metadata_chunks) {
                 Ok(value) => {
                     // Extract message_type_id and create a modified value in one step
                     let (message_type_id, mut value_without_type_id) = match value {
@@ -546,9 +1492,11 @@ fn extract_data_from_witness(script: &Script) -> Result<Vec<u8>, Error> {
                         _ => return Err(Error::ParseVout("Expected CBOR array, found different type".to_string())),
                     };
                     
-                    // Ensure message_type_id is an integer
+                    // Ensure message_type_id is an integer that fits the type byte
                     let type_id = match message_type_id {
-                        Value::Integer(id) => id as u8,
+                        Value::Integer(id) => type_id_byte(id, rules).ok_or_else(|| {
+                            Error::ParseVout("message_type_id out of range".to_string())
+                        })?,
                         _ => return Err(Error::ParseVout("message_type_id must be an integer".to_string())),
                     };
                     
@@ -579,15 +1527,30 @@ fn extract_data_from_witness(script: &Script) -> Result<Vec<u8>, Error> {
                    Err(Error::ParseVout(format!("CBOR decode error: {}", e)))
                 }
             }
+        } else if rules.cbor_body
+            && !description_chunks.is_empty()
+            && mime_type.split(';').next().map(str::trim) == Some("application/cbor")
+        {
+            let body = description_chunks.concat();
+            Ok(cbor_message(&body, rules).unwrap_or(body))
         } else {
             // Neither metadata nor description found
             Err(Error::ParseVout("No data found in the ord inscription".to_string()))
         }
     } else {
-        // Generic inscription - collect all data between OP_IF and OP_ENDIF
+        // Generic inscription - collect the data between OP_IF and OP_ENDIF. When the
+        // envelope uses tagged fields, only the pushes after the first OP_0 body marker
+        // are data; before `body_separator`, every push was.
+        let body_start = if rules.body_separator {
+            body.iter()
+                .position(|instruction| matches!(instruction, Ok(PushBytes(pb)) if pb.is_empty()))
+                .map_or(0, |i| i + 1)
+        } else {
+            0
+        };
         let mut result_data = Vec::new();
-        for i in 2..instructions.len() - 3 {
-            if let Ok(PushBytes(bytes)) = &instructions[i] {
+        for instruction in &body[body_start..] {
+            if let Ok(PushBytes(bytes)) = instruction {
                 result_data.extend_from_slice(bytes.as_bytes());
             }
         }
@@ -595,13 +1558,270 @@ fn extract_data_from_witness(script: &Script) -> Result<Vec<u8>, Error> {
     }
 }
 
+// The witness script is the last witness item of a P2WSH spend. Anything that isn't an
+// envelope carrying data is left to the regular output parsing.
+fn extract_data_from_p2wsh_witness(witness: &[String], rules: EnvelopeRules) -> Option<Vec<u8>> {
+    if witness.len() < 2 {
+        return None;
+    }
+    let bytes = hex::decode(witness.last()?).ok()?;
+    extract_data_from_witness(Script::from_bytes(&bytes), rules)
+        .ok()
+        .filter(|data| !data.is_empty())
+}
+
+// Before `fix_is_segwit` activation an input counts as segwit when the transaction it
+// spends carries witness data (txid != wtxid), a property of that whole transaction, so
+// every output spent from it gets the same answer. From activation on, only the spent
+// output matters: it is segwit when its script is a witness program.
+fn vin_output(
+    config: &Config,
+    height: Height,
+    input: &TxIn,
+    prev_tx: &bitcoin::Transaction,
+    vout: usize,
+) -> Option<VinOutput> {
+    let output = prev_tx.output.get(vout)?;
+    let is_segwit = if config.fix_is_segwit_enabled(height) {
+        output.script_pubkey.is_witness_program()
+    } else {
+        prev_tx.compute_txid().to_raw_hash() != prev_tx.compute_wtxid().to_raw_hash()
+    };
+    Some(VinOutput {
+        value: output.value.to_sat(),
+        script_pub_key: output.script_pubkey.to_bytes(),
+        is_segwit,
+        redeem_script_type: if output.script_pubkey.is_p2sh() {
+            redeem_script_type(input)
+        } else {
+            None
+        },
+    })
+}
+
+// A P2SH spend reveals its redeem script as the last push of the scriptSig, whether or
+// not it wraps a witness program: P2SH-P2WPKH and P2SH-P2WSH push the v0 program there
+// and leave the signatures (and witness script) in the witness.
+fn redeem_script_type(input: &TxIn) -> Option<ScriptType> {
+    match input.script_sig.instructions().last()? {
+        Ok(PushBytes(pb)) if !pb.is_empty() => {
+            Some(ScriptType::from_script(Script::from_bytes(pb.as_bytes())))
+        }
+        _ => None,
+    }
+}
+
+// The commitment hash of a BIP141 witness commitment output: OP_RETURN, a 36 byte push
+// of the `aa21a9ed` header and the hash, optionally followed by more bytes.
+fn witness_commitment(script: &Script) -> Option<&[u8]> {
+    let bytes = script.as_bytes();
+    (bytes.len() >= 38 && bytes[..6] == [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed]).then(|| &bytes[6..38])
+}
+
+// A coinbase never carries Counterparty data, so every one of its OP_RETURN outputs is
+// skipped rather than decoded: not only the BIP141 witness commitment but merge mining
+// and pool tags too, which would otherwise fail the whole coinbase as undecodable.
+fn is_skipped_coinbase_op_return(tx: &bitcoin::Transaction, vout: &TxOut) -> bool {
+    tx.is_coinbase() && vout.script_pubkey.is_op_return()
+}
+
+// The ARC4 key is the first input's previous txid in display (byte reversed) order, or
+// empty without inputs. An all-zeros txid, as in a coinbase's null outpoint, gives the
+// all-zeros key: RC4 accepts it and decrypts deterministically under it, but every
+// such transaction shares the same keystream.
+fn arc4_key(tx: &bitcoin::Transaction) -> Vec<u8> {
+    tx.input
+        .first()
+        .map(|input| {
+            let mut key = input.previous_output.txid.to_byte_array().to_vec();
+            key.reverse();
+            key
+        })
+        .unwrap_or_default()
+}
+
+// One previous transaction per input, in input order, whatever the fetch returned. Without
+// a reachable node the inputs are just left without info, but a fetch cut short by the
+// timeout or a shutdown, an incomplete answer, or one of the wrong length is reported.
+fn prev_txs_for_inputs(
+    fetched: Result<Vec<Option<bitcoin::Transaction>>, BatchRpcError>,
+    inputs: usize,
+    warnings: &mut Vec<String>,
+) -> Vec<Option<bitcoin::Transaction>> {
+    let mut prev_txs = match fetched {
+        Ok(txs) => {
+            if txs.len() != inputs {
+                warnings.push(format!(
+                    "Fetched {} previous transactions for {} inputs",
+                    txs.len(),
+                    inputs
+                ));
+            }
+            txs
+        }
+        Err(
+            e @ (BatchRpcError::Timeout(_)
+            | BatchRpcError::Stopped
+            | BatchRpcError::InvalidResponse(_)),
+        ) => {
+            warnings.push(format!("Failed to fetch previous transactions: {:?}", e));
+            vec![]
+        }
+        Err(_) => vec![],
+    };
+    prev_txs.resize(inputs, None);
+    prev_txs
+}
+
+// Where previous transactions come from while parsing: the node, or a map supplied for
+// a replay that must not depend on it.
+trait PrevTxSource {
+    fn get_transactions(
+        &self,
+        txids: &[Txid],
+    ) -> Result<Vec<Option<bitcoin::Transaction>>, BatchRpcError>;
+}
+
+impl PrevTxSource for BatchRpcClient {
+    fn get_transactions(
+        &self,
+        txids: &[Txid],
+    ) -> Result<Vec<Option<bitcoin::Transaction>>, BatchRpcError> {
+        BatchRpcClient::get_transactions(self, txids)
+    }
+}
+
+// A transaction missing from the map leaves its inputs without info.
+impl PrevTxSource for HashMap<Txid, bitcoin::Transaction> {
+    fn get_transactions(
+        &self,
+        txids: &[Txid],
+    ) -> Result<Vec<Option<bitcoin::Transaction>>, BatchRpcError> {
+        Ok(txids.iter().map(|txid| self.get(txid).cloned()).collect())
+    }
+}
+
+struct PrevTxs {
+    // One per input, in input order.
+    txs: Vec<Option<bitcoin::Transaction>>,
+    // For a reveal, the outpoint its commit spent from the commit's parent, which stands
+    // in for the commit in `txs`.
+    commit_parent: Option<OutPoint>,
+    // For a reveal, the commit output it spends.
+    commit_output: Option<TxOut>,
+}
+
+impl PrevTxs {
+    fn unknown(inputs: usize) -> Self {
+        PrevTxs {
+            txs: vec![None; inputs],
+            commit_parent: None,
+            commit_output: None,
+        }
+    }
+}
+
+// The previous transaction of each input. A reveal's first input is given its commit's
+// parent instead, returned with the outpoint the commit spent from it.
+fn fetch_prev_txs(
+    source: &impl PrevTxSource,
+    tx: &bitcoin::Transaction,
+    is_reveal_tx: bool,
+    warnings: &mut Vec<String>,
+) -> PrevTxs {
+    let input_txids: Vec<_> = tx
+        .input
+        .iter()
+        .map(|vin| vin.previous_output.txid)
+        .collect();
+    let mut prev_txs = prev_txs_for_inputs(
+        source.get_transactions(&input_txids),
+        tx.input.len(),
+        warnings,
+    );
+    let mut commit_parent = None;
+    let mut commit_output = None;
+    if is_reveal_tx {
+        if let (Some(Some(prev_tx)), Some(input)) = (prev_txs.first(), tx.input.first()) {
+            commit_output = prev_tx
+                .output
+                .get(input.previous_output.vout as usize)
+                .cloned();
+            if let Some(commit_input) = prev_tx.input.first() {
+                let outpoint = commit_input.previous_output;
+                prev_txs[0] = fetch_commit_parent(source, outpoint.txid);
+                if prev_txs[0].is_none() {
+                    warnings.push(format!(
+                        "Failed to fetch commit parent {} of reveal input",
+                        outpoint.txid
+                    ));
+                }
+                commit_parent = Some(outpoint);
+            }
+        }
+    }
+    PrevTxs {
+        txs: prev_txs,
+        commit_parent,
+        commit_output,
+    }
+}
+
+// A P2WSH output's program is the SHA256 of the witness script that spends it. Nested in
+// P2SH, the redeem script is that P2WSH program.
+fn p2wsh_commits_to(script_pubkey: &Script, witness_script: &[u8]) -> bool {
+    let p2wsh = ScriptBuf::new_p2wsh(&Script::from_bytes(witness_script).wscript_hash());
+    script_pubkey == p2wsh.as_script()
+        || script_pubkey == ScriptBuf::new_p2sh(&p2wsh.script_hash()).as_script()
+}
+
+// A transient node issue can answer the batch without the transaction, so a missing
+// result is fetched once more before giving up.
+fn fetch_commit_parent(source: &impl PrevTxSource, txid: Txid) -> Option<bitcoin::Transaction> {
+    (0..2).find_map(|_| {
+        source
+            .get_transactions(&[txid])
+            .ok()
+            .and_then(|txs| txs.into_iter().next().flatten())
+    })
+}
+
+// The hash Counterparty identifies a transaction by. Before `correct_segwit_txids` it is
+// taken over the full serialization, which for a segwit transaction includes the marker,
+// flag and witness: the wtxid, not the txid. That is the hash recorded historically, so
+// it is kept. A transaction whose inputs all have empty witnesses serializes without
+// them and hashes to its txid. Such a transaction can't have been serialized as segwit
+// either: the marker and flag with no witness data is rejected by consensus and by the
+// decoder, so having witness data and being serialized as segwit are the same thing.
+pub fn counterparty_tx_hash(tx: &bitcoin::Transaction, config: &Config, height: Height) -> String {
+    let segwit = tx.input.iter().any(|input| !input.witness.is_empty());
+    if segwit && config.correct_segwit_txids_enabled(height) {
+        tx.compute_txid().to_string()
+    } else {
+        Sha256dHash::hash(&serialize(tx)).to_string()
+    }
+}
+
 pub fn parse_transaction(
     tx: &bitcoin::Transaction,
     config: &Config,
-    height: u32,
+    height: Height,
+    parse_vouts: bool,
+) -> Transaction {
+    parse_transaction_from(tx, config, height, parse_vouts, None, None)
+}
+
+// Like `parse_transaction`, looking previous transactions up in `prev_tx_map` when one
+// is given instead of fetching them from the node. With `vout_warnings`, every output
+// `parse_vout` rejects is reported there, including those after the one parsing stopped at.
+fn parse_transaction_from(
+    tx: &bitcoin::Transaction,
+    config: &Config,
+    height: Height,
     parse_vouts: bool,
+    prev_tx_map: Option<&HashMap<Txid, bitcoin::Transaction>>,
+    mut vout_warnings: Option<&mut Vec<VoutWarning>>,
 ) -> Transaction {
-    let tx_bytes = serialize(tx);
     let mut vins = Vec::new();
     let mut segwit = false;
     let mut vtxinwit: Vec<Vec<String>> = Vec::new();
@@ -621,23 +1841,25 @@ pub fn parse_transaction(
         }
     }
 
-    let key = if !tx.input.is_empty() {
-        let mut key = tx.input[0].previous_output.txid.to_byte_array().to_vec();
-        key.reverse();
-        key
-    } else {
-        Vec::new()
-    };
+    let key = arc4_key(tx);
+    let mut warnings = Vec::new();
+    // A coinbase always has the null outpoint; anywhere else the shared key is suspect.
+    if parse_vouts && !tx.is_coinbase() && !key.is_empty() && key.iter().all(|b| *b == 0) {
+        warnings.push("ARC4 key derived from an all-zeros outpoint txid".to_string());
+    }
 
     let mut vouts = Vec::new();
     let mut destinations = Vec::new();
-    let mut fee = 0;
+    let mut destination_script_types = Vec::new();
+    let mut btc_out_total: u64 = 0;
     let mut btc_amount = 0;
     let mut data = Vec::new();
     let mut is_reveal_tx = false;
-    let mut commit_parent_txid = Txid::from_raw_hash(Sha256dHash::all_zeros());
-    let mut commit_parent_vout = 0;
+    let mut p2wsh_witness_script = None;
+    let mut data_source: Option<DataSource> = None;
+    let mut prefix_index: Option<usize> = None;
     let mut potential_dispensers = Vec::new();
+    let mut taproot_internal_key = None;
     let mut err = None;
     for vout in tx.output.iter() {
         vouts.push(Vout {
@@ -647,13 +1869,49 @@ pub fn parse_transaction(
         });
     }
     let mut parsed_vouts: Result<ParsedVouts, String> = Err("Not Parsed".to_string());
-    if parse_vouts {
+    if parse_vouts && key.is_empty() {
+        // The ARC4 key comes from the first input and rust-crypto panics on an empty key.
+        parsed_vouts =
+            Err(Error::ParseVout("no inputs for key derivation".to_string()).to_string());
+    } else if parse_vouts
+        && config
+            .max_outputs_parsed
+            .is_some_and(|max| tx.output.len() > max)
+    {
+        parsed_vouts = Err(Error::ParseVout(format!(
+            "Too many outputs to parse: {} | tx: {}",
+            tx.output.len(),
+            tx.compute_txid()
+        ))
+        .to_string());
+    } else if parse_vouts {
+        // Outputs from here on haven't been through `parse_vout`.
+        let mut unclassified = 0;
         for (vi, vout) in tx.output.iter().enumerate() {
             if !config.multisig_addresses_enabled(height) {
                 continue;
             }
+            // Kept within i64, which `fee` and `btc_amount` are reported in, so a
+            // pathological transaction fails here instead of overflowing below.
+            btc_out_total = match btc_out_total.checked_add(vout.value.to_sat()) {
+                Some(total) if i64::try_from(total).is_ok() => total,
+                _ => {
+                    err = Some(Error::ParseVout(format!(
+                        "Total output value out of range | tx: {}",
+                        tx.compute_txid()
+                    )));
+                    break;
+                }
+            };
+            if is_skipped_coinbase_op_return(tx, vout) {
+                potential_dispensers.push(Some(PotentialDispenser {
+                    destination: None,
+                    value: None,
+                }));
+                continue;
+            }
             let output_value = vout.value.to_sat() as i64;
-            fee -= output_value;
+            unclassified = vi + 1;
             let result = parse_vout(
                 &config,
                 key.clone(),
@@ -664,31 +1922,66 @@ pub fn parse_transaction(
             );
             match result {
                 Err(e) => {
+                    if let Some(vout_warnings) = vout_warnings.as_deref_mut() {
+                        vout_warnings.push(VoutWarning {
+                            txid: tx.compute_txid().to_string(),
+                            vout: vi,
+                            reason: e.to_string(),
+                        });
+                    }
                     err = Some(e);
                     break;
                 }
                 Ok((parse_output, potential_dispenser)) => {
                     potential_dispensers.push(potential_dispenser);
-                    if data.is_empty()
+                    if let ParseOutput::Skip = parse_output {
+                        continue;
+                    }
+                    // Before flexible ordering, destinations are only accepted ahead of data.
+                    if (data.is_empty() || config.flexible_output_order_enabled(height))
                         && parse_output.is_destination()
                         && destinations != vec![config.unspendable()]
                     {
                         if let ParseOutput::Destination(destination) = parse_output {
                             destinations.push(destination);
+                            destination_script_types
+                                .push(ScriptType::from_script(&vout.script_pubkey));
                         }
                         btc_amount += output_value;
                     } else if parse_output.is_destination() {
                         break;
-                    } else if let ParseOutput::Data(mut new_data) = parse_output {
+                    } else if let ParseOutput::Data(mut new_data, prefix) = parse_output {
                         // reveal transaction data
-                        if config.taproot_support_enabled(height) && new_data == b"CNTRPRTY" && !vtxinwit.is_empty() && vtxinwit[0].len() == 3 {
-                            if let Ok(bytes) = hex::decode(&vtxinwit[0][1]) {
+                        let reveal_script = vtxinwit.first().and_then(|witness| {
+                            let has_annex = config.taproot_annex_enabled(height)
+                                && witness.last().is_some_and(|item| item.starts_with("50"));
+                            reveal_script_position(witness.len(), has_annex)
+                                .map(|i| (&witness[i], &witness[i + 1]))
+                        });
+                        if let Some((script_hex, control_block_hex)) = reveal_script.filter(|_| {
+                            config.taproot_support_enabled(height) && new_data == b"CNTRPRTY"
+                        }) {
+                            if let Ok(bytes) = hex::decode(script_hex) {
                                 let script = Script::from_bytes(&bytes);
-                                match extract_data_from_witness(&script) {
+                                match extract_data_from_witness(
+                                    &script,
+                                    EnvelopeRules::new(config, height),
+                                ) {
                                     Ok(mut inscription_data) => {
                                         if !inscription_data.is_empty() {
                                             is_reveal_tx = true;
+                                            taproot_internal_key = hex::decode(control_block_hex)
+                                                .ok()
+                                                .and_then(|bytes| {
+                                                    parse_taproot_control_block(&bytes).ok()
+                                                })
+                                                .map(|control_block| {
+                                                    hex::encode(control_block.internal_key)
+                                                });
                                             data.append(&mut inscription_data);
+                                            data_source = Some(
+                                                DataSource::TaprootReveal.combine(data_source),
+                                            );
                                         }
                                     },
                                     Err(e) => {
@@ -705,13 +1998,70 @@ pub fn parse_transaction(
                                     tx.compute_txid().to_string()
                                 )));
                             }
+                        } else if let Some(witness) = vtxinwit.first().filter(|witness| {
+                            !witness.is_empty()
+                                && new_data == b"CNTRPRTY"
+                                && config.p2wsh_data_enabled(height)
+                        }) {
+                            match extract_data_from_p2wsh_witness(
+                                witness,
+                                EnvelopeRules::new(config, height),
+                            ) {
+                                Some(mut inscription_data) => {
+                                    is_reveal_tx = true;
+                                    p2wsh_witness_script = witness
+                                        .last()
+                                        .and_then(|script_hex| hex::decode(script_hex).ok());
+                                    data.append(&mut inscription_data);
+                                    data_source =
+                                        Some(DataSource::P2wshReveal.combine(data_source));
+                                }
+                                None => {
+                                    err = Some(Error::ParseVout(format!(
+                                        "Failed to extract data from P2WSH witness for tx: {}",
+                                        tx.compute_txid()
+                                    )));
+                                }
+                            }
                         } else {
-                            data.append(&mut new_data)
+                            data.append(&mut new_data);
+                            data_source = Some(
+                                data_source_for_script(&vout.script_pubkey).combine(data_source),
+                            );
+                            prefix_index = prefix_index.or(prefix);
                         }
                     }
                 }
             }
         }
+        if let Some(vout_warnings) =
+            vout_warnings.filter(|_| config.multisig_addresses_enabled(height))
+        {
+            let txid = tx.compute_txid().to_string();
+            for (vi, vout) in tx.output.iter().enumerate().skip(unclassified) {
+                if is_skipped_coinbase_op_return(tx, vout) {
+                    continue;
+                }
+                if let Err(e) = parse_vout(config, key.clone(), height, txid.clone(), vi, vout) {
+                    vout_warnings.push(VoutWarning {
+                        txid: txid.clone(),
+                        vout: vi,
+                        reason: e.to_string(),
+                    });
+                }
+            }
+        }
+        if config.scriptsig_data_enabled(height) {
+            for vin in tx.input.iter() {
+                if let Some((mut scriptsig_data, prefix)) =
+                    extract_data_from_script_sig(config, &key, &vin.script_sig)
+                {
+                    data.append(&mut scriptsig_data);
+                    data_source = Some(DataSource::ScriptSig.combine(data_source));
+                    prefix_index = prefix_index.or(Some(prefix));
+                }
+            }
+        }
         if !config.multisig_addresses_enabled(height) {
             err = Some(Error::ParseVout(
                 "Multisig addresses are not enabled".to_string(),
@@ -722,80 +2072,71 @@ pub fn parse_transaction(
         } else {
             Ok(ParsedVouts {
                 destinations,
+                destination_script_types,
                 btc_amount,
-                fee,
+                fee: -(btc_out_total as i64),
                 data: data.clone(),
                 potential_dispensers,
                 is_reveal_tx,
+                data_source,
+                prefix: prefix_index.and_then(|i| config.prefixes.get(i).cloned()),
+                has_bare_marker: tx
+                    .output
+                    .iter()
+                    .any(|vout| is_bare_marker(&vout.script_pubkey)),
+                messages: split_messages(&data, config.message_separator.as_deref()),
             })
         };
     }
 
     // Try to get previous transactions info if RPC is available and data is not empty
-    let mut prev_txs = vec![None; tx.input.len()];
+    let mut prev_txs = PrevTxs::unknown(tx.input.len());
     if !data.is_empty() || 
         parsed_vouts.as_ref().map_or(false, |p| p.destinations == vec![config.unspendable()]) {
 
-        if BATCH_CLIENT.lock().unwrap().is_none() {
-            *BATCH_CLIENT.lock().unwrap() = Some(
-                BatchRpcClient::new(
-                    config.rpc_address.clone(),
-                    config.rpc_user.clone(),
-                    config.rpc_password.clone(),
-                )
-                .unwrap(),
-            );
+        if let Some(prev_tx_map) = prev_tx_map {
+            prev_txs = fetch_prev_txs(prev_tx_map, tx, is_reveal_tx, &mut warnings);
+        } else {
+            if BATCH_CLIENT.lock().unwrap().is_none() {
+                *BATCH_CLIENT.lock().unwrap() = Some(
+                    BatchRpcClient::new(
+                        config.rpc_addresses.clone(),
+                        config.rpc_user.clone(),
+                        config.rpc_password.clone(),
+                        config.prev_tx_cache_size,
+                    )
+                    .unwrap()
+                    .with_timeout(config.rpc_timeout)
+                    .with_max_batch_size(config.max_rpc_batch_size),
+                );
+            }
+
+            if let Some(batch_client) = BATCH_CLIENT.lock().unwrap().as_ref() {
+                prev_txs = fetch_prev_txs(batch_client, tx, is_reveal_tx, &mut warnings);
+            }
         }
+    }
 
-        if let Some(batch_client) = BATCH_CLIENT.lock().unwrap().as_ref() {
-
-            let input_txids: Vec<_> = tx
-                .input
-                .iter()
-                .map(|vin| vin.previous_output.txid)
-                .collect();
-            prev_txs = batch_client
-                .get_transactions(&input_txids)
-                .unwrap_or_default();
-
-            if is_reveal_tx && !prev_txs.is_empty() {
-                if let Some(prev_tx) = &prev_txs[0] {
-                    if !prev_tx.input.is_empty() {
-                        commit_parent_txid = prev_tx.input[0].previous_output.txid;
-                        commit_parent_vout = prev_tx.input[0].previous_output.vout as usize;
-                        if let Ok(fetched_txs) = batch_client.get_transactions(&[commit_parent_txid]) {
-                            if !fetched_txs.is_empty() {
-                                prev_txs[0] = fetched_txs[0].clone();
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    }
+    // Consensus already ties a P2WSH spend's witness script to the output it spends, so
+    // this never decides whether the reveal counts. A mismatch means the commit output
+    // looked up isn't the one spent, and is only reported.
+    if let (Some(witness_script), Some(output)) = (p2wsh_witness_script, &prev_txs.commit_output) {
+        if !p2wsh_commits_to(&output.script_pubkey, &witness_script) {
+            warnings.push("P2WSH witness script does not match the looked up commit output".into());
+        }
+    }
 
     for (i, vin) in tx.input.iter().enumerate() {
         let hash = vin.previous_output.txid.to_string();
-        let vin_info = prev_txs.get(i).and_then(|prev_tx| {
+        let vin_info = prev_txs.txs.get(i).and_then(|prev_tx| {
             prev_tx.as_ref().and_then(|tx| {
                 let tx_id = tx.compute_txid();
-                let vout_idx = if tx_id == commit_parent_txid {
-                    commit_parent_vout
-                } else {
-                    vin.previous_output.vout as usize
+                let vout_idx = match prev_txs.commit_parent {
+                    Some(OutPoint { txid, vout }) if txid == tx_id => vout as usize,
+                    _ => vin.previous_output.vout as usize,
                 };
 
-                let is_segwit = tx_id.to_string() != tx.compute_wtxid().to_string();
-
-                tx.output.get(vout_idx).map(|output| VinOutput {
-                    value: output.value.to_sat(),
-                    script_pub_key: output.script_pubkey.to_bytes(),
-                    is_segwit: if config.fix_is_segwit_enabled(height) { 
-                        output.script_pubkey.is_witness_program()
-                    } else {
-                        is_segwit
-                    },
-                })
+                vin_output(config, height, vin, tx, vout_idx)
             })
         });
 
@@ -809,12 +2150,28 @@ pub fn parse_transaction(
     }
 
     let tx_id = tx.compute_txid().to_string();
-    let tx_hash;
-    if segwit && config.correct_segwit_txids_enabled(height) {
-        tx_hash = tx_id.clone();
-    } else {
-        tx_hash = Sha256dHash::hash(&tx_bytes).to_string();
-    }
+    let tx_hash = counterparty_tx_hash(tx, config, height);
+
+    let burn_amount = parsed_vouts
+        .as_ref()
+        .ok()
+        .filter(|p| p.destinations == vec![config.unspendable()])
+        .map(|p| p.btc_amount);
+
+    let marker_variant = tx
+        .output
+        .iter()
+        .find_map(|vout| marker_variant(&vout.script_pubkey))
+        .map(<[u8]>::to_vec);
+
+    // Consensus takes the last matching output.
+    let witness_commitment = tx
+        .output
+        .iter()
+        .rev()
+        .filter(|_| tx.is_coinbase())
+        .find_map(|vout| witness_commitment(&vout.script_pubkey))
+        .map(hex::encode);
 
     Transaction {
         version: tx.version.0,
@@ -827,42 +2184,96 @@ pub fn parse_transaction(
         vin: vins,
         vout: vouts,
         parsed_vouts,
+        is_burn: burn_amount.is_some(),
+        burn_amount,
+        taproot_internal_key,
+        warnings,
+        witness_commitment,
+        marker_variant,
     }
 }
 
 impl ToBlock for Block {
-    fn to_block(&self, config: Config, height: u32) -> CrateBlock {
-        let mut transactions = Vec::new();
-        for tx in self.txdata.iter() {
-            transactions.push(parse_transaction(tx, &config, height, true));
-        }
-        CrateBlock {
-            height,
-            version: self.header.version.to_consensus(),
-            hash_prev: self.header.prev_blockhash.to_string(),
-            hash_merkle_root: self.header.merkle_root.to_string(),
-            block_time: self.header.time,
-            bits: self.header.bits.to_consensus(),
-            nonce: self.header.nonce,
-            block_hash: self.block_hash().to_string(),
-            transaction_count: self.txdata.len(),
-            transactions,
-        }
+    fn to_block(&self, config: Config, height: Height) -> Result<CrateBlock, Error> {
+        parse_block_from(self, &config, height, true, None, None)
     }
 }
 
+// Whether the outputs of `tx` are parsed when its block's are.
+fn parses_vouts(config: &Config, tx: &bitcoin::Transaction) -> bool {
+    config.parse_coinbase || !tx.is_coinbase()
+}
+
+// Only the genesis block has no parent. Its coinbase is fixed per network, pays a bare
+// P2PK and spends the null outpoint, so it is recorded without parsing its outputs.
+fn is_genesis(block: &Block) -> bool {
+    block.header.prev_blockhash == BlockHash::all_zeros()
+}
+
 pub fn parse_block(
     block: Block,
     config: &Config,
-    height: u32,
+    height: Height,
+    parse_vouts: bool,
+) -> Result<CrateBlock, Error> {
+    parse_block_from(&block, config, height, parse_vouts, None, None)
+}
+
+// Like `parse_block`, with every previous transaction taken from `prev_txs` rather than
+// the node, so a historical block reparses the same way every time. Inputs whose
+// previous transaction isn't in the map are left without info.
+pub fn parse_block_with_prev_txs(
+    block: Block,
+    config: &Config,
+    height: Height,
+    parse_vouts: bool,
+    prev_txs: &HashMap<Txid, bitcoin::Transaction>,
+) -> Result<CrateBlock, Error> {
+    parse_block_from(&block, config, height, parse_vouts, Some(prev_txs), None)
+}
+
+fn parse_block_from(
+    block: &Block,
+    config: &Config,
+    height: Height,
     parse_vouts: bool,
+    prev_tx_map: Option<&HashMap<Txid, bitcoin::Transaction>>,
+    mut vout_warnings: Option<&mut Vec<VoutWarning>>,
 ) -> Result<CrateBlock, Error> {
+    if config.verify_merkle_root && !block.check_merkle_root() {
+        return Err(Error::MerkleRootMismatch(block.block_hash().to_string()));
+    }
+    // Overrides the caller: there is nothing to parse before the protocol's first block.
+    let parse_vouts =
+        parse_vouts && !config.before_counterparty_start(height) && !is_genesis(block);
     let mut transactions = Vec::new();
+    let mut parse_errors = 0;
     for tx in block.txdata.iter() {
-        transactions.push(parse_transaction(tx, config, height, parse_vouts));
+        let parse_vouts = parse_vouts && parses_vouts(config, tx);
+        let transaction = parse_transaction_from(
+            tx,
+            config,
+            height,
+            parse_vouts,
+            prev_tx_map,
+            vout_warnings.as_deref_mut(),
+        );
+        if parse_vouts && transaction.parsed_vouts.is_err() {
+            parse_errors += 1;
+            if config
+                .max_parse_errors_per_block
+                .is_some_and(|max| parse_errors > max)
+            {
+                return Err(Error::TooManyParseErrors {
+                    height: height.into(),
+                    count: parse_errors,
+                });
+            }
+        }
+        transactions.push(transaction);
     }
     Ok(CrateBlock {
-        height,
+        height: height.into(),
         version: block.header.version.to_consensus(),
         hash_prev: block.header.prev_blockhash.to_string(),
         hash_merkle_root: block.header.merkle_root.to_string(),
@@ -875,16 +2286,143 @@ pub fn parse_block(
     })
 }
 
-impl BlockHasPrevBlockHash for Block {
-    fn get_prev_block_hash(&self) -> &BlockHash {
-        &self.header.prev_blockhash
+// Parses a consensus encoded block, such as one kept with `store_raw_blocks`.
+pub fn parse_block_bytes(
+    bytes: &[u8],
+    config: &Config,
+    height: Height,
+    parse_vouts: bool,
+) -> Result<CrateBlock, Error> {
+    let block: Block = bitcoin::consensus::deserialize(bytes)?;
+    parse_block(block, config, height, parse_vouts)
+}
+
+// Reparses the block stored at `height` under the current config, without fetching it
+// again. Only blocks written with `store_raw_blocks` on can be reparsed.
+pub fn reparse_height<D: DatabaseOps>(
+    height: Height,
+    config: &Config,
+    db: &D,
+) -> Result<CrateBlock, Error> {
+    let bytes = db
+        .raw_block_bytes_at_height(height.into())?
+        .ok_or(Error::BlockNotWritten(height.into()))?;
+    parse_block_bytes(&bytes, config, height, true)
+}
+
+// Like `parse_block` with outputs parsed, also reporting every output `parse_vout`
+// rejects. `parse_transaction` stops at a transaction's first bad output; here the rest
+// are still classified so a reindex sees all of them. The parsed block is the same
+// either way. Transactions over `max_outputs_parsed` have no outputs classified.
+pub fn parse_block_verbose(
+    block: Block,
+    config: &Config,
+    height: Height,
+) -> Result<(CrateBlock, Vec<VoutWarning>), Error> {
+    let mut warnings = Vec::new();
+    let parsed = parse_block_from(&block, config, height, true, None, Some(&mut warnings))?;
+    Ok((parsed, warnings))
+}
+
+// Like `parse_block`, keeping only transactions that pay to a watched address or spend
+// from one. Spends are only seen where the previous output was fetched, which
+// `parse_transaction` does for Counterparty transactions. `transaction_count` still
+// counts the whole block.
+pub fn parse_block_filtered(
+    block: Block,
+    config: &Config,
+    height: Height,
+    addresses: &HashSet<String>,
+) -> Result<CrateBlock, Error> {
+    let mut parsed = parse_block(block, config, height, true)?;
+    let network = config.network.to_string();
+    parsed.transactions.retain(|tx| {
+        let pays = tx.parsed_vouts.as_ref().is_ok_and(|parsed_vouts| {
+            parsed_vouts
+                .destinations
+                .iter()
+                .any(|destination| addresses.contains(destination))
+        });
+        let spends = tx
+            .vin
+            .iter()
+            .filter_map(|vin| vin.info.as_ref())
+            .any(|info| {
+                script_to_address(info.script_pub_key.clone(), &network)
+                    .is_ok_and(|address| addresses.contains(&address))
+            });
+        pays || spends
+    });
+    Ok(parsed)
+}
+
+// Whether any transaction in the block carries Counterparty data, for skipping empty
+// blocks without parsing them. Outputs are classified by `parse_vout`, so nothing is
+// fetched and no `Transaction` is built, and the scan stops at the first hit. It may
+// answer true for a transaction a full parse rejects, but never false for one it accepts.
+pub fn block_has_counterparty_data(block: &Block, config: &Config, height: Height) -> bool {
+    block.txdata.iter().any(|tx| {
+        let key = arc4_key(tx);
+        if key.is_empty() {
+            return false;
+        }
+        let in_outputs = tx.output.iter().enumerate().any(|(vi, vout)| {
+            matches!(
+                parse_vout(config, key.clone(), height, String::new(), vi, vout),
+                Ok((ParseOutput::Data(..), _))
+            )
+        });
+        in_outputs
+            || (config.scriptsig_data_enabled(height)
+                && tx.input.iter().any(|vin| {
+                    extract_data_from_script_sig(config, &key, &vin.script_sig).is_some()
+                }))
+    })
+}
+
+// Like `parse_block`, but parses each transaction only when the caller asks for it, so
+// peak memory is one transaction rather than the whole parsed block. Previous outputs
+// are still fetched per transaction, so nothing can be prefetched for the block as a
+// whole. `max_parse_errors_per_block` is not applied: earlier transactions have already
+// been handed out by the time the budget runs out.
+pub fn parse_block_streaming(
+    block: Block,
+    config: &Config,
+    height: Height,
+    parse_vouts: bool,
+) -> Result<impl Iterator<Item = Transaction> + '_, Error> {
+    if config.verify_merkle_root && !block.check_merkle_root() {
+        return Err(Error::MerkleRootMismatch(block.block_hash().to_string()));
     }
+    let parse_vouts =
+        parse_vouts && !config.before_counterparty_start(height) && !is_genesis(&block);
+    Ok(block.txdata.into_iter().map(move |tx| {
+        let parse_vouts = parse_vouts && parses_vouts(config, &tx);
+        parse_transaction(&tx, config, height, parse_vouts)
+    }))
 }
 
 pub trait BitcoinRpc<B>: Send + Clone + 'static {
     fn get_block_hash(&self, height: u32) -> Result<BlockHash, Error>;
     fn get_block(&self, hash: &BlockHash) -> Result<Box<B>, Error>;
+    fn get_block_header(&self, hash: &BlockHash) -> Result<Header, Error>;
     fn get_blockchain_height(&self) -> Result<u32, Error>;
+    fn get_network_info(&self) -> Result<NetworkInfo, Error>;
+    fn get_chain(&self) -> Result<String, Error>;
+    fn get_tx_out(
+        &self,
+        txid: &Txid,
+        vout: u32,
+        include_mempool: bool,
+    ) -> Result<Option<TxOut>, Error>;
+
+    // Height of the deepest block with at least `min_confirmations` blocks on top of it,
+    // or 0 while the chain is shorter than that.
+    fn get_safe_height(&self, min_confirmations: u32) -> Result<u32, Error> {
+        Ok(self
+            .get_blockchain_height()?
+            .saturating_sub(min_confirmations))
+    }
 }
 
 struct GetBlockHash {
@@ -897,17 +2435,41 @@ struct GetBlock {
     sender: Sender<Result<Box<Block>, Error>>,
 }
 
+struct GetBlockHeader {
+    hash: BlockHash,
+    sender: Sender<Result<Header, Error>>,
+}
+
 struct GetBlockchainHeight {
     sender: Sender<Result<u32, Error>>,
 }
 
+struct GetNetworkInfo {
+    sender: Sender<Result<NetworkInfo, Error>>,
+}
+
+struct GetChain {
+    sender: Sender<Result<String, Error>>,
+}
+
+struct GetTxOut {
+    txid: Txid,
+    vout: u32,
+    include_mempool: bool,
+    sender: Sender<Result<Option<TxOut>, Error>>,
+}
+
 type Channel<T> = (Sender<T>, Receiver<T>);
 
 #[derive(Clone)]
 struct Channels {
     get_block_hash: Channel<GetBlockHash>,
     get_block: Channel<GetBlock>,
+    get_block_header: Channel<GetBlockHeader>,
     get_blockchain_height: Channel<GetBlockchainHeight>,
+    get_network_info: Channel<GetNetworkInfo>,
+    get_chain: Channel<GetChain>,
+    get_tx_out: Channel<GetTxOut>,
 }
 
 impl Channels {
@@ -915,17 +2477,50 @@ impl Channels {
         Channels {
             get_block_hash: bounded(n),
             get_block: bounded(n),
+            get_block_header: bounded(n),
             get_blockchain_height: bounded(n),
+            get_network_info: bounded(n),
+            get_chain: bounded(n),
+            get_tx_out: bounded(n),
+        }
+    }
+}
+
+struct BlockCache {
+    entries: LruCache<BlockHash, Block>,
+    hits: u64,
+    misses: u64,
+}
+
+impl BlockCache {
+    fn new(capacity: NonZeroUsize) -> Self {
+        BlockCache {
+            entries: LruCache::new(capacity),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            len: self.entries.len(),
+            capacity: self.entries.cap().get(),
         }
     }
 }
 
+// Blocks are keyed by their hash, so cached entries never go stale.
+type SharedBlockCache = Arc<Mutex<BlockCache>>;
+
 #[derive(Clone)]
 pub struct BitcoinClient {
     n: usize,
     config: Config,
     stopper: Stopper,
     channels: Channels,
+    block_cache: Option<SharedBlockCache>,
 }
 
 impl BitcoinClient {
@@ -935,13 +2530,103 @@ impl BitcoinClient {
             config: config.clone(),
             stopper,
             channels: Channels::new(n),
+            block_cache: NonZeroUsize::new(config.block_cache_size)
+                .map(|capacity| Arc::new(Mutex::new(BlockCache::new(capacity)))),
         };
         Ok(client)
     }
 
+    // Hands a request to the workers, applying the configured policy when the queue is full.
+    fn enqueue<T>(&self, sender: &Sender<T>, request: T) -> Result<(), Error> {
+        match self.config.request_queue_policy {
+            RequestQueuePolicy::Block => Ok(sender.send(request)?),
+            RequestQueuePolicy::FailFast => sender.try_send(request).map_err(|e| match e {
+                TrySendError::Full(_) => Error::Busy,
+                TrySendError::Disconnected(_) => Error::Send(e.to_string()),
+            }),
+            RequestQueuePolicy::Timeout(timeout) => {
+                sender.send_timeout(request, timeout).map_err(|e| match e {
+                    SendTimeoutError::Timeout(_) => Error::Busy,
+                    SendTimeoutError::Disconnected(_) => Error::Send(e.to_string()),
+                })
+            }
+        }
+    }
+
+    // Fails when the node is on a different chain than `config.network`, which would
+    // otherwise go unnoticed until addresses come out in the wrong format.
+    pub fn verify_network_matches(&self, config: &Config) -> Result<(), Error> {
+        let actual = self.get_chain()?;
+        let expected = config.network.chain_name();
+        if actual != expected {
+            return Err(Error::NetworkMismatch {
+                expected: expected.into(),
+                actual,
+            });
+        }
+        Ok(())
+    }
+
+    // Fetches the block at `height` through the workers and parses it.
+    pub fn fetch_and_parse(
+        &self,
+        height: Height,
+        config: &Config,
+        parse_vouts: bool,
+    ) -> Result<CrateBlock, Error> {
+        let hash = self.get_block_hash(height.into())?;
+        let block = self.get_block(&hash)?;
+        parse_block(*block, config, height, parse_vouts)
+    }
+
+    // Fetches the block with `hash` and parses it at `height`. Without a height, it is
+    // read from the coinbase (BIP34), so blocks older than that need one: parsing
+    // depends on the height for every protocol change.
+    pub fn fetch_and_parse_by_hash(
+        &self,
+        hash: &BlockHash,
+        height: Option<Height>,
+        config: &Config,
+        parse_vouts: bool,
+    ) -> Result<CrateBlock, Error> {
+        let block = self.get_block(hash)?;
+        let height = match height {
+            Some(height) => height,
+            None => block
+                .bip34_block_height()
+                .ok()
+                .and_then(|height| u32::try_from(height).ok())
+                .map(Height)
+                .ok_or_else(|| Error::UnknownHeight(hash.to_string()))?,
+        };
+        parse_block(*block, config, height, parse_vouts)
+    }
+
+    pub fn block_cache_stats(&self) -> Option<CacheStats> {
+        self.block_cache
+            .as_ref()
+            .map(|cache| cache.lock().unwrap_or_else(|e| e.into_inner()).stats())
+    }
+
+    pub fn fetch_and_parse_range(
+        &self,
+        start: u32,
+        end: u32,
+        config: &Config,
+    ) -> Result<impl Iterator<Item = Result<CrateBlock, Error>>, Error> {
+        OrderedBlocks::new(
+            self.clone(),
+            self.stopper.clone(),
+            config,
+            start..=end,
+            self.n,
+            config.fetch_window,
+        )
+    }
+
     pub fn start(&self) -> Result<Vec<JoinHandle<Result<(), Error>>>, Error> {
         let (_tx, _rx) = unbounded();
-        let client = BitcoinClientInner::new(&self.config)?;
+        let client = BitcoinClientInner::new(&self.config, self.block_cache.clone())?;
         new_worker_pool(
             "BitcoinClient".into(),
             self.n,
@@ -972,10 +2657,30 @@ impl BitcoinClient {
                   sender.send(client.get_block(&hash))?;
                 }
               },
+              recv(channels.get_block_header.1) -> msg => {
+                if let Ok(GetBlockHeader {hash, sender}) = msg {
+                  sender.send(client.get_block_header(&hash))?;
+                }
+              },
               recv(channels.get_blockchain_height.1) -> msg => {
                 if let Ok(GetBlockchainHeight {sender}) = msg {
                   sender.send(client.get_blockchain_height())?;
                 }
+              },
+              recv(channels.get_network_info.1) -> msg => {
+                if let Ok(GetNetworkInfo {sender}) = msg {
+                  sender.send(client.get_network_info())?;
+                }
+              },
+              recv(channels.get_chain.1) -> msg => {
+                if let Ok(GetChain {sender}) = msg {
+                  sender.send(client.get_chain())?;
+                }
+              },
+              recv(channels.get_tx_out.1) -> msg => {
+                if let Ok(GetTxOut {txid, vout, include_mempool, sender}) = msg {
+                  sender.send(client.get_tx_out(&txid, vout, include_mempool))?;
+                }
               }
             }
         }
@@ -985,10 +2690,10 @@ impl BitcoinClient {
 impl BitcoinRpc<Block> for BitcoinClient {
     fn get_block_hash(&self, height: u32) -> Result<BlockHash, Error> {
         let (tx, rx) = bounded(1);
-        self.channels
-            .get_block_hash
-            .0
-            .send(GetBlockHash { height, sender: tx })?;
+        self.enqueue(
+            &self.channels.get_block_hash.0,
+            GetBlockHash { height, sender: tx },
+        )?;
         let (id, done) = self.stopper.subscribe()?;
         select! {
             recv(done) -> _ => Err(Error::Stopped),
@@ -1001,10 +2706,32 @@ impl BitcoinRpc<Block> for BitcoinClient {
 
     fn get_block(&self, hash: &BlockHash) -> Result<Box<Block>, Error> {
         let (tx, rx) = bounded(1);
-        self.channels.get_block.0.send(GetBlock {
-            hash: *hash,
-            sender: tx,
-        })?;
+        self.enqueue(
+            &self.channels.get_block.0,
+            GetBlock {
+                hash: *hash,
+                sender: tx,
+            },
+        )?;
+        let (id, done) = self.stopper.subscribe()?;
+        select! {
+            recv(done) -> _ => Err(Error::Stopped),
+            recv(rx) -> result => {
+                self.stopper.unsubscribe(id)?;
+                result?
+            }
+        }
+    }
+
+    fn get_block_header(&self, hash: &BlockHash) -> Result<Header, Error> {
+        let (tx, rx) = bounded(1);
+        self.enqueue(
+            &self.channels.get_block_header.0,
+            GetBlockHeader {
+                hash: *hash,
+                sender: tx,
+            },
+        )?;
         let (id, done) = self.stopper.subscribe()?;
         select! {
             recv(done) -> _ => Err(Error::Stopped),
@@ -1017,10 +2744,65 @@ impl BitcoinRpc<Block> for BitcoinClient {
 
     fn get_blockchain_height(&self) -> Result<u32, Error> {
         let (tx, rx) = bounded(1);
-        self.channels
-            .get_blockchain_height
-            .0
-            .send(GetBlockchainHeight { sender: tx })?;
+        self.enqueue(
+            &self.channels.get_blockchain_height.0,
+            GetBlockchainHeight { sender: tx },
+        )?;
+        let (id, done) = self.stopper.subscribe()?;
+        select! {
+            recv(done) -> _ => Err(Error::Stopped),
+            recv(rx) -> result => {
+                self.stopper.unsubscribe(id)?;
+                result?
+            }
+        }
+    }
+
+    fn get_network_info(&self) -> Result<NetworkInfo, Error> {
+        let (tx, rx) = bounded(1);
+        self.enqueue(
+            &self.channels.get_network_info.0,
+            GetNetworkInfo { sender: tx },
+        )?;
+        let (id, done) = self.stopper.subscribe()?;
+        select! {
+            recv(done) -> _ => Err(Error::Stopped),
+            recv(rx) -> result => {
+                self.stopper.unsubscribe(id)?;
+                result?
+            }
+        }
+    }
+
+    fn get_chain(&self) -> Result<String, Error> {
+        let (tx, rx) = bounded(1);
+        self.enqueue(&self.channels.get_chain.0, GetChain { sender: tx })?;
+        let (id, done) = self.stopper.subscribe()?;
+        select! {
+            recv(done) -> _ => Err(Error::Stopped),
+            recv(rx) -> result => {
+                self.stopper.unsubscribe(id)?;
+                result?
+            }
+        }
+    }
+
+    fn get_tx_out(
+        &self,
+        txid: &Txid,
+        vout: u32,
+        include_mempool: bool,
+    ) -> Result<Option<TxOut>, Error> {
+        let (tx, rx) = bounded(1);
+        self.enqueue(
+            &self.channels.get_tx_out.0,
+            GetTxOut {
+                txid: *txid,
+                vout,
+                include_mempool,
+                sender: tx,
+            },
+        )?;
         let (id, done) = self.stopper.subscribe()?;
         select! {
             recv(done) -> _ => Err(Error::Stopped),
@@ -1032,22 +2814,187 @@ impl BitcoinRpc<Block> for BitcoinClient {
     }
 }
 
+type FetchedSender = Sender<Box<(Height, Block)>>;
+
+fn range_fetcher<C: BitcoinRpc<Block>>(
+    client: C,
+) -> impl Fn(Receiver<u32>, FetchedSender, Stopper) -> Result<(), Error> + Clone {
+    move |rx, tx, stopper| {
+        let (_, done) = stopper.subscribe()?;
+        loop {
+            select! {
+                recv(done) -> _ => return Ok(()),
+                recv(rx) -> result => {
+                    let height = match result {
+                        Ok(height) => height,
+                        Err(_) => return Ok(()),
+                    };
+                    let block = client.get_block(&client.get_block_hash(height)?)?;
+                    if tx.send(Box::new((height.into(), *block))).is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Fetches a height range on a "RangeFetcher" pool and parses it through `ParseWorker`,
+// yielding blocks strictly in height order. At most `window` heights are in flight at any
+// time. The pools run under their own stopper, so a bad block ends the range rather than
+// the indexer, and iteration ends after the first error.
+struct OrderedBlocks {
+    next: u32,
+    next_dispatch: u64,
+    end: u32,
+    window: usize,
+    finished: bool,
+    heights: Option<Sender<u32>>,
+    results: Receiver<Box<CrateBlock>>,
+    handles: Vec<JoinHandle<Result<(), Error>>>,
+    range_stopper: Stopper,
+    stopper: Stopper,
+    subscription: (Uuid, Done),
+}
+
+impl OrderedBlocks {
+    fn new<C: BitcoinRpc<Block>>(
+        client: C,
+        stopper: Stopper,
+        config: &Config,
+        range: std::ops::RangeInclusive<u32>,
+        n: usize,
+        window: usize,
+    ) -> Result<Self, Error> {
+        let subscription = stopper.subscribe()?;
+        let range_stopper = Stopper::new();
+        let (tx_heights, rx_heights) = unbounded();
+        let (tx_blocks, rx_blocks) = unbounded();
+        let (tx_parsed, rx_parsed) = unbounded();
+        let mut handles = new_worker_pool(
+            "RangeFetcher".into(),
+            n.max(1),
+            rx_heights,
+            tx_blocks,
+            range_stopper.clone(),
+            range_fetcher(client),
+        )?;
+        handles.append(&mut ParseWorker::new(config.clone(), n).start(
+            *range.start(),
+            rx_blocks,
+            tx_parsed,
+            range_stopper.clone(),
+        )?);
+
+        let mut blocks = OrderedBlocks {
+            next: *range.start(),
+            next_dispatch: *range.start() as u64,
+            end: *range.end(),
+            window: window.max(1),
+            finished: false,
+            heights: Some(tx_heights),
+            results: rx_parsed,
+            handles,
+            range_stopper,
+            stopper,
+            subscription,
+        };
+        if range.is_empty() {
+            blocks.finish();
+        }
+        Ok(blocks)
+    }
+
+    fn finish(&mut self) {
+        self.finished = true;
+        self.heights = None;
+        let _ = self.range_stopper.stop();
+    }
+
+    // The pools only hang up early once a worker has failed and stopped the range, so the
+    // failing worker's handle holds the error.
+    fn worker_error(&mut self) -> Error {
+        self.finish();
+        self.handles
+            .drain(..)
+            .filter_map(|handle| handle.join().ok())
+            .find_map(Result::err)
+            .unwrap_or(Error::Stopped)
+    }
+
+    fn dispatch(&mut self) -> Result<(), Error> {
+        let Some(heights) = &self.heights else {
+            return Ok(());
+        };
+        while self.next_dispatch <= self.end as u64
+            && ((self.next_dispatch - self.next as u64) as usize) < self.window
+        {
+            heights.send(self.next_dispatch as u32)?;
+            self.next_dispatch += 1;
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for OrderedBlocks {
+    type Item = Result<CrateBlock, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        if let Err(e) = self.dispatch() {
+            self.finish();
+            return Some(Err(e));
+        }
+        let done = self.subscription.1.clone();
+        select! {
+            recv(done) -> _ => {
+                self.finish();
+                Some(Err(Error::Stopped))
+            },
+            recv(self.results) -> msg => match msg {
+                Ok(block) => {
+                    if self.next == self.end {
+                        self.finish();
+                    } else {
+                        self.next += 1;
+                    }
+                    Some(Ok(*block))
+                }
+                Err(_) => Some(Err(self.worker_error())),
+            }
+        }
+    }
+}
+
+impl Drop for OrderedBlocks {
+    fn drop(&mut self) {
+        let _ = self.range_stopper.stop();
+        let _ = self.stopper.unsubscribe(self.subscription.0);
+    }
+}
+
 #[derive(Clone)]
 struct BitcoinClientInner {
     client: Arc<BatchRpcClient>,
+    block_cache: Option<SharedBlockCache>,
 }
 
 impl BitcoinClientInner {
-    fn new(config: &Config) -> Result<Self, Error> {
+    fn new(config: &Config, block_cache: Option<SharedBlockCache>) -> Result<Self, Error> {
         let client = BatchRpcClient::new(
-            config.rpc_address.clone(),
+            config.rpc_addresses.clone(),
             config.rpc_user.clone(),
             config.rpc_password.clone(),
+            config.prev_tx_cache_size,
         )
-        .map_err(|e| Error::BitcoinRpc(format!("Failed to create BatchRpcClient: {:#?}", e)))?;
+        .map_err(|e| Error::BitcoinRpc(format!("Failed to create BatchRpcClient: {:#?}", e)))?
+        .with_max_batch_size(config.max_rpc_batch_size);
 
         Ok(BitcoinClientInner {
             client: Arc::new(client),
+            block_cache,
         })
     }
 }
@@ -1060,10 +3007,40 @@ impl BitcoinRpc<Block> for BitcoinClientInner {
     }
 
     fn get_block(&self, hash: &BlockHash) -> Result<Box<Block>, Error> {
-        self.client
+        if let Some(cache) = &self.block_cache {
+            let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(block) = cache.entries.get(hash).cloned() {
+                cache.hits += 1;
+                return Ok(Box::new(block));
+            }
+            cache.misses += 1;
+        }
+
+        let block = self
+            .client
             .get_block(hash)
-            .map(Box::new)
-            .map_err(|e| Error::BitcoinRpc(format!("Failed to get block: {:#?}", e)))
+            .map_err(|e| Error::BitcoinRpc(format!("Failed to get block: {:#?}", e)))?;
+        // The node is not trusted to answer with the block asked for.
+        if block.block_hash() != *hash {
+            return Err(Error::BlockHashMismatch {
+                requested: hash.to_string(),
+                got: block.block_hash().to_string(),
+            });
+        }
+        if let Some(cache) = &self.block_cache {
+            cache
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .entries
+                .put(*hash, block.clone());
+        }
+        Ok(Box::new(block))
+    }
+
+    fn get_block_header(&self, hash: &BlockHash) -> Result<Header, Error> {
+        self.client
+            .get_block_header(hash)
+            .map_err(|e| Error::BitcoinRpc(format!("Failed to get block header: {:#?}", e)))
     }
 
     fn get_blockchain_height(&self) -> Result<u32, Error> {
@@ -1071,14 +3048,49 @@ impl BitcoinRpc<Block> for BitcoinClientInner {
             .get_blockchain_info()
             .map_err(|e| Error::BitcoinRpc(format!("Failed to get blockchain info: {:#?}", e)))
             .and_then(|info| {
-                info["blocks"]
-                    .as_u64()
+                info.get("blocks")
+                    .and_then(|blocks| blocks.as_u64())
+                    .and_then(|blocks| u32::try_from(blocks).ok())
+                    .ok_or_else(|| {
+                        Error::BitcoinRpc(
+                            "Missing or invalid field `blocks` in blockchain info".into(),
+                        )
+                    })
+            })
+    }
+
+    fn get_network_info(&self) -> Result<NetworkInfo, Error> {
+        self.client
+            .get_network_info()
+            .map_err(|e| Error::BitcoinRpc(format!("Failed to get network info: {:#?}", e)))
+    }
+
+    fn get_chain(&self) -> Result<String, Error> {
+        self.client
+            .get_blockchain_info()
+            .map_err(|e| Error::BitcoinRpc(format!("Failed to get blockchain info: {:#?}", e)))
+            .and_then(|info| {
+                info.get("chain")
+                    .and_then(|chain| chain.as_str())
+                    .map(String::from)
                     .ok_or_else(|| {
-                        Error::BitcoinRpc("Invalid blocks field in blockchain info".into())
+                        Error::BitcoinRpc(
+                            "Missing or invalid field `chain` in blockchain info".into(),
+                        )
                     })
-                    .map(|h| h as u32)
             })
     }
+
+    fn get_tx_out(
+        &self,
+        txid: &Txid,
+        vout: u32,
+        include_mempool: bool,
+    ) -> Result<Option<TxOut>, Error> {
+        self.client
+            .get_tx_out(txid, vout, include_mempool)
+            .map_err(|e| Error::BitcoinRpc(format!("Failed to get tx out: {:#?}", e)))
+    }
 }
 
 #[cfg(test)]
@@ -1089,15 +3101,33 @@ mod tests {
         absolute::LockTime,
         block::{self, Header},
         transaction::Version,
-        Amount, CompactTarget, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxMerkleNode,
-        TxOut, Txid, Witness,
+        Amount, CompactTarget, OutPoint, PubkeyHash, ScriptBuf, Sequence, Transaction, TxIn,
+        TxMerkleNode, TxOut, Txid, Witness,
     };
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
 
-    use crate::indexer::{
-        test_utils::{test_block_hash, test_h160_hash, test_sha256_hash},
-        types::entry::FromEntry,
+    use crate::{
+        indexer::{
+            block::WitnessItems,
+            config::{AddressNormalizer, Network},
+            database::Database,
+            test_utils::{
+                test_block, test_block_hash, test_config, test_h160_hash, test_rpc_server,
+                test_sha256_hash,
+            },
+            types::{
+                entry::{to_cf_name, FromEntry},
+                pipeline::{PipelineDataInitial, PipelineDataWithBlock, Transition},
+            },
+        },
+        new_test_db,
     };
 
+    #[cfg(feature = "debug-tools")]
+    use crate::indexer::block::BlockDiff;
+
     use super::*;
 
     #[test]
@@ -1142,7 +3172,8 @@ mod tests {
             txdata: vec![tx],
         };
 
-        let entries = block.get_entries(Mode::Indexer, height);
+        let parsed = block.to_block(test_config(), Height(height)).unwrap();
+        let entries = block.get_entries(&test_config(), Height(height), &parsed);
 
         let entry = entries.first().unwrap().to_entry();
         let e = BlockAtHeightHasHash::from_entry(entry).unwrap();
@@ -1168,4 +3199,3042 @@ mod tests {
         );
         assert_eq!(e.height, height);
     }
+
+    #[test]
+    fn test_get_entries_spend_tracker() {
+        let height = 2;
+        let first = test_tx_with_outputs(vec![test_op_return_script(TEST_MESSAGE)], Witness::new());
+        let mut second = test_tx_with_outputs(
+            vec![ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array(
+                test_h160_hash(5),
+            ))],
+            Witness::new(),
+        );
+        second.input.push(second.input[0].clone());
+        second.input[1].previous_output = OutPoint {
+            txid: first.compute_txid(),
+            vout: 3,
+        };
+        let block = test_block(vec![first.clone(), second]);
+        let mut config = test_config();
+        config.mode = Mode::SpendTracker;
+
+        let parsed = block.to_block(config.clone(), Height(height)).unwrap();
+        let entries: Vec<_> = block
+            .get_entries(&config, Height(height), &parsed)
+            .iter()
+            .map(|e| (e.cf_name(), e.to_entry()))
+            .collect();
+        let spent = |txid, vout| {
+            let entry = BlockAtHeightSpentOutputInTx { txid, vout, height };
+            (entry.cf_name(), entry.to_entry())
+        };
+        let hash = BlockAtHeightHasHash {
+            height,
+            hash: block.block_hash().to_byte_array(),
+        };
+        assert_eq!(
+            entries,
+            vec![
+                (hash.cf_name(), hash.to_entry()),
+                spent(test_sha256_hash(9), 0),
+                spent(test_sha256_hash(9), 0),
+                spent(first.compute_txid().to_byte_array(), 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_get_entries_potential_dispenser_at_output() {
+        let p2sh = ScriptBuf::new_p2sh(&bitcoin::ScriptHash::from_byte_array(test_h160_hash(4)));
+        let mut tx = test_tx_with_outputs(
+            vec![test_op_return_script(TEST_MESSAGE), p2sh],
+            Witness::new(),
+        );
+        tx.output[1].value = Amount::from_sat(10_000);
+        let block = test_block(vec![tx.clone()]);
+        let mut config = test_config();
+        let dispensers = |config: &Config, height: u32| -> Vec<PotentialDispenserAtOutput> {
+            let parsed = block.to_block(config.clone(), Height(height)).unwrap();
+            block
+                .get_entries(config, Height(height), &parsed)
+                .iter()
+                .filter(|e| e.cf_name() == to_cf_name::<PotentialDispenserAtOutput>())
+                .map(|e| PotentialDispenserAtOutput::from_entry(e.to_entry()).unwrap())
+                .collect()
+        };
+
+        let height = config.heights.p2sh_dispensers;
+        // Only recorded when asked for.
+        assert!(dispensers(&config, height).is_empty());
+
+        config.potential_dispenser_index = true;
+        assert_eq!(
+            dispensers(&config, height),
+            vec![PotentialDispenserAtOutput {
+                txid: tx.compute_txid().to_byte_array(),
+                vout: 1,
+                destination: b58_encode(&[vec![0x05], test_h160_hash(4).to_vec()].concat()),
+                value: 10_000,
+                height,
+            }]
+        );
+        // Before P2SH dispensers the output has no dispenser destination.
+        assert!(dispensers(&config, height - 1).is_empty());
+    }
+
+    #[test]
+    fn test_get_entries_duplicate_script_hash() {
+        let height = 2;
+        let script_pubkey = ScriptBuf::from_bytes(test_h160_hash(0).to_vec());
+        let tx = test_tx_with_outputs(
+            vec![script_pubkey.clone(), script_pubkey.clone()],
+            Witness::new(),
+        );
+        let block = test_block(vec![tx.clone(), tx]);
+
+        let parsed = block.to_block(test_config(), Height(height)).unwrap();
+        let script_hash_entries: Vec<_> = block
+            .get_entries(&test_config(), Height(height), &parsed)
+            .iter()
+            .filter(|e| e.cf_name() == to_cf_name::<ScriptHashHasOutputsInBlockAtHeight>())
+            .map(|e| ScriptHashHasOutputsInBlockAtHeight::from_entry(e.to_entry()).unwrap())
+            .collect();
+        assert_eq!(
+            script_hash_entries,
+            vec![ScriptHashHasOutputsInBlockAtHeight {
+                script_hash: script_pubkey.script_hash().to_byte_array(),
+                height,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_block_verify_merkle_root() {
+        let tx = test_tx_with_outputs(vec![test_op_return_script(TEST_MESSAGE)], Witness::new());
+        let mut block = test_block(vec![tx]);
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        let mut config = test_config();
+        config.verify_merkle_root = true;
+
+        assert!(parse_block(block.clone(), &config, Height(1), false).is_ok());
+
+        let mut tampered = block.clone();
+        tampered.txdata[0].lock_time = LockTime::from_consensus(1);
+        assert!(matches!(
+            parse_block(tampered.clone(), &config, Height(1), false),
+            Err(Error::MerkleRootMismatch(_))
+        ));
+
+        config.verify_merkle_root = false;
+        assert!(parse_block(tampered, &config, Height(1), false).is_ok());
+    }
+
+    #[test]
+    fn test_parse_block_max_parse_errors_per_block() {
+        let valid = test_tx_with_outputs(vec![test_op_return_script(TEST_MESSAGE)], Witness::new());
+        let mut failing = valid.clone();
+        failing.input.clear();
+        let block = test_block(vec![valid, failing.clone(), failing.clone(), failing]);
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+        config.max_parse_errors_per_block = Some(2);
+
+        assert!(matches!(
+            parse_block(block.clone(), &config, Height(0), true),
+            Err(Error::TooManyParseErrors {
+                height: 0,
+                count: 3
+            })
+        ));
+        // Unparsed vouts are not errors.
+        assert!(parse_block(block.clone(), &config, Height(0), false).is_ok());
+
+        config.max_parse_errors_per_block = Some(3);
+        assert!(parse_block(block.clone(), &config, Height(0), true).is_ok());
+
+        config.max_parse_errors_per_block = None;
+        assert!(parse_block(block, &config, Height(0), true).is_ok());
+    }
+
+    #[test]
+    fn test_pipeline_applies_parse_block_checks() {
+        let parse = |block: &Block, config: &Config| {
+            let data = PipelineDataWithBlock {
+                prev: Box::new(PipelineDataInitial {
+                    height: 5,
+                    target_height: 5,
+                    rollback_height: None,
+                }),
+                hash: block.block_hash(),
+                block: Box::new(block.clone()),
+            };
+            Box::new(data)
+                .transition(config.clone())
+                .map(|((), data)| data.block)
+        };
+        let mut failing =
+            test_tx_with_outputs(vec![test_op_return_script(TEST_MESSAGE)], Witness::new());
+        failing.input.clear();
+        let block = test_block(vec![
+            test_tx_with_outputs(vec![test_op_return_script(TEST_MESSAGE)], Witness::new()),
+            failing,
+        ]);
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+        let parsed = parse(&block, &config).unwrap();
+        assert_eq!(
+            parsed.transactions[0].parsed_vouts.as_ref().unwrap().data,
+            TEST_MESSAGE
+        );
+
+        config.counterparty_start_height = Some(6);
+        let parsed = parse(&block, &config).unwrap();
+        assert!(parsed
+            .transactions
+            .iter()
+            .all(|tx| tx.parsed_vouts.is_err()));
+        config.counterparty_start_height = None;
+
+        config.max_parse_errors_per_block = Some(0);
+        assert!(matches!(
+            parse(&block, &config),
+            Err(Error::TooManyParseErrors {
+                height: 5,
+                count: 1
+            })
+        ));
+        config.max_parse_errors_per_block = None;
+
+        config.verify_merkle_root = true;
+        assert!(matches!(
+            parse(&block, &config),
+            Err(Error::MerkleRootMismatch(_))
+        ));
+    }
+
+    #[cfg(feature = "block-serde")]
+    #[test]
+    fn test_block_bytes_round_trip() {
+        let p2pkh = ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array(test_h160_hash(5)));
+        let txdata = vec![
+            test_tx_with_outputs(vec![test_op_return_script(TEST_MESSAGE)], Witness::new()),
+            test_tx_with_outputs(
+                vec![test_multisig_script(TEST_MESSAGE), p2pkh],
+                Witness::new(),
+            ),
+            test_tx_with_outputs(
+                vec![ScriptBuf::new_op_return(push_bytes(b"CNTRPRTY".to_vec()))],
+                test_reveal_witness(TEST_MESSAGE),
+            ),
+            // Fails output parsing, so `parsed_vouts` holds an error.
+            test_tx_with_outputs(
+                vec![ScriptBuf::new_op_return(push_bytes(vec![0x01; 8]))],
+                Witness::new(),
+            ),
+        ];
+        let block = test_block(txdata);
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+        let parsed = parse_block(block, &config, Height(0), true).unwrap();
+        assert!(parsed.transactions[3].parsed_vouts.is_err());
+
+        let bytes = parsed.to_bytes().unwrap();
+        assert!(CrateBlock::from_bytes(&bytes).unwrap() == parsed);
+        assert!(matches!(
+            CrateBlock::from_bytes(&bytes[..bytes.len() / 2]),
+            Err(Error::Cbor(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_block_streaming() {
+        let checksig =
+            test_tx_with_outputs(vec![test_checksig_script(TEST_MESSAGE)], Witness::new());
+        let block = test_block(vec![
+            test_tx_with_outputs(vec![test_op_return_script(TEST_MESSAGE)], Witness::new()),
+            checksig,
+        ]);
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+
+        for parse_vouts in [false, true] {
+            let parsed = parse_block(block.clone(), &config, Height(0), parse_vouts).unwrap();
+            let streamed: Vec<_> =
+                parse_block_streaming(block.clone(), &config, Height(0), parse_vouts)
+                    .unwrap()
+                    .collect();
+            assert_eq!(streamed.len(), 2);
+            assert!(streamed == parsed.transactions);
+        }
+    }
+
+    #[test]
+    fn test_parse_block_verbose() {
+        let bad_op_return = ScriptBuf::new_op_return(push_bytes(vec![0x01; 8]));
+        let block = test_block(vec![
+            test_tx_with_outputs(vec![test_op_return_script(TEST_MESSAGE)], Witness::new()),
+            test_tx_with_outputs(vec![bad_op_return.clone(), bad_op_return], Witness::new()),
+        ]);
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+
+        let (parsed, warnings) = parse_block_verbose(block.clone(), &config, Height(0)).unwrap();
+        let txid = block.txdata[1].compute_txid().to_string();
+        assert_eq!(
+            warnings
+                .iter()
+                .map(|w| (w.txid.as_str(), w.vout))
+                .collect::<Vec<_>>(),
+            vec![(txid.as_str(), 0), (txid.as_str(), 1)]
+        );
+        assert!(warnings
+            .iter()
+            .all(|w| w.reason.contains("invalid OP_RETURN")));
+        assert!(parsed == parse_block(block, &config, Height(0), true).unwrap());
+    }
+
+    #[test]
+    fn test_parse_block_filtered() {
+        let txdata: Vec<_> = (1..=3)
+            .map(|i| {
+                let destination = ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array([i; 20]));
+                test_tx_with_outputs(
+                    vec![destination, test_op_return_script(TEST_MESSAGE)],
+                    Witness::new(),
+                )
+            })
+            .collect();
+        let block = test_block(txdata);
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+
+        let watched = parse_block(block.clone(), &config, Height(0), true)
+            .unwrap()
+            .transactions[1]
+            .clone();
+        let addresses = watched
+            .parsed_vouts
+            .as_ref()
+            .unwrap()
+            .destinations
+            .iter()
+            .cloned()
+            .collect();
+
+        let filtered = parse_block_filtered(block.clone(), &config, Height(0), &addresses).unwrap();
+        assert_eq!(filtered.transaction_count, 3);
+        assert_eq!(filtered.transactions.len(), 1);
+        assert_eq!(filtered.transactions[0].tx_id, watched.tx_id);
+
+        let unwatched = HashSet::from(["1BitcoinEaterAddressDontSendf59kuE".to_string()]);
+        let filtered = parse_block_filtered(block, &config, Height(0), &unwatched).unwrap();
+        assert!(filtered.transactions.is_empty());
+    }
+
+    #[test]
+    fn test_parse_block_counterparty_start_height() {
+        let block = test_block(vec![test_tx_with_outputs(
+            vec![test_op_return_script(TEST_MESSAGE)],
+            Witness::new(),
+        )]);
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+        config.counterparty_start_height = Some(10);
+
+        let parsed = parse_block(block.clone(), &config, Height(9), true).unwrap();
+        assert_eq!(
+            parsed.transactions[0].parsed_vouts.as_ref().err().unwrap(),
+            "Not Parsed"
+        );
+
+        for height in [Height(10), Height(11)] {
+            let parsed = parse_block(block.clone(), &config, height, true).unwrap();
+            assert_eq!(
+                parsed.transactions[0].parsed_vouts.as_ref().unwrap().data,
+                TEST_MESSAGE
+            );
+        }
+    }
+
+    #[test]
+    fn test_request_queue_policy() {
+        let mut config = test_config();
+        let saturated_client = |config: &Config| {
+            let client = BitcoinClient::new(config, Stopper::new(), 1).unwrap();
+            // No workers are running, so this request fills the queue for good.
+            let (sender, _) = bounded(1);
+            client
+                .channels
+                .get_block_hash
+                .0
+                .send(GetBlockHash { height: 0, sender })
+                .unwrap();
+            client
+        };
+
+        config.request_queue_policy = RequestQueuePolicy::FailFast;
+        let client = saturated_client(&config);
+        assert!(matches!(client.get_block_hash(1), Err(Error::Busy)));
+
+        config.request_queue_policy = RequestQueuePolicy::Timeout(Duration::from_millis(20));
+        let client = saturated_client(&config);
+        let start = Instant::now();
+        assert!(matches!(client.get_block_hash(1), Err(Error::Busy)));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        config.request_queue_policy = RequestQueuePolicy::Block;
+        let client = saturated_client(&config);
+        let requests = client.channels.get_block_hash.1.clone();
+        let handle = std::thread::spawn(move || client.get_block_hash(1));
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(!handle.is_finished());
+        requests.recv().unwrap();
+        let GetBlockHash { height, sender } = requests.recv().unwrap();
+        assert_eq!(height, 1);
+        sender.send(Ok(test_block_hash(1))).unwrap();
+        assert_eq!(handle.join().unwrap().unwrap(), test_block_hash(1));
+    }
+
+    #[test]
+    fn test_verify_network_matches() {
+        let server = test_rpc_server(|request| {
+            assert_eq!(request["method"], "getblockchaininfo");
+            json!({"result": {"chain": "main", "blocks": 900000}, "error": null, "id": 0})
+        });
+        let mut config = test_config();
+        config.rpc_addresses = vec![server.url.clone()];
+        let stopper = Stopper::new();
+        let client = BitcoinClient::new(&config, stopper.clone(), 1).unwrap();
+        let handles = client.start().unwrap();
+
+        assert!(client.verify_network_matches(&config).is_ok());
+
+        config.network = Network::Testnet4;
+        assert!(matches!(
+            client.verify_network_matches(&config),
+            Err(Error::NetworkMismatch { expected, actual })
+                if expected == "testnet4" && actual == "main"
+        ));
+
+        stopper.stop().unwrap();
+        for handle in handles {
+            handle.join().unwrap().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_fetch_and_parse() {
+        let p2pkh = ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array(test_h160_hash(5)));
+        let mut block = test_block(vec![test_tx_with_outputs(vec![p2pkh], Witness::new())]);
+        block.header.prev_blockhash = test_block_hash(6);
+        let hash = block.block_hash();
+        let block_hex = serialize(&block).to_lower_hex_string();
+        let server = test_rpc_server(move |request| {
+            let result = match request["method"].as_str() {
+                Some("getblockhash") => {
+                    assert_eq!(request["params"][0], 7);
+                    json!(hash.to_string())
+                }
+                Some("getblock") => {
+                    assert_eq!(request["params"][0], hash.to_string());
+                    json!(block_hex)
+                }
+                _ => json!(null),
+            };
+            json!({"result": result, "error": null, "id": 0})
+        });
+        let mut config = test_config();
+        config.rpc_addresses = vec![server.url.clone()];
+        config.enable_all_protocol_changes = true;
+        let stopper = Stopper::new();
+        let client = BitcoinClient::new(&config, stopper.clone(), 1).unwrap();
+        let handles = client.start().unwrap();
+
+        let parsed = client.fetch_and_parse(Height(7), &config, true).unwrap();
+        assert_eq!(parsed.height, 7);
+        assert_eq!(parsed.block_hash, hash.to_string());
+        assert_eq!(parsed.transactions.len(), 1);
+        assert_eq!(
+            parsed.transactions[0]
+                .parsed_vouts
+                .as_ref()
+                .unwrap()
+                .destinations
+                .len(),
+            1
+        );
+        assert_eq!(server.requests(), 2);
+
+        stopper.stop().unwrap();
+        for handle in handles {
+            handle.join().unwrap().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_fetch_and_parse_by_hash() {
+        let p2pkh = ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array(test_h160_hash(5)));
+        let coinbase = Transaction {
+            version: Version::ONE,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::builder().push_int(1000).into_script(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(5_000_000_000),
+                script_pubkey: p2pkh,
+            }],
+        };
+        let blocks: Vec<_> = [block::Version::TWO, block::Version::ONE]
+            .into_iter()
+            .map(|version| {
+                let mut block = test_block(vec![coinbase.clone()]);
+                block.header.version = version;
+                block.header.prev_blockhash = test_block_hash(6);
+                block
+            })
+            .collect();
+        let (bip34, legacy) = (blocks[0].block_hash(), blocks[1].block_hash());
+        let server = test_rpc_server(move |request| {
+            let block = blocks
+                .iter()
+                .find(|block| request["params"][0] == block.block_hash().to_string());
+            let result = match (request["method"].as_str(), block) {
+                (Some("getblock"), Some(block)) => json!(serialize(block).to_lower_hex_string()),
+                _ => json!(null),
+            };
+            json!({"result": result, "error": null, "id": 0})
+        });
+        let mut config = test_config();
+        config.rpc_addresses = vec![server.url.clone()];
+        config.enable_all_protocol_changes = true;
+        let stopper = Stopper::new();
+        let client = BitcoinClient::new(&config, stopper.clone(), 1).unwrap();
+        let handles = client.start().unwrap();
+
+        let parsed = client
+            .fetch_and_parse_by_hash(&bip34, None, &config, true)
+            .unwrap();
+        assert_eq!(parsed.height, 1000);
+        assert_eq!(parsed.block_hash, bip34.to_string());
+        assert!(parsed.transactions[0].parsed_vouts.is_ok());
+
+        // A supplied height wins over the coinbase's.
+        let parsed = client
+            .fetch_and_parse_by_hash(&bip34, Some(Height(7)), &config, true)
+            .unwrap();
+        assert_eq!(parsed.height, 7);
+
+        // A version 1 block carries no height.
+        assert!(matches!(
+            client.fetch_and_parse_by_hash(&legacy, None, &config, true),
+            Err(Error::UnknownHeight(hash)) if hash == legacy.to_string()
+        ));
+        let parsed = client
+            .fetch_and_parse_by_hash(&legacy, Some(Height(8)), &config, false)
+            .unwrap();
+        assert_eq!(parsed.height, 8);
+
+        stopper.stop().unwrap();
+        for handle in handles {
+            handle.join().unwrap().unwrap();
+        }
+    }
+
+    #[derive(Clone)]
+    struct DelayedRpc {
+        blocks: Arc<Vec<Block>>,
+    }
+
+    impl BitcoinRpc<Block> for DelayedRpc {
+        fn get_block_hash(&self, height: u32) -> Result<BlockHash, Error> {
+            // Later heights complete first.
+            std::thread::sleep(std::time::Duration::from_millis(
+                (self.blocks.len() as u64).saturating_sub(height as u64) * 2,
+            ));
+            self.blocks
+                .get(height as usize)
+                .map(|block| block.block_hash())
+                .ok_or_else(|| Error::UnknownHeight(height.to_string()))
+        }
+
+        fn get_block(&self, hash: &BlockHash) -> Result<Box<Block>, Error> {
+            let block = self.blocks.iter().find(|b| b.block_hash() == *hash);
+            Ok(Box::new(block.unwrap().clone()))
+        }
+
+        fn get_block_header(&self, hash: &BlockHash) -> Result<Header, Error> {
+            Ok(self.get_block(hash)?.header)
+        }
+
+        fn get_blockchain_height(&self) -> Result<u32, Error> {
+            Ok(self.blocks.len() as u32 - 1)
+        }
+
+        fn get_network_info(&self) -> Result<NetworkInfo, Error> {
+            Err(Error::BitcoinRpc("getnetworkinfo not mocked".into()))
+        }
+
+        fn get_chain(&self) -> Result<String, Error> {
+            Err(Error::BitcoinRpc("getblockchaininfo not mocked".into()))
+        }
+
+        fn get_tx_out(&self, _: &Txid, _: u32, _: bool) -> Result<Option<TxOut>, Error> {
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn test_ordered_blocks_in_height_order() {
+        let blocks = (0..20)
+            .map(|nonce| {
+                let mut block = test_block(vec![]);
+                block.header.nonce = nonce;
+                block
+            })
+            .collect::<Vec<_>>();
+        let rpc = DelayedRpc {
+            blocks: Arc::new(blocks.clone()),
+        };
+        let config = test_config();
+
+        let parsed = OrderedBlocks::new(rpc, Stopper::new(), &config, 0..=19, 4, 8)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(parsed.len(), 20);
+        for (height, (parsed, block)) in parsed.iter().zip(&blocks).enumerate() {
+            assert_eq!(parsed.height, height as u32);
+            assert_eq!(parsed.block_hash, block.block_hash().to_string());
+        }
+    }
+
+    #[test]
+    fn test_ordered_blocks_stopped() {
+        let rpc = DelayedRpc {
+            blocks: Arc::new(vec![]),
+        };
+        let stopper = Stopper::new();
+        stopper.stop().unwrap();
+
+        assert!(matches!(
+            OrderedBlocks::new(rpc, stopper, &test_config(), 0..=19, 4, 8),
+            Err(Error::Stopped)
+        ));
+    }
+
+    #[test]
+    fn test_ordered_blocks_error_ends_range() {
+        let blocks = (0..5)
+            .map(|nonce| {
+                let mut block = test_block(vec![]);
+                block.header.nonce = nonce;
+                block
+            })
+            .collect::<Vec<_>>();
+        let rpc = DelayedRpc {
+            blocks: Arc::new(blocks),
+        };
+        let stopper = Stopper::new();
+
+        let mut parsed = OrderedBlocks::new(rpc, stopper.clone(), &test_config(), 0..=9, 4, 8)
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert!(matches!(parsed.pop(), Some(Err(Error::UnknownHeight(_)))));
+        assert!(parsed.iter().all(Result::is_ok));
+        assert!(!stopper.stopped().unwrap());
+    }
+
+    #[test]
+    fn test_get_block_cache_hit() {
+        let block = test_block(vec![]);
+        let block_hex = serialize(&block).to_lower_hex_string();
+        let server = test_rpc_server(move |_| json!({"result": block_hex, "error": null, "id": 0}));
+        let mut config = test_config();
+        config.rpc_addresses = vec![server.url.clone()];
+        let cache = Arc::new(Mutex::new(BlockCache::new(NonZeroUsize::MIN)));
+        let client = BitcoinClientInner::new(&config, Some(cache.clone())).unwrap();
+
+        let hash = block.block_hash();
+        assert_eq!(client.get_block(&hash).unwrap().block_hash(), hash);
+        assert_eq!(client.get_block(&hash).unwrap().block_hash(), hash);
+        assert_eq!(server.requests(), 1);
+
+        let stats = cache.lock().unwrap().stats();
+        assert_eq!((stats.hits, stats.misses, stats.len), (1, 1, 1));
+    }
+
+    #[test]
+    fn test_get_block_hash_mismatch() {
+        let block = test_block(vec![]);
+        let block_hex = serialize(&block).to_lower_hex_string();
+        let server = test_rpc_server(move |_| json!({"result": block_hex, "error": null, "id": 0}));
+        let mut config = test_config();
+        config.rpc_addresses = vec![server.url.clone()];
+        let cache = Arc::new(Mutex::new(BlockCache::new(NonZeroUsize::MIN)));
+        let client = BitcoinClientInner::new(&config, Some(cache.clone())).unwrap();
+
+        let requested = test_block_hash(2);
+        assert!(matches!(
+            client.get_block(&requested),
+            Err(Error::BlockHashMismatch { requested: r, got })
+                if r == requested.to_string() && got == block.block_hash().to_string()
+        ));
+        // The wrong block is not cached under either hash.
+        assert_eq!(cache.lock().unwrap().stats().len, 0);
+    }
+
+    #[test]
+    fn test_blockchain_info_missing_fields() {
+        let server = test_rpc_server(|_| json!({"result": {}, "error": null, "id": 0}));
+        let mut config = test_config();
+        config.rpc_addresses = vec![server.url.clone()];
+        let client = BitcoinClientInner::new(&config, None).unwrap();
+
+        assert!(matches!(
+            client.get_blockchain_height(),
+            Err(Error::BitcoinRpc(message)) if message.contains("`blocks`")
+        ));
+        assert!(matches!(
+            client.get_chain(),
+            Err(Error::BitcoinRpc(message)) if message.contains("`chain`")
+        ));
+
+        let server = test_rpc_server(
+            |_| json!({"result": null, "error": {"code": -28, "message": "Loading block index"}, "id": 0}),
+        );
+        config.rpc_addresses = vec![server.url.clone()];
+        let client = BitcoinClientInner::new(&config, None).unwrap();
+
+        assert!(matches!(
+            client.get_blockchain_height(),
+            Err(Error::BitcoinRpc(message)) if message.contains("Loading block index")
+        ));
+    }
+
+    fn test_witness_program_output(version: u8, program: &[u8]) -> TxOut {
+        let mut script = vec![version, program.len() as u8];
+        script.extend_from_slice(program);
+        TxOut {
+            value: Amount::from_sat(546),
+            script_pubkey: ScriptBuf::from_bytes(script),
+        }
+    }
+
+    #[test]
+    fn test_parse_vout_taproot_program_length() {
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+
+        // OP_PUSHNUM_1 followed by the program push.
+        let p2tr = test_witness_program_output(0x51, &[0x02; 32]);
+        let (output, _) = parse_vout(&config, vec![0; 32], Height(0), "".into(), 0, &p2tr).unwrap();
+        assert!(matches!(output, ParseOutput::Destination(ref d) if d.starts_with("bc1p")));
+
+        let short = test_witness_program_output(0x51, &[0x02; 31]);
+        assert!(matches!(
+            parse_vout(&config, vec![0; 32], Height(0), "".into(), 1, &short),
+            Err(Error::ParseVout(ref e)) if e.contains("Invalid taproot program length")
+        ));
+
+        // Before the flag, other lengths are bech32m destinations, pay-to-anchor included.
+        let mut config = test_config();
+        let height = Height(config.heights.taproot_support);
+        let anchor = test_witness_program_output(0x51, &[0x4e, 0x73]);
+        for vout in [&short, &anchor] {
+            let (output, _) = parse_vout(&config, vec![0; 32], height, "".into(), 1, vout).unwrap();
+            assert!(matches!(output, ParseOutput::Destination(ref d) if d.starts_with("bc1p")));
+        }
+
+        config.heights.taproot_program_length = height.0;
+        assert!(parse_vout(&config, vec![0; 32], height, "".into(), 1, &anchor).is_err());
+    }
+
+    #[test]
+    fn test_parse_vout_segwit_v0_program_lengths() {
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+        let p2wpkh = test_witness_program_output(
+            0,
+            &hex::decode("751e76e8199196d454941c45d1b3a323f1433bd6").unwrap(),
+        );
+        let p2wsh = test_witness_program_output(
+            0,
+            &hex::decode("1863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262")
+                .unwrap(),
+        );
+
+        let (output, _) =
+            parse_vout(&config, vec![0; 32], Height(0), "".into(), 0, &p2wpkh).unwrap();
+        assert_eq!(
+            output,
+            ParseOutput::Destination("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4".into())
+        );
+        let (output, _) =
+            parse_vout(&config, vec![0; 32], Height(0), "".into(), 1, &p2wsh).unwrap();
+        assert_eq!(
+            output,
+            ParseOutput::Destination(
+                "bc1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qccfmv3".into()
+            )
+        );
+
+        let tx = test_tx_with_outputs(
+            vec![p2wpkh.script_pubkey, p2wsh.script_pubkey],
+            Witness::new(),
+        );
+        let parsed = test_parsed_vouts(&tx);
+        assert_eq!(
+            parsed.destination_script_types,
+            vec![ScriptType::P2wpkh, ScriptType::P2wsh]
+        );
+    }
+
+    #[test]
+    fn test_parse_vout_segwit_v0_nonstandard_length() {
+        let mut config = test_config();
+        // After segwit, before taproot support.
+        let height = Height(900_000);
+        let vout = test_witness_program_output(0, &[1; 22]);
+
+        let (output, _) =
+            parse_vout(&config, vec![0; 32], height, "txid".into(), 0, &vout).unwrap();
+        assert!(output.is_destination());
+
+        config.heights.segwit_v0_program_length = height.0;
+        assert!(matches!(
+            parse_vout(&config, vec![0; 32], height, "txid".into(), 0, &vout),
+            Err(Error::ParseVout(message)) if message.contains("Non-standard segwit v0 program length")
+        ));
+        for length in [20, 32] {
+            let vout = test_witness_program_output(0, &vec![1; length]);
+            let (output, _) =
+                parse_vout(&config, vec![0; 32], height, "txid".into(), 0, &vout).unwrap();
+            assert!(output.is_destination());
+        }
+    }
+
+    #[test]
+    fn test_parse_vout_future_witness_version() {
+        let mut config = test_config();
+        config.heights.future_segwit = 100;
+        // OP_2 <32 bytes>
+        let vout = test_witness_program_output(0x52, &test_sha256_hash(1));
+
+        let (output, dispenser) =
+            parse_vout(&config, vec![], Height(100), "txid".into(), 0, &vout).unwrap();
+        let expected = bitcoin::Address::from_witness_program(
+            bitcoin::WitnessProgram::new(bitcoin::WitnessVersion::V2, &test_sha256_hash(1))
+                .unwrap(),
+            bitcoin::Network::Bitcoin,
+        )
+        .to_string();
+        assert!(expected.starts_with("bc1z"));
+        assert!(matches!(output, ParseOutput::Destination(ref d) if *d == expected));
+        let dispenser = dispenser.unwrap();
+        assert_eq!(dispenser.destination, Some(expected));
+        assert_eq!(dispenser.value, Some(546));
+
+        assert!(parse_vout(&config, vec![], Height(99), "txid".into(), 0, &vout).is_err());
+    }
+
+    #[test]
+    fn test_parse_vout_future_witness_version_invalid_program() {
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+        // OP_2 with a 1-byte program is not a witness program
+        let vout = test_witness_program_output(0x52, &[1]);
+        assert!(parse_vout(&config, vec![], Height(0), "txid".into(), 0, &vout).is_err());
+    }
+
+    const TEST_MESSAGE: &[u8] = b"hello";
+
+    fn test_key() -> Vec<u8> {
+        test_sha256_hash(9).to_vec()
+    }
+
+    fn test_tx_with_outputs(outputs: Vec<ScriptBuf>, witness: Witness) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: Txid::from_byte_array(test_sha256_hash(9)),
+                    vout: 0,
+                },
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness,
+            }],
+            output: outputs
+                .into_iter()
+                .map(|script_pubkey| TxOut {
+                    value: Amount::from_sat(546),
+                    script_pubkey,
+                })
+                .collect(),
+        }
+    }
+
+    fn push_bytes(bytes: Vec<u8>) -> bitcoin::script::PushBytesBuf {
+        bitcoin::script::PushBytesBuf::try_from(bytes).unwrap()
+    }
+
+    fn test_op_return_script(message: &[u8]) -> ScriptBuf {
+        let plaintext = [b"CNTRPRTY".as_slice(), message].concat();
+        ScriptBuf::new_op_return(push_bytes(arc4_decrypt(&test_key(), &plaintext)))
+    }
+
+    fn test_checksig_script(message: &[u8]) -> ScriptBuf {
+        let mut plaintext = vec![(8 + message.len()) as u8];
+        plaintext.extend_from_slice(b"CNTRPRTY");
+        plaintext.extend_from_slice(message);
+        plaintext.resize(20, 0);
+        test_checksig_output(&plaintext).script_pubkey
+    }
+
+    fn test_checksig_output(plaintext: &[u8]) -> TxOut {
+        TxOut {
+            value: Amount::from_sat(546),
+            script_pubkey: ScriptBuf::builder()
+                .push_opcode(bitcoin::opcodes::all::OP_DUP)
+                .push_opcode(OP_HASH160)
+                .push_slice(push_bytes(arc4_decrypt(&test_key(), plaintext)))
+                .push_opcode(bitcoin::opcodes::all::OP_EQUALVERIFY)
+                .push_opcode(OP_CHECKSIG)
+                .into_script(),
+        }
+    }
+
+    #[test]
+    fn test_checksig_declared_length_clamped() {
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+        let parse = |plaintext: &[u8]| {
+            parse_vout(
+                &config,
+                test_key(),
+                Height(0),
+                "test".into(),
+                0,
+                &test_checksig_output(plaintext),
+            )
+            .unwrap()
+            .0
+        };
+
+        // The length byte claims 200 bytes of a 20 byte payload.
+        let mut plaintext = vec![200];
+        plaintext.extend_from_slice(b"CNTRPRTY");
+        plaintext.extend_from_slice(TEST_MESSAGE);
+        plaintext.resize(20, 0);
+        let mut expected = TEST_MESSAGE.to_vec();
+        expected.resize(11, 0);
+        assert!(matches!(parse(&plaintext), ParseOutput::Data(data, _) if data == expected));
+
+        // The length byte does not cover the prefix.
+        plaintext[0] = 3;
+        assert!(matches!(parse(&plaintext), ParseOutput::Data(data, _) if data.is_empty()));
+    }
+
+    // 1-of-3 multisig where the first two pubkeys carry 31 bytes of data each.
+    fn test_multisig_script(message: &[u8]) -> ScriptBuf {
+        let mut plaintext = vec![(8 + message.len()) as u8];
+        plaintext.extend_from_slice(b"CNTRPRTY");
+        plaintext.extend_from_slice(message);
+        plaintext.resize(62, 0);
+        let encrypted = arc4_decrypt(&test_key(), &plaintext);
+        let pubkey = |chunk: &[u8]| {
+            let mut pk = vec![0x02];
+            pk.extend_from_slice(chunk);
+            pk.push(0x00);
+            push_bytes(pk)
+        };
+        ScriptBuf::builder()
+            .push_opcode(OP_PUSHNUM_1)
+            .push_slice(pubkey(&encrypted[..31]))
+            .push_slice(pubkey(&encrypted[31..]))
+            .push_slice(pubkey(&[0x03; 31]))
+            .push_opcode(OP_PUSHNUM_3)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script()
+    }
+
+    // 1-of-4 multisig where the first three pubkeys carry 31 bytes of data each.
+    fn test_extended_multisig_script(message: &[u8]) -> ScriptBuf {
+        let mut plaintext = vec![(8 + message.len()) as u8];
+        plaintext.extend_from_slice(b"CNTRPRTY");
+        plaintext.extend_from_slice(message);
+        plaintext.resize(93, 0);
+        let encrypted = arc4_decrypt(&test_key(), &plaintext);
+        let pubkey = |chunk: &[u8]| {
+            let mut pk = vec![0x02];
+            pk.extend_from_slice(chunk);
+            pk.push(0x00);
+            push_bytes(pk)
+        };
+        ScriptBuf::builder()
+            .push_opcode(OP_PUSHNUM_1)
+            .push_slice(pubkey(&encrypted[..31]))
+            .push_slice(pubkey(&encrypted[31..62]))
+            .push_slice(pubkey(&encrypted[62..]))
+            .push_slice(pubkey(&[0x03; 31]))
+            .push_opcode(bitcoin::opcodes::all::OP_PUSHNUM_4)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script()
+    }
+
+    fn test_envelope_script(message: &[u8]) -> ScriptBuf {
+        ScriptBuf::builder()
+            .push_opcode(bitcoin::opcodes::OP_FALSE)
+            .push_opcode(bitcoin::opcodes::all::OP_IF)
+            .push_slice(push_bytes(message.to_vec()))
+            .push_opcode(bitcoin::opcodes::all::OP_ENDIF)
+            .push_slice(test_sha256_hash(7))
+            .push_opcode(OP_CHECKSIG)
+            .into_script()
+    }
+
+    // An ord envelope tagged for counterparty, with content type `mime`, then `tag` (5 for
+    // metadata, empty for the body) and `chunks`, one push each.
+    fn test_ord_script(mime: &[u8], tag: &[u8], chunks: &[&[u8]]) -> ScriptBuf {
+        let mut builder = ScriptBuf::builder()
+            .push_opcode(bitcoin::opcodes::OP_FALSE)
+            .push_opcode(bitcoin::opcodes::all::OP_IF)
+            .push_slice(b"ord")
+            .push_slice([7])
+            .push_slice(b"xcp")
+            .push_slice([1])
+            .push_slice(push_bytes(mime.to_vec()))
+            .push_slice(push_bytes(tag.to_vec()));
+        for chunk in chunks {
+            builder = builder.push_slice(push_bytes(chunk.to_vec()));
+        }
+        builder
+            .push_opcode(bitcoin::opcodes::all::OP_ENDIF)
+            .push_slice(test_sha256_hash(7))
+            .push_opcode(OP_CHECKSIG)
+            .into_script()
+    }
+
+    #[test]
+    fn test_extract_data_from_witness_raw_envelope() {
+        let script = test_envelope_script(TEST_MESSAGE);
+        assert_eq!(
+            extract_data_from_witness(&script, EnvelopeRules::latest()).unwrap(),
+            TEST_MESSAGE
+        );
+    }
+
+    #[test]
+    fn test_extract_data_from_witness_body_separator() {
+        let script = ScriptBuf::builder()
+            .push_opcode(bitcoin::opcodes::OP_FALSE)
+            .push_opcode(bitcoin::opcodes::all::OP_IF)
+            .push_slice([1])
+            .push_slice(b"text/plain")
+            .push_opcode(bitcoin::opcodes::OP_0)
+            .push_slice(push_bytes(TEST_MESSAGE[..4].to_vec()))
+            .push_slice(push_bytes(TEST_MESSAGE[4..].to_vec()))
+            .push_opcode(bitcoin::opcodes::all::OP_ENDIF)
+            .push_slice(test_sha256_hash(7))
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        assert_eq!(
+            extract_data_from_witness(&script, EnvelopeRules::latest()).unwrap(),
+            TEST_MESSAGE
+        );
+
+        // Before activation the tag pushes are read as data too.
+        let mut config = test_config();
+        let height = Height(900_000);
+        config.heights.matched_envelope_endif = 0;
+        config.heights.envelope_body_separator = height.0;
+        let rules = EnvelopeRules::new(&config, Height(height.0 - 1));
+        assert_eq!(
+            extract_data_from_witness(&script, rules).unwrap(),
+            [&[1][..], b"text/plain", TEST_MESSAGE].concat()
+        );
+        let rules = EnvelopeRules::new(&config, height);
+        assert_eq!(
+            extract_data_from_witness(&script, rules).unwrap(),
+            TEST_MESSAGE
+        );
+
+        // A pre-activation reveal carrying such an envelope.
+        let witness = Witness::from_slice(&[vec![0x01; 64], script.to_bytes(), vec![0xc0; 33]]);
+        let marker = ScriptBuf::new_op_return(push_bytes(b"CNTRPRTY".to_vec()));
+        let tx = test_tx_with_outputs(vec![marker], witness);
+        config.heights.taproot_support = 0;
+        let parsed = parse_transaction(&tx, &config, Height(height.0 - 1), true)
+            .parsed_vouts
+            .unwrap();
+        assert!(parsed.is_reveal_tx);
+        assert_eq!(
+            parsed.data,
+            [&[1][..], b"text/plain", TEST_MESSAGE].concat()
+        );
+    }
+
+    #[test]
+    fn test_extract_all_inscriptions_batch() {
+        let script = ScriptBuf::builder()
+            .push_opcode(bitcoin::opcodes::OP_FALSE)
+            .push_opcode(bitcoin::opcodes::all::OP_IF)
+            .push_slice(push_bytes(TEST_MESSAGE[..4].to_vec()))
+            .push_opcode(bitcoin::opcodes::all::OP_ENDIF)
+            .push_opcode(bitcoin::opcodes::OP_FALSE)
+            .push_opcode(bitcoin::opcodes::all::OP_IF)
+            .push_slice(push_bytes(TEST_MESSAGE[4..].to_vec()))
+            .push_opcode(bitcoin::opcodes::all::OP_ENDIF)
+            .push_slice(test_sha256_hash(7))
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        assert_eq!(
+            extract_all_inscriptions(&script),
+            vec![TEST_MESSAGE[..4].to_vec(), TEST_MESSAGE[4..].to_vec()]
+        );
+        assert_eq!(
+            extract_data_from_witness(&script, EnvelopeRules::latest()).unwrap(),
+            TEST_MESSAGE[..4]
+        );
+
+        let single = test_envelope_script(TEST_MESSAGE);
+        assert_eq!(
+            extract_all_inscriptions(&single),
+            vec![TEST_MESSAGE.to_vec()]
+        );
+
+        // Before activation the envelopes are merged into one body.
+        let mut config = test_config();
+        let height = Height(900_000);
+        config.heights.matched_envelope_endif = 0;
+        config.heights.batch_inscriptions = height.0;
+        let rules = EnvelopeRules::new(&config, Height(height.0 - 1));
+        assert_eq!(
+            extract_data_from_witness(&script, rules).unwrap(),
+            TEST_MESSAGE
+        );
+        let rules = EnvelopeRules::new(&config, height);
+        assert_eq!(
+            extract_data_from_witness(&script, rules).unwrap(),
+            TEST_MESSAGE[..4]
+        );
+
+        // Likewise with the fixed-offset envelope boundaries.
+        config.heights.matched_envelope_endif = u32::MAX;
+        let rules = EnvelopeRules::new(&config, Height(height.0 - 1));
+        assert_eq!(
+            extract_data_from_witness(&script, rules).unwrap(),
+            TEST_MESSAGE
+        );
+        let rules = EnvelopeRules::new(&config, height);
+        assert_eq!(
+            extract_data_from_witness(&script, rules).unwrap(),
+            TEST_MESSAGE[..4]
+        );
+    }
+
+    #[test]
+    fn test_extract_data_from_witness_trailing_opcodes() {
+        let script = ScriptBuf::builder()
+            .push_opcode(bitcoin::opcodes::OP_FALSE)
+            .push_opcode(bitcoin::opcodes::all::OP_IF)
+            .push_slice(push_bytes(TEST_MESSAGE.to_vec()))
+            .push_opcode(bitcoin::opcodes::all::OP_ENDIF)
+            .push_slice(test_sha256_hash(7))
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(bitcoin::opcodes::all::OP_NOP)
+            .push_opcode(bitcoin::opcodes::all::OP_NOP)
+            .into_script();
+        assert_eq!(
+            extract_data_from_witness(&script, EnvelopeRules::latest()).unwrap(),
+            TEST_MESSAGE
+        );
+        assert_eq!(
+            extract_all_inscriptions(&script),
+            vec![TEST_MESSAGE.to_vec()]
+        );
+
+        // No signature check after the envelope.
+        let script = ScriptBuf::builder()
+            .push_opcode(bitcoin::opcodes::OP_FALSE)
+            .push_opcode(bitcoin::opcodes::all::OP_IF)
+            .push_slice(push_bytes(TEST_MESSAGE.to_vec()))
+            .push_opcode(bitcoin::opcodes::all::OP_ENDIF)
+            .push_slice(test_sha256_hash(7))
+            .push_opcode(bitcoin::opcodes::all::OP_NOP)
+            .into_script();
+        assert!(extract_data_from_witness(&script, EnvelopeRules::latest()).is_err());
+    }
+
+    #[test]
+    fn test_extract_data_from_witness_trailing_opcodes_activation() {
+        let script = ScriptBuf::builder()
+            .push_opcode(bitcoin::opcodes::OP_FALSE)
+            .push_opcode(bitcoin::opcodes::all::OP_IF)
+            .push_slice(push_bytes(TEST_MESSAGE.to_vec()))
+            .push_opcode(bitcoin::opcodes::all::OP_ENDIF)
+            .push_slice(test_sha256_hash(7))
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(bitcoin::opcodes::all::OP_NOP)
+            .into_script();
+        let mut config = test_config();
+        let height = Height(900_000);
+        config.heights.matched_envelope_endif = height.0;
+
+        // Before activation the signature check has to be the last instruction.
+        let rules = EnvelopeRules::new(&config, Height(height.0 - 1));
+        assert!(extract_data_from_witness(&script, rules).is_err());
+        let rules = EnvelopeRules::new(&config, height);
+        assert_eq!(
+            extract_data_from_witness(&script, rules).unwrap(),
+            TEST_MESSAGE
+        );
+
+        // A plain reveal reads the same on both sides.
+        let script = test_envelope_script(TEST_MESSAGE);
+        for height in [Height(height.0 - 1), height] {
+            let rules = EnvelopeRules::new(&config, height);
+            assert_eq!(
+                extract_data_from_witness(&script, rules).unwrap(),
+                TEST_MESSAGE
+            );
+        }
+
+        // OP_IFDUP opened an envelope before activation.
+        let script = ScriptBuf::builder()
+            .push_opcode(bitcoin::opcodes::OP_FALSE)
+            .push_opcode(bitcoin::opcodes::all::OP_IFDUP)
+            .push_slice(push_bytes(TEST_MESSAGE.to_vec()))
+            .push_opcode(bitcoin::opcodes::all::OP_ENDIF)
+            .push_slice(test_sha256_hash(7))
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        let rules = EnvelopeRules::new(&config, Height(height.0 - 1));
+        assert_eq!(
+            extract_data_from_witness(&script, rules).unwrap(),
+            TEST_MESSAGE
+        );
+        let rules = EnvelopeRules::new(&config, height);
+        assert!(extract_data_from_witness(&script, rules).is_err());
+    }
+
+    fn test_reveal_witness(message: &[u8]) -> Witness {
+        let script = test_envelope_script(message);
+        Witness::from_slice(&[vec![0x01; 64], script.to_bytes(), vec![0xc0; 33]])
+    }
+
+    #[test]
+    fn test_witness_for_input_p2tr_script_path() {
+        let tx = test_tx_with_outputs(
+            vec![test_op_return_script(TEST_MESSAGE)],
+            test_reveal_witness(TEST_MESSAGE),
+        );
+        let parsed = parse_transaction(&tx, &test_config(), Height(0), false);
+
+        assert_eq!(
+            parsed.witness_for_input(0),
+            Some(WitnessItems::P2trScriptPath {
+                items: vec![vec![0x01; 64]],
+                script: test_envelope_script(TEST_MESSAGE).to_bytes(),
+                control_block: vec![0xc0; 33],
+                annex: None,
+            })
+        );
+        assert_eq!(parsed.witness_for_input(1), None);
+    }
+
+    #[test]
+    fn test_witness_for_input_p2wpkh() {
+        let signature = [vec![0x30], vec![0x01; 70]].concat();
+        let pubkey = [vec![0x02], test_sha256_hash(3).to_vec()].concat();
+        let tx = test_tx_with_outputs(
+            vec![test_op_return_script(TEST_MESSAGE)],
+            Witness::from_slice(&[signature.clone(), pubkey.clone()]),
+        );
+        let parsed = parse_transaction(&tx, &test_config(), Height(0), false);
+
+        assert_eq!(
+            parsed.witness_for_input(0),
+            Some(WitnessItems::P2wpkh { signature, pubkey })
+        );
+        assert_eq!(parsed.vtxinwit[0].len(), 2);
+    }
+
+    #[test]
+    fn test_get_entries_reveal_commits_to_tx() {
+        let height = 3;
+        let marker = ScriptBuf::new_op_return(push_bytes(b"CNTRPRTY".to_vec()));
+        let reveal = test_tx_with_outputs(vec![marker], test_reveal_witness(TEST_MESSAGE));
+        // An inscription without the marker output isn't a Counterparty reveal.
+        let inscription = test_tx_with_outputs(
+            vec![test_op_return_script(TEST_MESSAGE)],
+            test_reveal_witness(TEST_MESSAGE),
+        );
+        let plain = test_tx_with_outputs(vec![test_op_return_script(TEST_MESSAGE)], Witness::new());
+        let mut block = test_block(vec![reveal.clone(), inscription, plain]);
+        block.header.prev_blockhash = test_block_hash(2);
+        block.header.merkle_root = TxMerkleNode::from_raw_hash(
+            sha256d::Hash::from_slice(&test_sha256_hash(height)).unwrap(),
+        );
+        let links = |config: &Config| -> Vec<_> {
+            let parsed = block.to_block(config.clone(), Height(height)).unwrap();
+            block
+                .get_entries(config, Height(height), &parsed)
+                .iter()
+                .filter(|e| e.cf_name() == "reveal_commits_to_tx_in_block_at_height")
+                .map(|e| RevealCommitsToTxInBlockAtHeight::from_entry(e.to_entry()).unwrap())
+                .collect()
+        };
+
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+        assert_eq!(
+            links(&config),
+            vec![RevealCommitsToTxInBlockAtHeight {
+                reveal_txid: reveal.compute_txid().to_byte_array(),
+                commit_txid: test_sha256_hash(9),
+                height,
+            }]
+        );
+        // Before taproot support there are no reveals.
+        assert_eq!(links(&test_config()), vec![]);
+    }
+
+    #[test]
+    fn test_taproot_reveal_with_annex() {
+        let mut witness = test_reveal_witness(TEST_MESSAGE).to_vec();
+        witness.push(vec![0x50, 0x01, 0x02]);
+        let marker = ScriptBuf::new_op_return(push_bytes(b"CNTRPRTY".to_vec()));
+        let tx = test_tx_with_outputs(vec![marker], Witness::from_slice(&witness));
+
+        let parsed = test_parsed_vouts(&tx);
+        assert!(parsed.is_reveal_tx);
+        assert_eq!(parsed.data, TEST_MESSAGE);
+        assert_eq!(parsed.data_source, Some(DataSource::TaprootReveal));
+
+        // Without the annex activation the four-item witness is not a reveal.
+        let config = test_config();
+        let parsed = parse_transaction(&tx, &config, Height(902000), true)
+            .parsed_vouts
+            .unwrap();
+        assert!(!parsed.is_reveal_tx);
+    }
+
+    // Leaf version 0xc0 with odd parity, the secp256k1 generator as internal key and a
+    // single merkle path node.
+    const TEST_CONTROL_BLOCK: &str =
+        "c179be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798\
+        0707070707070707070707070707070707070707070707070707070707070707";
+
+    #[test]
+    fn test_parse_taproot_control_block() {
+        let control_block =
+            parse_taproot_control_block(&hex::decode(TEST_CONTROL_BLOCK).unwrap()).unwrap();
+        assert_eq!(control_block.leaf_version, 0xc0);
+        assert_eq!(
+            hex::encode(control_block.internal_key),
+            "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"
+        );
+        assert_eq!(control_block.merkle_path, vec![[7u8; 32]]);
+
+        assert!(matches!(
+            parse_taproot_control_block(&[0xc0; 32]),
+            Err(Error::ParseVout(_))
+        ));
+    }
+
+    #[test]
+    fn test_taproot_reveal_internal_key() {
+        let script = test_envelope_script(TEST_MESSAGE);
+        let witness = Witness::from_slice(&[
+            vec![0x01; 64],
+            script.to_bytes(),
+            hex::decode(TEST_CONTROL_BLOCK).unwrap(),
+        ]);
+        let marker = ScriptBuf::new_op_return(push_bytes(b"CNTRPRTY".to_vec()));
+        let tx = test_tx_with_outputs(vec![marker], witness);
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+
+        let transaction = parse_transaction(&tx, &config, Height(0), true);
+        assert!(transaction.parsed_vouts.unwrap().is_reveal_tx);
+        assert_eq!(
+            transaction.taproot_internal_key.as_deref(),
+            Some("79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")
+        );
+
+        // Not a reveal, so no key is attached.
+        let tx = test_tx_with_outputs(vec![test_op_return_script(TEST_MESSAGE)], Witness::new());
+        assert!(parse_transaction(&tx, &config, Height(0), true)
+            .taproot_internal_key
+            .is_none());
+    }
+
+    fn test_p2wsh_envelope_witness(message: &[u8]) -> Witness {
+        Witness::from_slice(&[vec![0x01; 64], test_envelope_script(message).to_bytes()])
+    }
+
+    fn test_parsed_vouts(tx: &Transaction) -> ParsedVouts {
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+        parse_transaction(tx, &config, Height(0), true)
+            .parsed_vouts
+            .unwrap()
+    }
+
+    #[test]
+    fn test_message_separator() {
+        let separator = b"||".to_vec();
+        let data = [TEST_MESSAGE, &separator, b"world"].concat();
+        let tx = test_tx_with_outputs(vec![test_op_return_script(&data)], Witness::new());
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+
+        let parsed = parse_transaction(&tx, &config, Height(0), true)
+            .parsed_vouts
+            .unwrap();
+        assert_eq!(parsed.messages, vec![data.clone()]);
+
+        config.message_separator = Some(separator.clone());
+        let parsed = parse_transaction(&tx, &config, Height(0), true)
+            .parsed_vouts
+            .unwrap();
+        assert_eq!(parsed.data, data);
+        assert_eq!(
+            parsed.messages,
+            vec![TEST_MESSAGE.to_vec(), b"world".to_vec()]
+        );
+
+        assert_eq!(
+            split_messages(b"||a||||b|", Some(&separator)),
+            vec![vec![], b"a".to_vec(), vec![], b"b|".to_vec()]
+        );
+        assert_eq!(split_messages(b"", Some(&separator)), Vec::<Vec<u8>>::new());
+        assert_eq!(split_messages(b"a||b", Some(b"")), vec![b"a||b".to_vec()]);
+    }
+
+    #[test]
+    fn test_data_source_op_return() {
+        let tx = test_tx_with_outputs(vec![test_op_return_script(TEST_MESSAGE)], Witness::new());
+        let parsed = test_parsed_vouts(&tx);
+        assert_eq!(parsed.data, TEST_MESSAGE);
+        assert_eq!(parsed.data_source, Some(DataSource::OpReturn));
+    }
+
+    #[test]
+    fn test_data_source_checksig() {
+        let tx = test_tx_with_outputs(vec![test_checksig_script(TEST_MESSAGE)], Witness::new());
+        let parsed = test_parsed_vouts(&tx);
+        assert_eq!(parsed.data, TEST_MESSAGE);
+        assert_eq!(parsed.data_source, Some(DataSource::Checksig));
+    }
+
+    #[test]
+    fn test_data_source_multisig() {
+        let tx = test_tx_with_outputs(vec![test_multisig_script(TEST_MESSAGE)], Witness::new());
+        let parsed = test_parsed_vouts(&tx);
+        assert_eq!(parsed.data, TEST_MESSAGE);
+        assert_eq!(parsed.data_source, Some(DataSource::Multisig));
+    }
+
+    #[test]
+    fn test_op_return_anchor() {
+        let mut tx = test_tx_with_outputs(
+            vec![
+                test_op_return_script(TEST_MESSAGE),
+                ScriptBuf::from_bytes(vec![OP_RETURN.to_u8()]),
+            ],
+            Witness::new(),
+        );
+        tx.output[1].value = Amount::ZERO;
+        let mut config = test_config();
+        let height = Height(900_000);
+
+        let parsed = parse_transaction(&tx, &config, height, true);
+        assert!(parsed
+            .parsed_vouts
+            .err()
+            .unwrap()
+            .contains("invalid OP_RETURN"));
+
+        config.heights.op_return_anchor = height.0;
+        let parsed = parse_transaction(&tx, &config, height, true)
+            .parsed_vouts
+            .unwrap();
+        assert_eq!(parsed.data, TEST_MESSAGE);
+        assert_eq!(parsed.destinations, Vec::<String>::new());
+
+        // An anchor carries no value.
+        tx.output[1].value = Amount::from_sat(1);
+        let parsed = parse_transaction(&tx, &config, height, true);
+        assert!(parsed.parsed_vouts.is_err());
+    }
+
+    #[test]
+    fn test_extended_multisig() {
+        // Spills into the third pubkey.
+        let message = [TEST_MESSAGE; 16].concat();
+        let tx = test_tx_with_outputs(
+            vec![test_extended_multisig_script(&message)],
+            Witness::new(),
+        );
+        let mut config = test_config();
+        let height = Height(900_000);
+
+        let parsed = parse_transaction(&tx, &config, height, true);
+        assert!(parsed
+            .parsed_vouts
+            .err()
+            .unwrap()
+            .contains("invalid OP_MULTISIG"));
+
+        config.heights.extended_multisig = height.0;
+        let parsed = parse_transaction(&tx, &config, height, true)
+            .parsed_vouts
+            .unwrap();
+        assert_eq!(parsed.data, message);
+        assert_eq!(parsed.data_source, Some(DataSource::Multisig));
+
+        // Sixteen pubkeys is past what OP_CHECKMULTISIG allows.
+        let pubkeys = vec![vec![0x02; 33]; 16];
+        let mut builder = ScriptBuf::builder().push_opcode(OP_PUSHNUM_1);
+        for pubkey in &pubkeys {
+            builder = builder.push_slice(push_bytes(pubkey.clone()));
+        }
+        let script = builder
+            .push_opcode(bitcoin::opcodes::all::OP_PUSHNUM_16)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        assert!(extended_multisig_pubkeys(&script).is_none());
+        // OP_RESERVED sits just below OP_PUSHNUM_1 and pushes no number.
+        let mut builder = ScriptBuf::builder().push_opcode(bitcoin::opcodes::all::OP_RESERVED);
+        for pubkey in &pubkeys[..4] {
+            builder = builder.push_slice(push_bytes(pubkey.clone()));
+        }
+        let script = builder
+            .push_opcode(bitcoin::opcodes::all::OP_PUSHNUM_4)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        assert!(extended_multisig_pubkeys(&script).is_none());
+        assert_eq!(pushnum(bitcoin::opcodes::all::OP_RESERVED), None);
+        assert_eq!(pushnum(OP_PUSHNUM_1), Some(1));
+        assert_eq!(pushnum(bitcoin::opcodes::all::OP_PUSHNUM_16), Some(16));
+        assert_eq!(
+            extended_multisig_pubkeys(&test_extended_multisig_script(TEST_MESSAGE))
+                .unwrap()
+                .0,
+            1
+        );
+    }
+
+    #[test]
+    fn test_data_source_taproot_reveal() {
+        let marker = ScriptBuf::new_op_return(push_bytes(b"CNTRPRTY".to_vec()));
+        let tx = test_tx_with_outputs(vec![marker], test_reveal_witness(TEST_MESSAGE));
+        let parsed = test_parsed_vouts(&tx);
+        assert!(parsed.is_reveal_tx);
+        assert_eq!(parsed.data, TEST_MESSAGE);
+        assert_eq!(parsed.data_source, Some(DataSource::TaprootReveal));
+    }
+
+    #[test]
+    fn test_message_type_plain_data() {
+        let tx = test_tx_with_outputs(vec![test_op_return_script(TEST_MESSAGE)], Witness::new());
+        let parsed = test_parsed_vouts(&tx);
+        assert_eq!(parsed.message_type(), Some(TEST_MESSAGE[0]));
+
+        let mut empty = parsed;
+        empty.data.clear();
+        assert_eq!(empty.message_type(), None);
+    }
+
+    #[test]
+    fn test_dispenser_candidates() {
+        let p2pkh = |i| ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array(test_h160_hash(i)));
+        let mut tx = test_tx_with_outputs(
+            vec![
+                test_op_return_script(TEST_MESSAGE),
+                p2pkh(1),
+                p2pkh(2),
+                p2pkh(1),
+                p2pkh(1),
+            ],
+            Witness::new(),
+        );
+        tx.output[4].value = Amount::from_sat(1000);
+        let parsed = test_parsed_vouts(&tx);
+        assert_eq!(parsed.potential_dispensers.len(), 5);
+
+        let candidates: Vec<_> = parsed
+            .dispenser_candidates()
+            .into_iter()
+            .map(|pd| (pd.destination.clone().unwrap(), pd.value))
+            .collect();
+        assert_eq!(
+            candidates,
+            vec![
+                (parsed.destinations[0].clone(), Some(546)),
+                (parsed.destinations[1].clone(), Some(546)),
+                (parsed.destinations[0].clone(), Some(1000)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_block_has_counterparty_data() {
+        let p2pkh = |i| ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array(test_h160_hash(i)));
+        let mut block = test_block(vec![
+            test_tx_with_outputs(vec![p2pkh(1), p2pkh(2)], Witness::new()),
+            test_tx_with_outputs(
+                vec![p2pkh(3), ScriptBuf::from_bytes(vec![0x61, 0x75])],
+                Witness::new(),
+            ),
+        ]);
+        let config = test_config();
+        assert!(!block_has_counterparty_data(
+            &block,
+            &config,
+            Height(902000)
+        ));
+
+        block.txdata.push(test_tx_with_outputs(
+            vec![p2pkh(4), test_op_return_script(TEST_MESSAGE)],
+            Witness::new(),
+        ));
+        assert!(block_has_counterparty_data(&block, &config, Height(902000)));
+        assert!(parse_block(block, &config, Height(902000), true)
+            .unwrap()
+            .transactions[2]
+            .parsed_vouts
+            .as_ref()
+            .is_ok_and(|parsed_vouts| parsed_vouts.data == TEST_MESSAGE));
+    }
+
+    // Coinbase of mainnet block 831298: a P2PKH payout, dust, the witness commitment
+    // and CORE and RSK merge mining tags.
+    const TEST_SEGWIT_COINBASE: &str = "010000000001010000000000000000000000000000000000000000000000000000000000000000ffffffff640342af0c2cfabe6d6dd04bc3504cba11910d72d3f9bcc603156272ec18d096431da690d1c11650bcec10000000f09f909f092f4632506f6f6c2f6900000000000000000000000000000000000000000000000000000000000000000000000500406f0100000000000522020000000000001976a914c6740a12d0a7d556f89782bf5faf0e12cf25a63988acf1c70e26000000001976a914c85526a428126c00ad071b56341a5a553a5e96a388ac0000000000000000266a24aa21a9ed8fd9974d26b10d3db6664fa2c59e8a504cb97c06e765a54c9096343cbac7716a00000000000000002f6a2d434f5245012953559db5cc88ab20b1960faa9793803d070337bdb2a04b4ccf74792cc6753c27c5fd5f1d6458bf00000000000000002c6a4c2952534b424c4f434b3af55b0e3836fafb2163bc99ce0bc3a950bf3bac5029e340f20459d525005d16580120000000000000000000000000000000000000000000000000000000000000000038aef23c";
+
+    #[test]
+    fn test_parse_transaction_segwit_coinbase() {
+        let tx: Transaction =
+            bitcoin::consensus::deserialize(&hex::decode(TEST_SEGWIT_COINBASE).unwrap()).unwrap();
+        assert!(tx.is_coinbase());
+        let parsed = parse_transaction(&tx, &test_config(), Height(831298), true);
+        assert_eq!(
+            parsed.witness_commitment.as_deref(),
+            Some("8fd9974d26b10d3db6664fa2c59e8a504cb97c06e765a54c9096343cbac7716a")
+        );
+        // The merge mining tags are skipped along with the commitment.
+        let parsed_vouts = parsed.parsed_vouts.unwrap();
+        assert_eq!(parsed_vouts.destinations.len(), 2);
+        assert!(parsed_vouts.data.is_empty());
+        assert_eq!(parsed_vouts.potential_dispensers.len(), 5);
+
+        // Elsewhere the commitment is not one, and it and the tags fail to decode.
+        let mut spend = test_tx_with_outputs(vec![], Witness::new());
+        spend.output.push(tx.output[2].clone());
+        let parsed = parse_transaction(&spend, &test_config(), Height(831298), true);
+        assert_eq!(parsed.witness_commitment, None);
+        assert!(parsed.parsed_vouts.is_err());
+        let mut spend = test_tx_with_outputs(vec![], Witness::new());
+        spend.output.push(tx.output[3].clone());
+        let parsed = parse_transaction(&spend, &test_config(), Height(831298), true);
+        assert!(parsed.parsed_vouts.is_err());
+    }
+
+    #[test]
+    fn test_prev_txs_for_inputs_repairs_length() {
+        let tx = test_tx_with_outputs(vec![], Witness::new());
+        let mut warnings = Vec::new();
+
+        let prev_txs = prev_txs_for_inputs(Ok(vec![Some(tx.clone())]), 3, &mut warnings);
+        assert_eq!(prev_txs, vec![Some(tx.clone()), None, None]);
+        assert_eq!(
+            warnings,
+            vec!["Fetched 1 previous transactions for 3 inputs"]
+        );
+
+        let prev_txs = prev_txs_for_inputs(Ok(vec![None, Some(tx)]), 1, &mut warnings);
+        assert_eq!(prev_txs, vec![None]);
+        assert_eq!(warnings.len(), 2);
+
+        // A node that leaves inputs out of its answer.
+        let server = test_rpc_server(
+            |request| json!([{"result": null, "error": null, "id": request[0]["id"]}]),
+        );
+        let client =
+            BatchRpcClient::new(vec![server.url.clone()], "".into(), "".into(), 10).unwrap();
+        let fetched = client.get_transactions(&[
+            Txid::from_byte_array(test_sha256_hash(1)),
+            Txid::from_byte_array(test_sha256_hash(2)),
+        ]);
+        let mut warnings = Vec::new();
+        assert_eq!(
+            prev_txs_for_inputs(fetched, 2, &mut warnings),
+            vec![None, None]
+        );
+        assert!(warnings[0].contains("InvalidResponse"));
+
+        // An unreachable node is not reported.
+        let mut warnings = Vec::new();
+        let fetched = Err(BatchRpcError::Unreachable(
+            std::io::ErrorKind::ConnectionRefused.into(),
+        ));
+        assert_eq!(
+            prev_txs_for_inputs(fetched, 2, &mut warnings),
+            vec![None, None]
+        );
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_parse_block_without_coinbase() {
+        let tx: Transaction =
+            bitcoin::consensus::deserialize(&hex::decode(TEST_SEGWIT_COINBASE).unwrap()).unwrap();
+        let block = test_block(vec![
+            tx,
+            test_tx_with_outputs(vec![test_op_return_script(TEST_MESSAGE)], Witness::new()),
+        ]);
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+        config.max_parse_errors_per_block = Some(0);
+        let parsed = parse_block(block.clone(), &config, Height(0), true).unwrap();
+        assert!(parsed.transactions[0].parsed_vouts.is_ok());
+
+        config.parse_coinbase = false;
+        let parsed = parse_block(block, &config, Height(0), true).unwrap();
+        let coinbase = &parsed.transactions[0];
+        assert!(coinbase.coinbase);
+        assert_eq!(coinbase.vout.len(), 5);
+        assert!(coinbase.witness_commitment.is_some());
+        assert_eq!(coinbase.parsed_vouts.as_ref().err().unwrap(), "Not Parsed");
+        assert_eq!(
+            parsed.transactions[1].parsed_vouts.as_ref().unwrap().data,
+            TEST_MESSAGE
+        );
+    }
+
+    #[test]
+    fn test_extract_message_payload() {
+        let prefix = b"CNTRPRTY";
+        let length_prefixed = |len: u8, data: &[u8]| {
+            let mut bytes = vec![len];
+            bytes.extend_from_slice(prefix);
+            bytes.extend_from_slice(data);
+            bytes
+        };
+
+        for source in [DataSource::OpReturn, DataSource::ScriptSig] {
+            let extract = |bytes: &[u8]| extract_message_payload(source, bytes, prefix);
+            assert_eq!(extract(b"CNTRPRTYdata"), Some(b"data".to_vec()));
+            assert_eq!(extract(b"CNTRPRTY"), Some(vec![]));
+            assert_eq!(extract(b"CNTRPRT"), None);
+            assert_eq!(extract(b""), None);
+            // A length byte is data here, so the prefix is out of place.
+            assert_eq!(extract(&length_prefixed(12, b"data")), None);
+        }
+
+        for source in [DataSource::Checksig, DataSource::Multisig] {
+            let extract = |bytes: &[u8]| extract_message_payload(source, bytes, prefix);
+            // The length counts the prefix and the message.
+            assert_eq!(
+                extract(&length_prefixed(12, b"data")),
+                Some(b"data".to_vec())
+            );
+            // Bytes past the declared length are padding.
+            assert_eq!(extract(&length_prefixed(10, b"data")), Some(b"da".to_vec()));
+            // A declared length past the payload is clamped to it.
+            assert_eq!(
+                extract(&length_prefixed(33, b"data")),
+                Some(b"data".to_vec())
+            );
+            // A declared length inside the prefix leaves nothing.
+            assert_eq!(extract(&length_prefixed(8, b"data")), Some(vec![]));
+            assert_eq!(extract(&length_prefixed(3, b"data")), Some(vec![]));
+            assert_eq!(extract(&length_prefixed(0, b"")), Some(vec![]));
+            assert_eq!(extract(b"CNTRPRTYdata"), None);
+            assert_eq!(extract(&length_prefixed(12, b"data")[..8]), None);
+            assert_eq!(extract(b""), None);
+        }
+
+        for source in [
+            DataSource::TaprootReveal,
+            DataSource::P2wshReveal,
+            DataSource::Mixed,
+        ] {
+            assert_eq!(
+                extract_message_payload(source, b"CNTRPRTYdata", prefix),
+                None
+            );
+            assert_eq!(
+                extract_message_payload(source, &length_prefixed(12, b"data"), prefix),
+                None
+            );
+        }
+
+        let mut config = test_config();
+        config.prefixes = vec![b"CNTRPRTY".to_vec(), b"XCP".to_vec()];
+        assert_eq!(
+            message_payload(&config, DataSource::OpReturn, b"XCPdata"),
+            Some((b"data".to_vec(), 1))
+        );
+        assert_eq!(
+            message_payload(&config, DataSource::Checksig, b"\x07XCPdata"),
+            Some((b"data".to_vec(), 1))
+        );
+        assert_eq!(
+            message_payload(&config, DataSource::Multisig, b"\x07XCPdata"),
+            Some((b"data".to_vec(), 1))
+        );
+    }
+
+    #[test]
+    fn test_reparse_height() {
+        let block = test_block(vec![test_tx_with_outputs(
+            vec![test_op_return_script(TEST_MESSAGE)],
+            Witness::new(),
+        )]);
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+        config.store_raw_blocks = true;
+        let db = new_test_db!().unwrap();
+        let parsed = parse_block(block.clone(), &config, Height(5), true).unwrap();
+        let entries = block.get_entries(&config, Height(5), &parsed);
+        db.write_batch(|batch| db.put_entries(batch, None, &entries))
+            .unwrap();
+
+        let reparsed = reparse_height(Height(5), &config, &db).unwrap();
+        assert!(reparsed == parsed);
+        assert_eq!(
+            reparsed.transactions[0].parsed_vouts.as_ref().unwrap().data,
+            TEST_MESSAGE
+        );
+        assert!(matches!(
+            reparse_height(Height(6), &config, &db),
+            Err(Error::BlockNotWritten(6))
+        ));
+    }
+
+    #[test]
+    fn test_parse_block_genesis() {
+        let genesis = bitcoin::constants::genesis_block(bitcoin::Network::Bitcoin);
+        let mut config = test_config();
+        config.max_parse_errors_per_block = Some(0);
+
+        let parsed = parse_block(genesis.clone(), &config, Height(0), true).unwrap();
+        assert_eq!(parsed.height, 0);
+        assert_eq!(
+            parsed.block_hash,
+            "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"
+        );
+        assert_eq!(parsed.transaction_count, 1);
+        let coinbase = &parsed.transactions[0];
+        assert!(coinbase.coinbase);
+        assert_eq!(
+            coinbase.tx_id,
+            "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b"
+        );
+        assert_eq!(coinbase.vout.len(), 1);
+        assert_eq!(coinbase.vout[0].value, 5_000_000_000);
+        assert!(coinbase.parsed_vouts.is_err());
+        assert!(coinbase.warnings.is_empty());
+
+        let (_, warnings) = parse_block_verbose(genesis.clone(), &config, Height(0)).unwrap();
+        assert!(warnings.is_empty());
+        assert!(genesis.to_block(config, Height(0)).unwrap() == parsed);
+    }
+
+    #[test]
+    fn test_block_destinations() {
+        let p2pkh = |i| ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array(test_h160_hash(i)));
+        let block = test_block(vec![
+            test_tx_with_outputs(vec![p2pkh(1), p2pkh(2), p2pkh(1)], Witness::new()),
+            // Data only.
+            test_tx_with_outputs(vec![test_op_return_script(TEST_MESSAGE)], Witness::new()),
+            // Fails to parse.
+            test_tx_with_outputs(
+                vec![p2pkh(4), ScriptBuf::from_bytes(vec![0x61, 0x75])],
+                Witness::new(),
+            ),
+            test_tx_with_outputs(vec![p2pkh(3), p2pkh(2)], Witness::new()),
+        ]);
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+        let parsed = parse_block(block, &config, Height(0), true).unwrap();
+        assert!(parsed.transactions[2].parsed_vouts.is_err());
+
+        let address = |i| {
+            let tx = test_tx_with_outputs(vec![p2pkh(i)], Witness::new());
+            test_parsed_vouts(&tx).destinations[0].clone()
+        };
+        assert_eq!(
+            parsed.destinations(),
+            vec![address(1), address(2), address(3)]
+        );
+    }
+
+    #[test]
+    fn test_block_total_data_bytes() {
+        let p2pkh = ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array(test_h160_hash(1)));
+        let block = test_block(vec![
+            test_tx_with_outputs(vec![test_op_return_script(&[1; 5])], Witness::new()),
+            test_tx_with_outputs(vec![p2pkh.clone()], Witness::new()),
+            test_tx_with_outputs(vec![test_op_return_script(&[2; 40])], Witness::new()),
+            // Fails to parse.
+            test_tx_with_outputs(
+                vec![
+                    test_op_return_script(&[3; 10]),
+                    ScriptBuf::from_bytes(vec![0x61, 0x75]),
+                ],
+                Witness::new(),
+            ),
+        ]);
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+        let parsed = parse_block(block, &config, Height(0), true).unwrap();
+        assert!(parsed.transactions[3].parsed_vouts.is_err());
+
+        assert_eq!(parsed.total_data_bytes(), 45);
+    }
+
+    #[test]
+    fn test_message_type_ord_reveal() {
+        let metadata = serde_cbor::to_vec(&Value::Array(vec![
+            Value::Integer(20),
+            Value::Text("XCP".into()),
+        ]))
+        .unwrap();
+        let script = test_ord_script(b"text/plain", &[5], &[&metadata]);
+        let witness = Witness::from_slice(&[vec![0x01; 64], script.to_bytes(), vec![0xc0; 33]]);
+        let marker = ScriptBuf::new_op_return(push_bytes(b"CNTRPRTY".to_vec()));
+        let tx = test_tx_with_outputs(vec![marker], witness);
+
+        let parsed = test_parsed_vouts(&tx);
+        assert!(parsed.is_reveal_tx);
+        assert_eq!(parsed.message_type(), Some(20));
+        assert_eq!(
+            serde_cbor::from_slice::<Value>(&parsed.data[1..]).unwrap(),
+            Value::Array(vec![
+                Value::Text("XCP".into()),
+                Value::Text("text/plain".into())
+            ])
+        );
+    }
+
+    #[test]
+    fn test_decode_large_metadata() {
+        let payload: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+        let fields = vec![Value::Bytes(payload), Value::Text("XCP".into())];
+        let document = Value::Array([vec![Value::Integer(20)], fields.clone()].concat());
+        let metadata = serde_cbor::to_vec(&document).unwrap();
+        assert!(metadata.len() > STREAMED_METADATA_THRESHOLD);
+        let chunks: Vec<&[u8]> = metadata.chunks(520).collect();
+
+        // The reader hands out no more than was asked for, from one push at a time.
+        let mut reader = ChunkReader::new(&chunks);
+        let mut buf = [0; 600];
+        assert_eq!(std::io::Read::read(&mut reader, &mut buf).unwrap(), 520);
+        assert_eq!(std::io::Read::read(&mut reader, &mut buf[..8]).unwrap(), 8);
+
+        assert_eq!(decode_metadata(&chunks).unwrap(), document);
+        let truncated = &chunks[..chunks.len() - 1];
+        assert!(decode_metadata(truncated).unwrap_err().is_eof());
+        let trailing = [chunks.as_slice(), &[&[0u8][..]]].concat();
+        assert!(decode_metadata(&trailing).is_err());
+
+        let script = test_ord_script(b"text/plain", &[5], &chunks);
+        let data = extract_data_from_witness(&script, EnvelopeRules::latest()).unwrap();
+        assert_eq!(data[0], 20);
+        assert_eq!(
+            serde_cbor::from_slice::<Value>(&data[1..]).unwrap(),
+            Value::Array([fields, vec![Value::Text("text/plain".into())]].concat())
+        );
+    }
+
+    #[test]
+    fn test_message_type_id_out_of_range() {
+        let metadata = |id| {
+            serde_cbor::to_vec(&Value::Array(vec![
+                Value::Integer(id),
+                Value::Text("XCP".into()),
+            ]))
+            .unwrap()
+        };
+        let script = |metadata: Vec<u8>| test_ord_script(b"text/plain", &[5], &[&metadata]);
+
+        assert_eq!(
+            extract_data_from_witness(&script(metadata(255)), EnvelopeRules::latest()).unwrap()[0],
+            255
+        );
+        for id in [300, 256, -1] {
+            assert!(matches!(
+                extract_data_from_witness(&script(metadata(id)), EnvelopeRules::latest()),
+                Err(Error::ParseVout(message)) if message == "message_type_id out of range"
+            ));
+            // A CBOR body with such an id isn't a message.
+            assert_eq!(cbor_message(&metadata(id), EnvelopeRules::latest()), None);
+        }
+
+        // Before the range check, the id is truncated to its low byte.
+        let rules = EnvelopeRules {
+            type_id_range: false,
+            ..EnvelopeRules::latest()
+        };
+        for (id, type_id) in [(300, 44), (256, 0), (-1, 255)] {
+            assert_eq!(
+                extract_data_from_witness(&script(metadata(id)), rules).unwrap()[0],
+                type_id
+            );
+            assert_eq!(cbor_message(&metadata(id), rules).unwrap()[0], type_id);
+        }
+    }
+
+    #[test]
+    fn test_message_type_ord_cbor_body() {
+        let ord_script = |content: &[u8]| test_ord_script(b"application/cbor", &[], &[content]);
+        let message = serde_cbor::to_vec(&Value::Array(vec![
+            Value::Integer(20),
+            Value::Text("XCP".into()),
+        ]))
+        .unwrap();
+        let script = ord_script(&message);
+        let witness = Witness::from_slice(&[vec![0x01; 64], script.to_bytes(), vec![0xc0; 33]]);
+        let marker = ScriptBuf::new_op_return(push_bytes(b"CNTRPRTY".to_vec()));
+        let tx = test_tx_with_outputs(vec![marker], witness);
+
+        let parsed = test_parsed_vouts(&tx);
+        assert!(parsed.is_reveal_tx);
+        assert_eq!(parsed.message_type(), Some(20));
+        assert_eq!(
+            serde_cbor::from_slice::<Value>(&parsed.data[1..]).unwrap(),
+            Value::Array(vec![Value::Text("XCP".into())])
+        );
+
+        // A body that isn't a CBOR message is taken as is.
+        let raw = ord_script(TEST_MESSAGE);
+        assert_eq!(
+            extract_data_from_witness(&raw, EnvelopeRules::latest()).unwrap(),
+            TEST_MESSAGE
+        );
+
+        // Before activation the inscription has no data.
+        let rules = EnvelopeRules {
+            cbor_body: false,
+            ..EnvelopeRules::latest()
+        };
+        assert!(extract_data_from_witness(&script, rules).is_err());
+        let config = test_config();
+        assert!(
+            parse_transaction(&tx, &config, Height(config.heights.taproot_support), true)
+                .parsed_vouts
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_data_source_p2wsh_reveal() {
+        let marker = ScriptBuf::new_op_return(push_bytes(b"CNTRPRTY".to_vec()));
+        let tx = test_tx_with_outputs(vec![marker], test_p2wsh_envelope_witness(TEST_MESSAGE));
+        let parsed = test_parsed_vouts(&tx);
+        assert!(parsed.is_reveal_tx);
+        assert_eq!(parsed.data, TEST_MESSAGE);
+        assert_eq!(parsed.data_source, Some(DataSource::P2wshReveal));
+
+        // A witness that is not an envelope must not leave the marker as the message.
+        let marker = ScriptBuf::new_op_return(push_bytes(b"CNTRPRTY".to_vec()));
+        let tx = test_tx_with_outputs(
+            vec![marker],
+            Witness::from_slice(&[vec![0x01; 64], vec![0x51]]),
+        );
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+        assert!(parse_transaction(&tx, &config, Height(0), true)
+            .parsed_vouts
+            .is_err());
+    }
+
+    #[test]
+    fn test_p2wsh_reveal_checks_witness_script() {
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+        let parse = |committed: ScriptBuf| {
+            let commit = test_tx_with_outputs(vec![committed], Witness::new());
+            let marker = ScriptBuf::new_op_return(push_bytes(b"CNTRPRTY".to_vec()));
+            let mut reveal =
+                test_tx_with_outputs(vec![marker], test_p2wsh_envelope_witness(TEST_MESSAGE));
+            reveal.input[0].previous_output = OutPoint {
+                txid: commit.compute_txid(),
+                vout: 0,
+            };
+            let prev_txs = HashMap::from([(commit.compute_txid(), commit)]);
+            parse_transaction_from(&reveal, &config, Height(0), true, Some(&prev_txs), None)
+        };
+
+        let witness_script = test_envelope_script(TEST_MESSAGE);
+        let p2wsh = ScriptBuf::new_p2wsh(&witness_script.wscript_hash());
+        let p2sh_p2wsh = ScriptBuf::new_p2sh(&p2wsh.script_hash());
+        for committed in [p2wsh.clone(), p2sh_p2wsh.clone()] {
+            let parsed = parse(committed);
+            let parsed_vouts = parsed.parsed_vouts.as_ref().unwrap();
+            assert!(parsed_vouts.is_reveal_tx);
+            assert_eq!(parsed_vouts.data, TEST_MESSAGE);
+            assert!(!parsed.warnings.iter().any(|w| w.contains("P2WSH")));
+        }
+
+        // The reveal counts all the same; the mismatch is only reported.
+        let other = ScriptBuf::new_p2wsh(&test_envelope_script(b"other").wscript_hash());
+        let parsed = parse(other);
+        assert_eq!(parsed.parsed_vouts.as_ref().unwrap().data, TEST_MESSAGE);
+        assert!(parsed.warnings.iter().any(|w| w.contains("P2WSH")));
+
+        assert!(p2wsh_commits_to(&p2wsh, witness_script.as_bytes()));
+        assert!(p2wsh_commits_to(&p2sh_p2wsh, witness_script.as_bytes()));
+        assert!(!p2wsh_commits_to(&p2wsh, b"other"));
+        // Only a P2WSH output commits to a witness script.
+        assert!(!p2wsh_commits_to(
+            &ScriptBuf::new_p2wpkh(&bitcoin::WPubkeyHash::from_byte_array(test_h160_hash(1))),
+            witness_script.as_bytes()
+        ));
+    }
+
+    #[test]
+    fn test_p2wsh_reveal_requires_activation() {
+        let marker = ScriptBuf::new_op_return(push_bytes(b"CNTRPRTY".to_vec()));
+        let tx = test_tx_with_outputs(vec![marker], test_p2wsh_envelope_witness(TEST_MESSAGE));
+        let config = test_config();
+        let parsed = parse_transaction(&tx, &config, Height(config.heights.taproot_support), true)
+            .parsed_vouts
+            .unwrap();
+        assert!(!parsed.is_reveal_tx);
+        assert_eq!(parsed.data, b"CNTRPRTY");
+        assert_eq!(parsed.data_source, Some(DataSource::OpReturn));
+    }
+
+    #[test]
+    fn test_fetch_commit_parent_retries_empty_result() {
+        let parent =
+            test_tx_with_outputs(vec![test_op_return_script(TEST_MESSAGE)], Witness::new());
+        let parent_hex = hex::encode(serialize(&parent));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let calls_clone = calls.clone();
+        let server = test_rpc_server(move |request| {
+            if calls_clone.fetch_add(1, Ordering::SeqCst) == 0 {
+                return json!([]);
+            }
+            json!([{"result": parent_hex, "error": null, "id": request[0]["id"]}])
+        });
+        let client =
+            BatchRpcClient::new(vec![server.url.clone()], "".into(), "".into(), 10).unwrap();
+
+        assert_eq!(
+            fetch_commit_parent(&client, parent.compute_txid()),
+            Some(parent)
+        );
+        assert_eq!(server.requests(), 2);
+    }
+
+    #[test]
+    fn test_parse_block_with_prev_txs() {
+        let parent = test_tx_with_outputs(
+            vec![
+                test_op_return_script(TEST_MESSAGE),
+                ScriptBuf::new_p2wpkh(&bitcoin::WPubkeyHash::from_byte_array(test_h160_hash(3))),
+            ],
+            Witness::new(),
+        );
+        let mut commit = test_tx_with_outputs(
+            vec![ScriptBuf::new_op_return(push_bytes(vec![0x01]))],
+            Witness::new(),
+        );
+        commit.input[0].previous_output = OutPoint {
+            txid: parent.compute_txid(),
+            vout: 1,
+        };
+        let marker = ScriptBuf::new_op_return(push_bytes(b"CNTRPRTY".to_vec()));
+        let mut reveal = test_tx_with_outputs(vec![marker], test_reveal_witness(TEST_MESSAGE));
+        reveal.input[0].previous_output = OutPoint {
+            txid: commit.compute_txid(),
+            vout: 0,
+        };
+        let block = test_block(vec![reveal]);
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+
+        let prev_txs = HashMap::from([
+            (commit.compute_txid(), commit.clone()),
+            (parent.compute_txid(), parent.clone()),
+        ]);
+        let parsed =
+            parse_block_with_prev_txs(block.clone(), &config, Height(0), true, &prev_txs).unwrap();
+        let tx = &parsed.transactions[0];
+        assert!(tx.parsed_vouts.as_ref().unwrap().is_reveal_tx);
+        assert!(tx.warnings.is_empty());
+        // The reveal's input is described by the output its commit spent.
+        let info = tx.vin[0].info.as_ref().unwrap();
+        assert_eq!(info.value, 546);
+        assert_eq!(
+            info.script_pub_key,
+            parent.output[1].script_pubkey.to_bytes()
+        );
+        assert!(info.is_segwit);
+
+        // Without the commit's parent the input is left without info.
+        let prev_txs = HashMap::from([(commit.compute_txid(), commit)]);
+        let parsed = parse_block_with_prev_txs(block, &config, Height(0), true, &prev_txs).unwrap();
+        let tx = &parsed.transactions[0];
+        assert!(tx.vin[0].info.is_none());
+        assert_eq!(
+            tx.warnings,
+            vec![format!(
+                "Failed to fetch commit parent {} of reveal input",
+                parent.compute_txid()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_fetch_commit_parent_gives_up_after_retry() {
+        let server = test_rpc_server(|_| json!([]));
+        let client =
+            BatchRpcClient::new(vec![server.url.clone()], "".into(), "".into(), 10).unwrap();
+
+        assert_eq!(fetch_commit_parent(&client, Txid::all_zeros()), None);
+        assert_eq!(server.requests(), 2);
+    }
+
+    #[test]
+    fn test_bare_marker_flag_before_taproot_activation() {
+        let marker = ScriptBuf::new_op_return(push_bytes(b"CNTRPRTY".to_vec()));
+        let tx = test_tx_with_outputs(vec![marker], test_reveal_witness(TEST_MESSAGE));
+        let mut config = test_config();
+        // Lets the bare marker through as an empty plaintext payload, so the outputs
+        // parse without the taproot gate that would read it as a reveal marker.
+        config.heights.plaintext_op_return = 0;
+        let height = Height(config.heights.taproot_support - 1);
+
+        let parsed = parse_transaction(&tx, &config, height, true)
+            .parsed_vouts
+            .unwrap();
+        assert!(parsed.has_bare_marker);
+        assert!(!parsed.is_reveal_tx);
+        assert!(parsed.data.is_empty());
+
+        let tx = test_tx_with_outputs(vec![test_op_return_script(TEST_MESSAGE)], Witness::new());
+        assert!(!test_parsed_vouts(&tx).has_bare_marker);
+    }
+
+    #[test]
+    fn test_marker_variant() {
+        let marker_tx = |marker: &[u8]| {
+            let marker = ScriptBuf::new_op_return(push_bytes(marker.to_vec()));
+            test_tx_with_outputs(vec![marker], test_reveal_witness(TEST_MESSAGE))
+        };
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+
+        let parsed = parse_transaction(&marker_tx(b"CNTRPRTY"), &config, Height(0), true);
+        assert!(parsed.parsed_vouts.as_ref().unwrap().is_reveal_tx);
+        assert_eq!(parsed.marker_variant, None);
+
+        for variant in [
+            &b"cntrprty"[..],
+            b"CNTRPRTY\x00",
+            b"CNTRPRT",
+            b"CNTRPRTX",
+            b" CNTRPRTY ",
+        ] {
+            let parsed = parse_transaction(&marker_tx(variant), &config, Height(0), true);
+            // Still not a reveal: the near miss only explains why.
+            assert!(!parsed.parsed_vouts.is_ok_and(|p| p.is_reveal_tx));
+            assert_eq!(parsed.marker_variant.as_deref(), Some(variant));
+        }
+
+        for unrelated in [&b"CNTR"[..], b"XCPXCPXC", b"CNTRPRTYCNTRPRTY"] {
+            let parsed = parse_transaction(&marker_tx(unrelated), &config, Height(0), true);
+            assert_eq!(parsed.marker_variant, None);
+        }
+        let tx = test_tx_with_outputs(vec![test_op_return_script(TEST_MESSAGE)], Witness::new());
+        assert_eq!(
+            parse_transaction(&tx, &config, Height(0), true).marker_variant,
+            None
+        );
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance(b"CNTRPRTY", b"CNTRPRTY"), 0);
+        assert_eq!(edit_distance(b"CNTRPRTX", b"CNTRPRTY"), 1);
+        assert_eq!(edit_distance(b"CNTRPRT", b"CNTRPRTY"), 1);
+        assert_eq!(edit_distance(b"XCNTRPRTY", b"CNTRPRTY"), 1);
+        assert_eq!(edit_distance(b"", b"CNTRPRTY"), 8);
+        assert_eq!(edit_distance(b"kitten", b"sitting"), 3);
+    }
+
+    #[test]
+    fn test_data_source_mixed() {
+        let tx = test_tx_with_outputs(
+            vec![
+                test_multisig_script(TEST_MESSAGE),
+                test_op_return_script(TEST_MESSAGE),
+            ],
+            Witness::new(),
+        );
+        let parsed = test_parsed_vouts(&tx);
+        assert_eq!(parsed.data_source, Some(DataSource::Mixed));
+    }
+
+    #[test]
+    fn test_data_source_none_without_data() {
+        let p2pkh = ScriptBuf::new_p2pkh(&bitcoin::PubkeyHash::from_byte_array(test_h160_hash(5)));
+        let tx = test_tx_with_outputs(vec![p2pkh], Witness::new());
+        let parsed = test_parsed_vouts(&tx);
+        assert_eq!(parsed.destinations.len(), 1);
+        assert_eq!(parsed.data_source, None);
+    }
+
+    #[test]
+    fn test_parse_transaction_data_before_destination() {
+        let p2pkh = ScriptBuf::new_p2pkh(&bitcoin::PubkeyHash::from_byte_array(test_h160_hash(5)));
+        let tx = test_tx_with_outputs(
+            vec![test_op_return_script(TEST_MESSAGE), p2pkh],
+            Witness::new(),
+        );
+
+        let parsed = test_parsed_vouts(&tx);
+        assert_eq!(parsed.data, TEST_MESSAGE);
+        assert_eq!(parsed.destinations.len(), 1);
+        assert_eq!(parsed.btc_amount, 546);
+
+        // Strict ordering stops at the first destination after data.
+        let parsed = parse_transaction(&tx, &test_config(), Height(902000), true)
+            .parsed_vouts
+            .unwrap();
+        assert_eq!(parsed.data, TEST_MESSAGE);
+        assert!(parsed.destinations.is_empty());
+        assert_eq!(parsed.btc_amount, 0);
+    }
+
+    #[test]
+    fn test_parse_transaction_script_sig_data() {
+        let p2pkh = ScriptBuf::new_p2pkh(&bitcoin::PubkeyHash::from_byte_array(test_h160_hash(5)));
+        let mut tx = test_tx_with_outputs(vec![p2pkh], Witness::new());
+        let plaintext = [b"CNTRPRTY".as_slice(), TEST_MESSAGE].concat();
+        tx.input[0].script_sig = bitcoin::script::Builder::new()
+            .push_slice(push_bytes(vec![0x30; 71]))
+            .push_slice(push_bytes(vec![0x02; 33]))
+            .push_slice(push_bytes(arc4_decrypt(&test_key(), &plaintext)))
+            .into_script();
+
+        let parsed = test_parsed_vouts(&tx);
+        assert_eq!(parsed.data, TEST_MESSAGE);
+        assert_eq!(parsed.destinations.len(), 1);
+        assert_eq!(parsed.data_source, Some(DataSource::ScriptSig));
+
+        let mut config = test_config();
+        config.heights.scriptsig_data_start = 400000;
+        config.heights.scriptsig_data_end = 500000;
+        for height in [Height(400000), Height(499999)] {
+            let parsed = parse_transaction(&tx, &config, height, true)
+                .parsed_vouts
+                .unwrap();
+            assert_eq!(parsed.data, TEST_MESSAGE);
+        }
+        // Outside the range the scriptSig is left alone.
+        for height in [Height(399999), Height(500000)] {
+            let parsed = parse_transaction(&tx, &config, height, true)
+                .parsed_vouts
+                .unwrap();
+            assert!(parsed.data.is_empty());
+            assert_eq!(parsed.data_source, None);
+        }
+    }
+
+    #[test]
+    fn test_parse_transaction_plaintext_op_return() {
+        let plaintext = [b"CNTRPRTY".as_slice(), TEST_MESSAGE].concat();
+        let plain_tx = test_tx_with_outputs(
+            vec![ScriptBuf::new_op_return(push_bytes(plaintext))],
+            Witness::new(),
+        );
+
+        let parsed = test_parsed_vouts(&plain_tx);
+        assert_eq!(parsed.data, TEST_MESSAGE);
+        assert_eq!(parsed.data_source, Some(DataSource::OpReturn));
+
+        // Encrypted payloads still decode with the flag set.
+        let tx = test_tx_with_outputs(vec![test_op_return_script(TEST_MESSAGE)], Witness::new());
+        assert_eq!(test_parsed_vouts(&tx).data, TEST_MESSAGE);
+
+        // Before activation the plaintext payload is run through ARC4 and rejected.
+        assert!(
+            parse_transaction(&plain_tx, &test_config(), Height(902000), true)
+                .parsed_vouts
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_transaction_empty_op_return() {
+        let p2pkh = ScriptBuf::new_p2pkh(&bitcoin::PubkeyHash::from_byte_array(test_h160_hash(5)));
+        let empty_op_return = ScriptBuf::new_op_return(push_bytes(vec![]));
+        let tx = test_tx_with_outputs(
+            vec![
+                p2pkh,
+                empty_op_return.clone(),
+                test_op_return_script(TEST_MESSAGE),
+            ],
+            Witness::new(),
+        );
+        let parsed = test_parsed_vouts(&tx);
+        assert_eq!(parsed.destinations.len(), 1);
+        assert_eq!(parsed.data, TEST_MESSAGE);
+        assert_eq!(parsed.potential_dispensers.len(), 3);
+
+        let tx = test_tx_with_outputs(vec![empty_op_return], Witness::new());
+        let parsed = test_parsed_vouts(&tx);
+        assert!(parsed.destinations.is_empty());
+        assert!(parsed.data.is_empty());
+        assert_eq!(parsed.data_source, None);
+
+        let mut config = test_config();
+        let height = Height(900_000);
+        let parsed = parse_transaction(&tx, &config, height, true);
+        assert!(parsed
+            .parsed_vouts
+            .err()
+            .unwrap()
+            .contains("invalid OP_RETURN"));
+
+        config.heights.empty_op_return = height.0;
+        let parsed = parse_transaction(&tx, &config, height, true);
+        assert!(parsed.parsed_vouts.unwrap().data.is_empty());
+    }
+
+    #[test]
+    fn test_parse_transaction_prefix_migration() {
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+        config.prefixes = vec![b"CNTRPRTY".to_vec(), b"XCPNEW".to_vec()];
+        let p2pkh = ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array(test_h160_hash(5)));
+
+        let old_tx = test_tx_with_outputs(
+            vec![test_op_return_script(TEST_MESSAGE), p2pkh.clone()],
+            Witness::new(),
+        );
+        let plaintext = [b"XCPNEW".as_slice(), TEST_MESSAGE].concat();
+        let new_tx = test_tx_with_outputs(
+            vec![
+                ScriptBuf::new_op_return(push_bytes(arc4_decrypt(&test_key(), &plaintext))),
+                p2pkh,
+            ],
+            Witness::new(),
+        );
+
+        for (tx, prefix) in [(&old_tx, b"CNTRPRTY".as_slice()), (&new_tx, b"XCPNEW")] {
+            let parsed = parse_transaction(tx, &config, Height(0), true)
+                .parsed_vouts
+                .unwrap();
+            assert_eq!(parsed.data, TEST_MESSAGE);
+            assert_eq!(parsed.prefix.as_deref(), Some(prefix));
+        }
+
+        // With only the old prefix configured, the new one is not data.
+        config.prefixes.truncate(1);
+        assert!(parse_transaction(&new_tx, &config, Height(0), true)
+            .parsed_vouts
+            .is_err());
+    }
+
+    #[test]
+    fn test_parse_transaction_non_push_op_return() {
+        let p2pkh = ScriptBuf::new_p2pkh(&bitcoin::PubkeyHash::from_byte_array(test_h160_hash(5)));
+        let non_push = ScriptBuf::builder()
+            .push_opcode(OP_RETURN)
+            .push_opcode(OP_PUSHNUM_1)
+            .push_opcode(OP_PUSHNUM_2)
+            .into_script();
+        let tx = test_tx_with_outputs(
+            vec![p2pkh, non_push, test_op_return_script(TEST_MESSAGE)],
+            Witness::new(),
+        );
+
+        let parsed = test_parsed_vouts(&tx);
+        assert_eq!(parsed.destinations.len(), 1);
+        assert_eq!(parsed.data, TEST_MESSAGE);
+        assert_eq!(parsed.potential_dispensers.len(), 3);
+
+        // Before activation the output aborts the transaction.
+        let error = parse_transaction(&tx, &test_config(), Height(902000), true)
+            .parsed_vouts
+            .err()
+            .unwrap();
+        assert!(error.contains("invalid OP_RETURN"));
+    }
+
+    #[test]
+    fn test_multisig_member_address_version() {
+        let pubkeys = [[0x02; 33], [0x03; 33]];
+        let multisig = ScriptBuf::builder()
+            .push_opcode(OP_PUSHNUM_1)
+            .push_slice(pubkeys[0])
+            .push_slice(pubkeys[1])
+            .push_opcode(OP_PUSHNUM_2)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        let tx = test_tx_with_outputs(vec![multisig], Witness::new());
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+        config.multisig_member_address_version = vec![0x30];
+
+        let mut members: Vec<_> = pubkeys
+            .iter()
+            .map(|pubkey| {
+                let hash = ripemd160::Hash::hash(sha256::Hash::hash(pubkey).as_byte_array());
+                b58_encode(&[[0x30].as_slice(), hash.as_byte_array()].concat())
+            })
+            .collect();
+        members.sort();
+        assert!(members.iter().all(|member| member.starts_with('L')));
+        let parsed = parse_transaction(&tx, &config, Height(0), true)
+            .parsed_vouts
+            .unwrap();
+        assert_eq!(
+            parsed.destinations,
+            vec![format!("1_{}_{}_2", members[0], members[1])]
+        );
+    }
+
+    #[test]
+    fn test_parse_transaction_p2pk() {
+        // The secp256k1 generator point, compressed.
+        let pubkey: bitcoin::PublicKey =
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"
+                .parse()
+                .unwrap();
+        let tx = test_tx_with_outputs(
+            vec![
+                ScriptBuf::new_p2pk(&pubkey),
+                test_op_return_script(TEST_MESSAGE),
+            ],
+            Witness::new(),
+        );
+
+        let parsed = test_parsed_vouts(&tx);
+        let p2pkh = test_tx_with_outputs(
+            vec![ScriptBuf::new_p2pkh(&pubkey.pubkey_hash())],
+            Witness::new(),
+        );
+        assert_eq!(parsed.destinations, test_parsed_vouts(&p2pkh).destinations);
+        assert_eq!(parsed.data, TEST_MESSAGE);
+
+        // Before activation the output aborts the transaction.
+        let error = parse_transaction(&tx, &test_config(), Height(902000), true)
+            .parsed_vouts
+            .err()
+            .unwrap();
+        assert!(error.contains("invalid OP_CHECKSIG"));
+    }
+
+    #[test]
+    fn test_parse_vout_verbose_errors() {
+        let vout = TxOut {
+            value: Amount::from_sat(546),
+            script_pubkey: ScriptBuf::builder()
+                .push_opcode(bitcoin::opcodes::all::OP_NOP)
+                .push_opcode(bitcoin::opcodes::all::OP_DROP)
+                .into_script(),
+        };
+        let mut config = test_config();
+
+        let error = parse_vout(&config, test_key(), Height(0), "".into(), 0, &vout)
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(!error.contains("asm"));
+
+        config.verbose_parse_errors = true;
+        let error = parse_vout(&config, test_key(), Height(0), "".into(), 0, &vout)
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains("Unrecognized output type"));
+        assert!(error.contains("script: 6175"));
+        assert!(error.contains("asm: OP_NOP OP_DROP"));
+    }
+
+    #[test]
+    fn test_vin_output_is_segwit_activation() {
+        let config = test_config();
+        let activation = config.heights.fix_is_segwit;
+        let p2pkh = ScriptBuf::new_p2pkh(&bitcoin::PubkeyHash::from_byte_array(test_h160_hash(1)));
+        let p2wpkh =
+            ScriptBuf::new_p2wpkh(&bitcoin::WPubkeyHash::from_byte_array(test_h160_hash(2)));
+        let witness_tx = test_tx_with_outputs(
+            vec![p2pkh.clone(), p2wpkh.clone()],
+            Witness::from_slice(&[vec![0x01; 64]]),
+        );
+        let legacy_tx = test_tx_with_outputs(vec![p2pkh, p2wpkh], Witness::new());
+
+        let is_segwit = |tx: &Transaction, vout: usize, height: u32| {
+            vin_output(&config, Height(height), &tx.input[0], tx, vout)
+                .unwrap()
+                .is_segwit
+        };
+
+        // Before activation every output of a witness transaction is segwit.
+        assert!(is_segwit(&witness_tx, 0, activation - 1));
+        assert!(is_segwit(&witness_tx, 1, activation - 1));
+        assert!(!is_segwit(&legacy_tx, 0, activation - 1));
+        assert!(!is_segwit(&legacy_tx, 1, activation - 1));
+
+        // From activation only witness program outputs are.
+        assert!(!is_segwit(&witness_tx, 0, activation));
+        assert!(is_segwit(&witness_tx, 1, activation));
+        assert!(!is_segwit(&legacy_tx, 0, activation));
+        assert!(is_segwit(&legacy_tx, 1, activation));
+
+        assert!(vin_output(
+            &config,
+            Height(activation),
+            &legacy_tx.input[0],
+            &legacy_tx,
+            2
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_vin_output_redeem_script_type() {
+        let config = test_config();
+        let p2sh_spend = |redeem_script: &ScriptBuf, script_sig: ScriptBuf, witness: Witness| {
+            let prev_tx = test_tx_with_outputs(
+                vec![ScriptBuf::new_p2sh(&redeem_script.script_hash())],
+                Witness::new(),
+            );
+            let mut tx = test_tx_with_outputs(vec![], witness);
+            tx.input[0].script_sig = script_sig;
+            vin_output(&config, Height(0), &tx.input[0], &prev_tx, 0)
+                .unwrap()
+                .redeem_script_type
+        };
+
+        // P2SH-P2WPKH: the scriptSig only pushes the v0 program, signatures are in the witness.
+        let p2wpkh =
+            ScriptBuf::new_p2wpkh(&bitcoin::WPubkeyHash::from_byte_array(test_h160_hash(2)));
+        let script_sig = ScriptBuf::builder()
+            .push_slice(push_bytes(p2wpkh.to_bytes()))
+            .into_script();
+        let witness = Witness::from_slice(&[vec![0x30; 71], vec![0x02; 33]]);
+        assert_eq!(
+            p2sh_spend(&p2wpkh, script_sig, witness),
+            Some(ScriptType::P2wpkh)
+        );
+
+        // P2SH multisig: OP_0, the signatures, then the redeem script.
+        let multisig = test_multisig_script(TEST_MESSAGE);
+        let script_sig = ScriptBuf::builder()
+            .push_opcode(OP_FALSE)
+            .push_slice(push_bytes(vec![0x30; 71]))
+            .push_slice(push_bytes(multisig.to_bytes()))
+            .into_script();
+        assert_eq!(
+            p2sh_spend(&multisig, script_sig, Witness::new()),
+            Some(ScriptType::Multisig)
+        );
+
+        // Only P2SH prev-outputs get a redeem script type.
+        let p2pkh = ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array(test_h160_hash(1)));
+        let p2pkh_tx = test_tx_with_outputs(vec![p2pkh], Witness::new());
+        let mut tx = test_tx_with_outputs(vec![], Witness::new());
+        tx.input[0].script_sig = ScriptBuf::builder()
+            .push_slice(push_bytes(vec![0x30; 71]))
+            .push_slice(push_bytes(vec![0x02; 33]))
+            .into_script();
+        assert_eq!(
+            vin_output(&config, Height(0), &tx.input[0], &p2pkh_tx, 0)
+                .unwrap()
+                .redeem_script_type,
+            None
+        );
+    }
+
+    #[test]
+    fn test_counterparty_tx_hash_matrix() {
+        let config = test_config();
+        let activation = config.heights.correct_segwit_txids;
+        let p2pkh = ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array(test_h160_hash(5)));
+        let segwit_tx = test_tx_with_outputs(
+            vec![p2pkh.clone()],
+            Witness::from_slice(&[vec![0x30; 71], vec![0x02; 33]]),
+        );
+        let legacy_tx = test_tx_with_outputs(vec![p2pkh], Witness::new());
+
+        // The two differ only in the witness, so they share a txid.
+        let txid = "c0257112a3a683150b7b16213ce8f9370d895e6dfbad56943ee626e0143c0815";
+        let wtxid = "f5782ca84e3c90161cb452c2ab65930a430189f7d34b41dc432b6efd8eadd0f9";
+        assert_eq!(segwit_tx.compute_txid().to_string(), txid);
+        assert_eq!(segwit_tx.compute_wtxid().to_string(), wtxid);
+        assert_eq!(legacy_tx.compute_txid().to_string(), txid);
+
+        let cases = [
+            (&segwit_tx, activation - 1, true, wtxid),
+            (&segwit_tx, activation, true, txid),
+            (&legacy_tx, activation - 1, false, txid),
+            (&legacy_tx, activation, false, txid),
+        ];
+        for (tx, height, segwit, tx_hash) in cases {
+            assert_eq!(
+                counterparty_tx_hash(tx, &config, Height(height)),
+                tx_hash,
+                "height {}",
+                height
+            );
+            let parsed = parse_transaction(tx, &config, Height(height), false);
+            assert_eq!(parsed.segwit, segwit);
+            assert_eq!(parsed.tx_id, tx.compute_txid().to_string());
+            assert_eq!(parsed.tx_hash, tx_hash);
+        }
+    }
+
+    #[test]
+    fn test_segwit_marker_without_witnesses() {
+        let p2pkh = ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array(test_h160_hash(5)));
+        let tx = test_tx_with_outputs(vec![p2pkh], Witness::new());
+        let legacy = serialize(&tx);
+        // version, marker and flag, the legacy body, one empty witness, lock time
+        let segwit = [
+            &legacy[..4],
+            &[0x00, 0x01],
+            &legacy[4..legacy.len() - 4],
+            &[0x00],
+            &legacy[legacy.len() - 4..],
+        ]
+        .concat();
+
+        assert!(
+            bitcoin::consensus::deserialize::<bitcoin::Transaction>(&segwit)
+                .unwrap_err()
+                .to_string()
+                .contains("witness flag set but no witnesses present")
+        );
+
+        let parsed = parse_transaction(&tx, &test_config(), Height(0), false);
+        assert!(!parsed.segwit);
+        assert_eq!(
+            parsed.tx_hash,
+            "c0257112a3a683150b7b16213ce8f9370d895e6dfbad56943ee626e0143c0815"
+        );
+        assert_eq!(parsed.tx_hash, tx.compute_txid().to_string());
+    }
+
+    #[test]
+    fn test_parse_transaction_output_total_near_i64_max() {
+        let p2pkh = ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array(test_h160_hash(5)));
+        let mut tx = test_tx_with_outputs(vec![p2pkh.clone(), p2pkh], Witness::new());
+        let half = i64::MAX as u64 / 2;
+        tx.output[0].value = Amount::from_sat(half);
+        tx.output[1].value = Amount::from_sat(half + 1);
+
+        let parsed = test_parsed_vouts(&tx);
+        assert_eq!(parsed.fee, -i64::MAX);
+        assert_eq!(parsed.btc_amount, i64::MAX);
+
+        tx.output[1].value = Amount::from_sat(half + 2);
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+        assert!(matches!(
+            parse_transaction(&tx, &config, Height(0), true).parsed_vouts,
+            Err(ref e) if e.contains("Total output value out of range")
+        ));
+    }
+
+    #[test]
+    fn test_arc4_key_all_zeros_outpoint() {
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+        let plaintext = [b"CNTRPRTY".as_slice(), TEST_MESSAGE].concat();
+        let encrypted = arc4_decrypt(&[0; 32], &plaintext);
+        let mut tx = test_tx_with_outputs(
+            vec![ScriptBuf::new_op_return(push_bytes(encrypted.clone()))],
+            Witness::new(),
+        );
+        tx.input[0].previous_output = OutPoint {
+            txid: Txid::all_zeros(),
+            vout: 0,
+        };
+
+        assert_eq!(arc4_key(&tx), vec![0; 32]);
+        // The all-zeros keystream is fixed, so the ciphertext is too.
+        assert_eq!(
+            encrypted[..8],
+            [0x9d, 0x56, 0xdd, 0x13, 0xf3, 0x65, 0x09, 0x63]
+        );
+        let parsed = parse_transaction(&tx, &config, Height(0), true);
+        assert_eq!(parsed.parsed_vouts.as_ref().unwrap().data, TEST_MESSAGE);
+        assert!(parsed == parse_transaction(&tx, &config, Height(0), true));
+        assert_eq!(parsed.warnings.len(), 1);
+
+        // A coinbase always has the null outpoint and is not flagged.
+        tx.input[0].previous_output = OutPoint::null();
+        assert!(tx.is_coinbase());
+        assert_eq!(arc4_key(&tx), vec![0; 32]);
+        assert!(parse_transaction(&tx, &config, Height(0), true)
+            .warnings
+            .is_empty());
+    }
+
+    #[test]
+    fn test_parse_transaction_without_inputs() {
+        let mut tx =
+            test_tx_with_outputs(vec![test_op_return_script(TEST_MESSAGE)], Witness::new());
+        tx.input.clear();
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+
+        let parsed = parse_transaction(&tx, &config, Height(0), true);
+        assert_eq!(parsed.vout.len(), 1);
+        assert!(matches!(
+            parsed.parsed_vouts,
+            Err(ref e) if e.contains("no inputs for key derivation")
+        ));
+    }
+
+    #[test]
+    fn test_parse_transaction_max_outputs_parsed() {
+        let p2pkh = ScriptBuf::new_p2pkh(&bitcoin::PubkeyHash::from_byte_array(test_h160_hash(5)));
+        let tx = test_tx_with_outputs(vec![p2pkh; 3], Witness::new());
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+
+        config.max_outputs_parsed = Some(3);
+        assert!(parse_transaction(&tx, &config, Height(0), true)
+            .parsed_vouts
+            .is_ok());
+
+        config.max_outputs_parsed = Some(2);
+        let parsed = parse_transaction(&tx, &config, Height(0), true);
+        assert_eq!(parsed.vout.len(), 3);
+        assert!(matches!(
+            parsed.parsed_vouts,
+            Err(ref e) if e.contains("Too many outputs to parse: 3")
+        ));
+    }
+
+    #[test]
+    fn test_parse_transaction_burn() {
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+        let unspendable = bitcoin::base58::decode_check(&config.unspendable()).unwrap();
+        let burn_script =
+            ScriptBuf::new_p2pkh(&bitcoin::PubkeyHash::from_slice(&unspendable[1..]).unwrap());
+        let mut tx = test_tx_with_outputs(vec![burn_script], Witness::new());
+        tx.output[0].value = Amount::from_sat(5000);
+        let parsed = parse_transaction(&tx, &config, Height(0), true);
+        assert!(parsed.is_burn);
+        assert_eq!(parsed.burn_amount, Some(5000));
+
+        // The unspendable address is normalized along with the parsed destinations.
+        let mut normalized_config = config.clone();
+        normalized_config.address_normalizer = Some(AddressNormalizer(Arc::new(|address| {
+            address.to_uppercase()
+        })));
+        let parsed = parse_transaction(&tx, &normalized_config, Height(0), true);
+        assert_eq!(parsed.burn_amount, Some(5000));
+
+        let p2pkh = ScriptBuf::new_p2pkh(&bitcoin::PubkeyHash::from_byte_array(test_h160_hash(5)));
+        let tx = test_tx_with_outputs(vec![p2pkh], Witness::new());
+        let parsed = parse_transaction(&tx, &config, Height(0), true);
+        assert!(!parsed.is_burn);
+        assert_eq!(parsed.burn_amount, None);
+    }
+
+    #[cfg(feature = "debug-tools")]
+    #[test]
+    fn test_debug_classify_op_return() {
+        let plaintext = [b"CNTRPRTY".as_slice(), TEST_MESSAGE].concat();
+        let script = ScriptBuf::new_op_return(push_bytes(arc4_decrypt(&[0; 32], &plaintext)));
+        let report = debug_classify(&script.to_hex_string(), &test_config(), Height(0));
+        assert!(report.contains("asm: OP_RETURN"));
+        assert!(report.contains("result: data (5 bytes)"));
+    }
+
+    #[cfg(feature = "debug-tools")]
+    #[test]
+    fn test_block_diff() {
+        let p2pkh = ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array(test_h160_hash(5)));
+        let block = test_block(vec![
+            test_tx_with_outputs(vec![p2pkh.clone()], Witness::new()),
+            test_tx_with_outputs(
+                vec![test_op_return_script(TEST_MESSAGE), p2pkh],
+                Witness::new(),
+            ),
+        ]);
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+        let parsed = parse_block(block, &config, Height(0), true).unwrap();
+        assert!(parsed.diff(&parsed).is_empty());
+
+        let mut changed = parsed.clone();
+        if let Ok(parsed_vouts) = changed.transactions[1].parsed_vouts.as_mut() {
+            parsed_vouts.data = b"other".to_vec();
+        }
+        let diffs = parsed.diff(&changed);
+        assert_eq!(
+            diffs,
+            vec![BlockDiff::Changed("tx[1].parsed_vouts.data".into())]
+        );
+        assert_eq!(diffs[0].to_string(), "tx[1].parsed_vouts.data changed");
+
+        changed.transactions.pop();
+        assert_eq!(parsed.diff(&changed), vec![BlockDiff::Removed(1)]);
+    }
+
+    #[test]
+    fn test_address_normalizer() {
+        let multisig = ScriptBuf::builder()
+            .push_opcode(OP_PUSHNUM_1)
+            .push_slice(push_bytes(vec![0x02; 33]))
+            .push_slice(push_bytes(vec![0x03; 33]))
+            .push_opcode(OP_PUSHNUM_2)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        let outputs = [
+            ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array(test_h160_hash(1))),
+            ScriptBuf::new_p2sh(&bitcoin::ScriptHash::from_byte_array(test_h160_hash(2))),
+            test_witness_program_output(0, &test_h160_hash(3)).script_pubkey,
+            test_witness_program_output(0x51, &test_sha256_hash(4)).script_pubkey,
+            multisig,
+        ]
+        .map(|script_pubkey| TxOut {
+            value: Amount::from_sat(546),
+            script_pubkey,
+        });
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+        let mut normalized_config = config.clone();
+        normalized_config.address_normalizer = Some(AddressNormalizer(Arc::new(|address| {
+            address.to_uppercase()
+        })));
+
+        let uppercase = |output| match output {
+            ParseOutput::Destination(destination) => {
+                ParseOutput::Destination(destination.to_uppercase())
+            }
+            output => output,
+        };
+        for (vi, vout) in outputs.iter().enumerate() {
+            let (output, dispenser) =
+                parse_vout(&config, test_key(), Height(0), "".into(), vi, vout).unwrap();
+            let (normalized, normalized_dispenser) = parse_vout(
+                &normalized_config,
+                test_key(),
+                Height(0),
+                "".into(),
+                vi,
+                vout,
+            )
+            .unwrap();
+            assert!(output.is_destination());
+            assert_ne!(normalized, output);
+            assert_eq!(normalized, uppercase(output));
+            assert_eq!(
+                normalized_dispenser.unwrap().destination,
+                dispenser.unwrap().destination.map(|d| d.to_uppercase())
+            );
+        }
+    }
+
+    #[test]
+    fn test_classify_outputs() {
+        let encrypted = |message: &[u8]| {
+            let plaintext = [b"CNTRPRTY".as_slice(), message].concat();
+            ScriptBuf::new_op_return(push_bytes(arc4_decrypt(&[0; 32], &plaintext)))
+        };
+        let long_message = [TEST_MESSAGE; 8].concat();
+        let outputs: Vec<_> = [
+            encrypted(TEST_MESSAGE),
+            test_multisig_script(TEST_MESSAGE),
+            ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array(test_h160_hash(5))),
+            encrypted(&long_message),
+            ScriptBuf::new_op_return(push_bytes(vec![])),
+            ScriptBuf::from_bytes(vec![0xff]),
+            encrypted(TEST_MESSAGE),
+        ]
+        .into_iter()
+        .map(|script_pubkey| TxOut {
+            value: Amount::from_sat(546),
+            script_pubkey,
+        })
+        .collect();
+        let mut config = test_config();
+        config.enable_all_protocol_changes = true;
+
+        let classified = classify_outputs(&config, Height(0), &outputs);
+        assert_eq!(classified.len(), outputs.len());
+        for (vi, (result, vout)) in classified.iter().zip(&outputs).enumerate() {
+            let single = parse_vout(&config, vec![0; 32], Height(0), String::new(), vi, vout)
+                .map(|(output, _)| output);
+            assert_eq!(
+                result.as_ref().map_err(|e| e.to_string()),
+                single.as_ref().map_err(|e| e.to_string())
+            );
+        }
+
+        // The keystream grows for the longer payload and is reused for the shorter ones.
+        for (vi, message) in [(0, TEST_MESSAGE), (3, &long_message), (6, TEST_MESSAGE)] {
+            assert_eq!(
+                classified[vi].as_ref().unwrap(),
+                &ParseOutput::Data(message.to_vec(), Some(0))
+            );
+        }
+        // Encoded under another transaction's key, the pubkeys read as a destination.
+        assert!(classified[1].as_ref().unwrap().is_destination());
+        assert!(classified[2].as_ref().unwrap().is_destination());
+        assert_eq!(classified[4].as_ref().unwrap(), &ParseOutput::Skip);
+        assert!(classified[5].is_err());
+        assert!(classify_outputs(&config, Height(0), &[]).is_empty());
+    }
+
+    #[cfg(feature = "debug-tools")]
+    #[test]
+    fn test_classify_output_verbose_multisig() {
+        let vout = TxOut {
+            value: bitcoin::Amount::from_sat(546),
+            script_pubkey: test_multisig_script(TEST_MESSAGE),
+        };
+        let classification =
+            classify_output_verbose(&test_config(), test_key(), Height(0), "".into(), 0, &vout)
+                .unwrap();
+        assert_eq!(
+            classification.chosen,
+            ParseOutput::Data(TEST_MESSAGE.to_vec(), Some(0))
+        );
+        assert_eq!(classification.alternatives.len(), 2);
+
+        let data = &classification.alternatives[0];
+        assert!(data.accepted);
+        assert_eq!(data.output, classification.chosen);
+
+        let destination = &classification.alternatives[1];
+        assert!(!destination.accepted);
+        assert!(matches!(
+            destination.output,
+            ParseOutput::Destination(ref d) if d.starts_with("1_") && d.ends_with("_3")
+        ));
+
+        // Under another key the prefix check fails and the destination wins.
+        let classification =
+            classify_output_verbose(&test_config(), vec![0; 32], Height(0), "".into(), 0, &vout)
+                .unwrap();
+        assert!(classification.chosen.is_destination());
+        assert!(!classification.alternatives[0].accepted);
+        assert!(classification.alternatives[1].accepted);
+        assert_eq!(classification.alternatives[1].output, classification.chosen);
+    }
 }
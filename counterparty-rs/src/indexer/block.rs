@@ -1,18 +1,31 @@
+use std::collections::HashSet;
+#[cfg(feature = "debug-tools")]
+use std::fmt;
+
 use super::config::Config;
+use super::types::error::Error;
+use super::types::height::Height;
+use bitcoin::{opcodes::all::OP_CHECKMULTISIG, script::Instruction::Op, Script};
 use pyo3::{
     exceptions::PyException,
     types::{PyAnyMethods, PyBytes, PyDict, PyTuple},
     IntoPy, PyObject, Python,
 };
+#[cfg(feature = "block-serde")]
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "block-serde", derive(Serialize, Deserialize))]
 pub struct VinOutput {
     pub script_pub_key: Vec<u8>,
     pub value: u64,
     pub is_segwit: bool,
+    // Set when the spent output is P2SH: the type of the redeem script the input revealed.
+    pub redeem_script_type: Option<ScriptType>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "block-serde", derive(Serialize, Deserialize))]
 pub struct Vin {
     pub hash: String, // prev output txid
     pub n: u32,       // prev output index
@@ -41,6 +54,12 @@ impl IntoPy<PyObject> for Vin {
                 .unwrap();
             info_dict.set_item("value", info.value).unwrap();
             info_dict.set_item("is_segwit", info.is_segwit).unwrap();
+            info_dict
+                .set_item(
+                    "redeem_script_type",
+                    info.redeem_script_type.map(|t| t.as_str()),
+                )
+                .unwrap();
             dict.set_item("info", info_dict).unwrap();
         } else {
             dict.set_item("info", py.None()).unwrap();
@@ -50,7 +69,8 @@ impl IntoPy<PyObject> for Vin {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "block-serde", derive(Serialize, Deserialize))]
 pub struct Vout {
     pub value: u64,
     pub script_pub_key: Vec<u8>,
@@ -72,7 +92,27 @@ impl IntoPy<PyObject> for Vout {
     }
 }
 
-#[derive(Clone)]
+// An output `parse_vout` rejected, reported by `parse_block_verbose`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VoutWarning {
+    pub txid: String,
+    pub vout: usize,
+    pub reason: String,
+}
+
+impl IntoPy<PyObject> for VoutWarning {
+    #[allow(clippy::unwrap_used)]
+    fn into_py(self, py: Python<'_>) -> PyObject {
+        let dict = PyDict::new_bound(py);
+        dict.set_item("txid", self.txid).unwrap();
+        dict.set_item("vout", self.vout).unwrap();
+        dict.set_item("reason", self.reason).unwrap();
+        dict.unbind().into()
+    }
+}
+
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "block-serde", derive(Serialize, Deserialize))]
 pub struct PotentialDispenser {
     pub destination: Option<String>,
     pub value: Option<u64>,
@@ -84,14 +124,138 @@ impl IntoPy<PyObject> for PotentialDispenser {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "block-serde", derive(Serialize, Deserialize))]
+pub enum DataSource {
+    OpReturn,
+    Multisig,
+    Checksig,
+    TaprootReveal,
+    P2wshReveal,
+    ScriptSig,
+    Mixed,
+}
+
+impl DataSource {
+    pub fn combine(self, previous: Option<DataSource>) -> DataSource {
+        match previous {
+            Some(previous) if previous != self => DataSource::Mixed,
+            _ => self,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DataSource::OpReturn => "op_return",
+            DataSource::Multisig => "multisig",
+            DataSource::Checksig => "checksig",
+            DataSource::TaprootReveal => "taproot_reveal",
+            DataSource::P2wshReveal => "p2wsh_reveal",
+            DataSource::ScriptSig => "scriptsig",
+            DataSource::Mixed => "mixed",
+        }
+    }
+}
+
+// The kind of output a destination was derived from. Segwit v0 programs are split by
+// length since P2WSH destinations are handled differently from P2WPKH ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "block-serde", derive(Serialize, Deserialize))]
+pub enum ScriptType {
+    P2pkh,
+    P2pk,
+    P2sh,
+    Multisig,
+    P2wpkh,
+    P2wsh,
+    P2tr,
+    FutureWitness,
+    Other,
+}
+
+impl ScriptType {
+    pub fn from_script(script: &Script) -> ScriptType {
+        if script.is_p2pkh() {
+            ScriptType::P2pkh
+        } else if script.is_p2pk() {
+            ScriptType::P2pk
+        } else if script.is_p2sh() {
+            ScriptType::P2sh
+        } else if script.instructions().last() == Some(Ok(Op(OP_CHECKMULTISIG))) {
+            ScriptType::Multisig
+        } else if script.is_p2wpkh() {
+            ScriptType::P2wpkh
+        } else if script.is_p2wsh() {
+            ScriptType::P2wsh
+        } else if script.is_p2tr() {
+            ScriptType::P2tr
+        } else if script.is_witness_program() {
+            ScriptType::FutureWitness
+        } else {
+            ScriptType::Other
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScriptType::P2pkh => "p2pkh",
+            ScriptType::P2pk => "p2pk",
+            ScriptType::P2sh => "p2sh",
+            ScriptType::Multisig => "multisig",
+            ScriptType::P2wpkh => "p2wpkh",
+            ScriptType::P2wsh => "p2wsh",
+            ScriptType::P2tr => "p2tr",
+            ScriptType::FutureWitness => "future_witness",
+            ScriptType::Other => "other",
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "block-serde", derive(Serialize, Deserialize))]
 pub struct ParsedVouts {
     pub destinations: Vec<String>,
+    // Parallel to `destinations`.
+    pub destination_script_types: Vec<ScriptType>,
     pub btc_amount: i64,
+    // The negated total value of the outputs parsed. Inputs are not known here, so the
+    // caller adds them to get the actual fee.
     pub fee: i64,
     pub data: Vec<u8>,
     pub potential_dispensers: Vec<Option<PotentialDispenser>>,
     pub is_reveal_tx: bool,
+    pub data_source: Option<DataSource>,
+    // The configured prefix the data was found under, when it carried one.
+    pub prefix: Option<Vec<u8>>,
+    // Set when an OP_RETURN carries exactly the bare `CNTRPRTY` marker, whether or not
+    // the marker counts at this height. For analytics only.
+    pub has_bare_marker: bool,
+    // `data` split on the configured message separator; the whole of `data` when none
+    // is set.
+    pub messages: Vec<Vec<u8>>,
+}
+
+impl ParsedVouts {
+    // The message type id leading `data`. Reveal inscriptions are repacked with the type
+    // id taken out of the CBOR metadata and put in front, so this holds for every source.
+    pub fn message_type(&self) -> Option<u8> {
+        self.data.first().copied()
+    }
+
+    // The potential dispensers that have a destination, first occurrence of each
+    // (destination, value) pair only, in output order.
+    pub fn dispenser_candidates(&self) -> Vec<&PotentialDispenser> {
+        let mut seen = HashSet::new();
+        self.potential_dispensers
+            .iter()
+            .flatten()
+            .filter(|pd| {
+                pd.destination
+                    .as_deref()
+                    .is_some_and(|destination| seen.insert((destination, pd.value)))
+            })
+            .collect()
+    }
 }
 
 impl IntoPy<PyObject> for ParsedVouts {
@@ -116,7 +280,8 @@ impl IntoPy<PyObject> for ParsedVouts {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "block-serde", derive(Serialize, Deserialize))]
 pub struct Transaction {
     pub version: i32,
     pub segwit: bool,
@@ -128,11 +293,183 @@ pub struct Transaction {
     pub parsed_vouts: Result<ParsedVouts, String>,
     pub vin: Vec<Vin>,
     pub vout: Vec<Vout>,
+    // Set when the unspendable address is the sole destination (proof-of-burn).
+    pub is_burn: bool,
+    pub burn_amount: Option<i64>,
+    // Hex x-only internal key from the control block of a taproot reveal.
+    pub taproot_internal_key: Option<String>,
+    // Problems that did not fail parsing but left the result incomplete.
+    pub warnings: Vec<String>,
+    // Hex witness commitment (BIP141) of a segwit coinbase.
+    pub witness_commitment: Option<String>,
+    // An OP_RETURN push that is close to, but not, the `CNTRPRTY` reveal marker. Such a
+    // push never marks a reveal, and usually fails parsing, so it is kept here to tell
+    // why. For diagnostics only.
+    pub marker_variant: Option<Vec<u8>>,
+}
+
+// The witness of an input, its items labelled by the spend they look like. The spent
+// output isn't known here, so the pattern is read from the items alone: a DER signature
+// and a compressed pubkey is P2WPKH; a lone 64 or 65 byte signature is a taproot key-path
+// spend; a tapscript control block last is a script-path spend; and anything else ending
+// in a decodable script is P2WSH. Taproot spends may carry an annex, the last item when
+// it starts with 0x50.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WitnessItems {
+    P2wpkh {
+        signature: Vec<u8>,
+        pubkey: Vec<u8>,
+    },
+    P2wsh {
+        items: Vec<Vec<u8>>,
+        witness_script: Vec<u8>,
+    },
+    P2trKeyPath {
+        signature: Vec<u8>,
+        annex: Option<Vec<u8>>,
+    },
+    P2trScriptPath {
+        items: Vec<Vec<u8>>,
+        script: Vec<u8>,
+        control_block: Vec<u8>,
+        annex: Option<Vec<u8>>,
+    },
+    Unknown(Vec<Vec<u8>>),
+}
+
+impl WitnessItems {
+    pub fn from_items(mut items: Vec<Vec<u8>>) -> WitnessItems {
+        if let [signature, pubkey] = items.as_slice() {
+            if signature.first() == Some(&0x30)
+                && pubkey.len() == 33
+                && matches!(pubkey[0], 0x02 | 0x03)
+            {
+                return WitnessItems::P2wpkh {
+                    signature: signature.clone(),
+                    pubkey: pubkey.clone(),
+                };
+            }
+        }
+
+        let original = items.clone();
+        let annex = match items.as_slice() {
+            [_, .., last] if last.first() == Some(&0x50) => items.pop(),
+            _ => None,
+        };
+        if let [signature] = items.as_slice() {
+            if matches!(signature.len(), 64 | 65) {
+                return WitnessItems::P2trKeyPath {
+                    signature: signature.clone(),
+                    annex,
+                };
+            }
+        }
+        if items.len() >= 2 && is_tapscript_control_block(&items[items.len() - 1]) {
+            let control_block = items.pop().unwrap_or_default();
+            let script = items.pop().unwrap_or_default();
+            return WitnessItems::P2trScriptPath {
+                items,
+                script,
+                control_block,
+                annex,
+            };
+        }
+
+        let mut items = original;
+        match items.last() {
+            Some(last) if Script::from_bytes(last).instructions().all(|i| i.is_ok()) => {
+                let witness_script = items.pop().unwrap_or_default();
+                WitnessItems::P2wsh {
+                    items,
+                    witness_script,
+                }
+            }
+            _ => WitnessItems::Unknown(items),
+        }
+    }
+}
+
+impl IntoPy<PyObject> for WitnessItems {
+    #[allow(clippy::unwrap_used)]
+    fn into_py(self, py: Python<'_>) -> PyObject {
+        let bytes = |b: Vec<u8>| PyBytes::new_bound(py, &b).into_py(py);
+        let list = |items: Vec<Vec<u8>>| items.into_iter().map(bytes).collect::<Vec<_>>();
+        let dict = PyDict::new_bound(py);
+        match self {
+            WitnessItems::P2wpkh { signature, pubkey } => {
+                dict.set_item("type", "p2wpkh").unwrap();
+                dict.set_item("signature", bytes(signature)).unwrap();
+                dict.set_item("pubkey", bytes(pubkey)).unwrap();
+            }
+            WitnessItems::P2wsh {
+                items,
+                witness_script,
+            } => {
+                dict.set_item("type", "p2wsh").unwrap();
+                dict.set_item("items", list(items)).unwrap();
+                dict.set_item("witness_script", bytes(witness_script))
+                    .unwrap();
+            }
+            WitnessItems::P2trKeyPath { signature, annex } => {
+                dict.set_item("type", "p2tr_key_path").unwrap();
+                dict.set_item("signature", bytes(signature)).unwrap();
+                dict.set_item("annex", annex.map(bytes)).unwrap();
+            }
+            WitnessItems::P2trScriptPath {
+                items,
+                script,
+                control_block,
+                annex,
+            } => {
+                dict.set_item("type", "p2tr_script_path").unwrap();
+                dict.set_item("items", list(items)).unwrap();
+                dict.set_item("script", bytes(script)).unwrap();
+                dict.set_item("control_block", bytes(control_block))
+                    .unwrap();
+                dict.set_item("annex", annex.map(bytes)).unwrap();
+            }
+            WitnessItems::Unknown(items) => {
+                dict.set_item("type", "unknown").unwrap();
+                dict.set_item("items", list(items)).unwrap();
+            }
+        }
+        dict.unbind().into()
+    }
+}
+
+// The internal key then up to 128 merkle path hashes, after a tapscript leaf version
+// byte carrying the output key parity.
+fn is_tapscript_control_block(bytes: &[u8]) -> bool {
+    bytes.len() >= 33
+        && (bytes.len() - 33).is_multiple_of(32)
+        && (bytes.len() - 33) / 32 <= 128
+        && bytes[0] & 0xfe == 0xc0
+}
+
+impl Transaction {
+    // The witness of input `i`, decoded from `vtxinwit`. None when there is no such
+    // input or it has no witness.
+    pub fn witness_for_input(&self, i: usize) -> Option<WitnessItems> {
+        let items = self
+            .vtxinwit
+            .get(i)?
+            .iter()
+            .map(hex::decode)
+            .collect::<Result<Vec<_>, _>>()
+            .ok()?;
+        if items.is_empty() {
+            return None;
+        }
+        Some(WitnessItems::from_items(items))
+    }
 }
 
 impl IntoPy<PyObject> for Transaction {
     #[allow(clippy::unwrap_used)]
     fn into_py(self, py: Python<'_>) -> PyObject {
+        let witnesses = (0..self.vin.len())
+            .map(|i| self.witness_for_input(i).into_py(py))
+            .collect::<Vec<_>>();
         let dict = PyDict::new_bound(py);
         dict.set_item("version", self.version).unwrap();
         dict.set_item("segwit", self.segwit).unwrap();
@@ -141,6 +478,41 @@ impl IntoPy<PyObject> for Transaction {
         dict.set_item("tx_id", self.tx_id).unwrap();
         dict.set_item("tx_hash", self.tx_hash).unwrap();
         dict.set_item("vtxinwit", self.vtxinwit).unwrap();
+        dict.set_item("witnesses", witnesses).unwrap();
+
+        let data_source = self
+            .parsed_vouts
+            .as_ref()
+            .ok()
+            .and_then(|p| p.data_source)
+            .map(|s| s.as_str());
+        dict.set_item("data_source", data_source).unwrap();
+        let messages = self.parsed_vouts.as_ref().ok().map(|p| {
+            p.messages
+                .iter()
+                .map(|message| PyBytes::new_bound(py, message).into_py(py))
+                .collect::<Vec<_>>()
+        });
+        dict.set_item("messages", messages).unwrap();
+        let destination_script_types = self.parsed_vouts.as_ref().ok().map(|p| {
+            p.destination_script_types
+                .iter()
+                .map(|t| t.as_str())
+                .collect::<Vec<_>>()
+        });
+        dict.set_item("destination_script_types", destination_script_types)
+            .unwrap();
+        dict.set_item("is_burn", self.is_burn).unwrap();
+        dict.set_item("burn_amount", self.burn_amount).unwrap();
+        dict.set_item("taproot_internal_key", self.taproot_internal_key)
+            .unwrap();
+        dict.set_item("warnings", self.warnings).unwrap();
+        dict.set_item("witness_commitment", self.witness_commitment)
+            .unwrap();
+        let marker_variant = self
+            .marker_variant
+            .map(|variant| PyBytes::new_bound(py, &variant).into_py(py));
+        dict.set_item("marker_variant", marker_variant).unwrap();
 
         match self.parsed_vouts {
             Ok(parsed_vouts) => {
@@ -164,7 +536,8 @@ impl IntoPy<PyObject> for Transaction {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "block-serde", derive(Serialize, Deserialize))]
 pub struct Block {
     pub height: u32,
     pub version: i32,
@@ -178,6 +551,157 @@ pub struct Block {
     pub transactions: Vec<Transaction>,
 }
 
+impl Block {
+    // Every destination credited in the block, first occurrence of each only, in
+    // transaction then output order. Transactions whose outputs failed to parse have none.
+    pub fn destinations(&self) -> Vec<&str> {
+        let mut seen = HashSet::new();
+        self.transactions
+            .iter()
+            .filter_map(|tx| tx.parsed_vouts.as_ref().ok())
+            .flat_map(|parsed_vouts| parsed_vouts.destinations.iter())
+            .map(String::as_str)
+            .filter(|destination| seen.insert(*destination))
+            .collect()
+    }
+
+    // The Counterparty data carried by the block, in bytes. Transactions whose outputs
+    // failed to parse count for nothing.
+    pub fn total_data_bytes(&self) -> usize {
+        self.transactions
+            .iter()
+            .filter_map(|tx| tx.parsed_vouts.as_ref().ok())
+            .map(|parsed_vouts| parsed_vouts.data.len())
+            .sum()
+    }
+}
+
+// A difference between two parsed blocks, as found by `Block::diff`. Paths name the
+// field, e.g. `tx[3].parsed_vouts.data`.
+#[cfg(feature = "debug-tools")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BlockDiff {
+    Changed(String),
+    // Transactions at an index only the other block has.
+    Added(usize),
+    Removed(usize),
+}
+
+#[cfg(feature = "debug-tools")]
+impl fmt::Display for BlockDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockDiff::Changed(path) => write!(f, "{} changed", path),
+            BlockDiff::Added(i) => write!(f, "tx[{}] added", i),
+            BlockDiff::Removed(i) => write!(f, "tx[{}] removed", i),
+        }
+    }
+}
+
+// Pushes a `BlockDiff::Changed` for each listed field that differs between `$a` and `$b`.
+#[cfg(feature = "debug-tools")]
+macro_rules! diff_fields {
+    ($diffs:expr, $path:expr, $a:expr, $b:expr, [$($field:ident),* $(,)?]) => {{
+        $(
+            if $a.$field != $b.$field {
+                $diffs.push(BlockDiff::Changed(format!("{}{}", $path, stringify!($field))));
+            }
+        )*
+    }};
+}
+
+#[cfg(feature = "debug-tools")]
+impl Block {
+    // Field by field differences from `self` to `other`, for checking that a parser
+    // change leaves its output alone. Transactions are compared by index; parsed outputs
+    // are compared field by field only when both parsed.
+    pub fn diff(&self, other: &Block) -> Vec<BlockDiff> {
+        let mut diffs = Vec::new();
+        diff_fields!(
+            diffs,
+            "",
+            self,
+            other,
+            [
+                height,
+                version,
+                hash_prev,
+                hash_merkle_root,
+                block_time,
+                bits,
+                nonce,
+                block_hash,
+                transaction_count,
+            ]
+        );
+        let pairs = self.transactions.iter().zip(&other.transactions);
+        for (i, (a, b)) in pairs.enumerate() {
+            let path = format!("tx[{}].", i);
+            diff_fields!(
+                diffs,
+                path,
+                a,
+                b,
+                [
+                    version,
+                    segwit,
+                    coinbase,
+                    lock_time,
+                    tx_id,
+                    tx_hash,
+                    vtxinwit,
+                    vin,
+                    vout,
+                    is_burn,
+                    burn_amount,
+                    taproot_internal_key,
+                    warnings,
+                    witness_commitment,
+                    marker_variant,
+                ]
+            );
+            match (&a.parsed_vouts, &b.parsed_vouts) {
+                (Ok(a), Ok(b)) => diff_fields!(
+                    diffs,
+                    format!("{}parsed_vouts.", path),
+                    a,
+                    b,
+                    [
+                        destinations,
+                        destination_script_types,
+                        btc_amount,
+                        fee,
+                        data,
+                        potential_dispensers,
+                        is_reveal_tx,
+                        data_source,
+                        prefix,
+                        has_bare_marker,
+                        messages,
+                    ]
+                ),
+                (a, b) if a != b => diffs.push(BlockDiff::Changed(path + "parsed_vouts")),
+                _ => {}
+            }
+        }
+        let common = self.transactions.len().min(other.transactions.len());
+        diffs.extend((common..other.transactions.len()).map(BlockDiff::Added));
+        diffs.extend((common..self.transactions.len()).map(BlockDiff::Removed));
+        diffs
+    }
+}
+
+#[cfg(feature = "block-serde")]
+impl Block {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        Ok(serde_cbor::to_vec(self)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        Ok(serde_cbor::from_slice(bytes)?)
+    }
+}
+
 impl IntoPy<PyObject> for Block {
     #[allow(clippy::unwrap_used)]
     fn into_py(self, py: Python<'_>) -> PyObject {
@@ -207,5 +731,5 @@ impl IntoPy<PyObject> for Block {
 }
 
 pub trait ToBlock {
-    fn to_block(&self, config: Config, height: u32) -> Block;
+    fn to_block(&self, config: Config, height: Height) -> Result<Block, Error>;
 }
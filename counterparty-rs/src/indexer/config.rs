@@ -1,12 +1,25 @@
-use std::fmt::Display;
+use std::{env, fmt::Display, fs, str::FromStr, sync::Arc, time::Duration};
 
-use pyo3::{exceptions::PyValueError, types::PyDict, FromPyObject, PyAny, PyErr, PyResult};
-use tracing::level_filters::LevelFilter;
+use bitcoin::BlockHash;
+use pyo3::{
+    exceptions::PyValueError, types::PyDict, FromPyObject, PyAny, PyErr, PyObject, PyResult, Python,
+};
+use tracing::{error, level_filters::LevelFilter};
+
+use super::{
+    rpc_client::{DEFAULT_MAX_BATCH_SIZE, UNIX_SOCKET_SCHEME},
+    types::{error::Error, height::Height},
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Mode {
     Indexer,
     Fetcher,
+    // Bootstrap mode: only block hashes are indexed, from headers, without
+    // downloading transactions.
+    Headers,
+    // Only block hashes and spent outputs are indexed, for consumers of the spend graph.
+    SpendTracker,
 }
 
 impl<'source> FromPyObject<'source> for Mode {
@@ -15,16 +28,52 @@ impl<'source> FromPyObject<'source> for Mode {
         match mode_str.trim().to_lowercase().as_str() {
             "indexer" => Ok(Mode::Indexer),
             "fetcher" => Ok(Mode::Fetcher),
+            "headers" => Ok(Mode::Headers),
+            "spend_tracker" => Ok(Mode::SpendTracker),
             _ => Err(PyErr::new::<PyValueError, _>(
-                "'mode' must be either 'indexer' or 'fetcher'",
+                "'mode' must be one of 'indexer', 'fetcher', 'headers' or 'spend_tracker'",
             )),
         }
     }
 }
 
+// What a `BitcoinClient` request does when every worker is busy and the request queue
+// is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestQueuePolicy {
+    Block,
+    FailFast,
+    Timeout(Duration),
+}
+
+impl<'source> FromPyObject<'source> for RequestQueuePolicy {
+    fn extract(obj: &'source PyAny) -> PyResult<Self> {
+        let policy_str: String = obj.extract()?;
+        match policy_str.trim().to_lowercase().as_str() {
+            "block" => Ok(RequestQueuePolicy::Block),
+            "fail_fast" => Ok(RequestQueuePolicy::FailFast),
+            policy => policy
+                .strip_prefix("timeout:")
+                .and_then(|ms| ms.parse().ok())
+                .map(|ms| RequestQueuePolicy::Timeout(Duration::from_millis(ms)))
+                .ok_or_else(|| {
+                    PyErr::new::<PyValueError, _>(
+                        "'request_queue_policy' must be 'block', 'fail_fast' or 'timeout:<ms>'",
+                    )
+                }),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct LogLevel(LevelFilter);
 
+impl Default for LogLevel {
+    fn default() -> Self {
+        LogLevel(LevelFilter::INFO)
+    }
+}
+
 impl From<LogLevel> for LevelFilter {
     fn from(log_level: LogLevel) -> Self {
         log_level.0
@@ -57,22 +106,35 @@ pub enum Network {
     Signet,
 }
 
-impl<'source> FromPyObject<'source> for Network {
-    fn extract(obj: &'source PyAny) -> PyResult<Self> {
-        let network_str: String = obj.extract()?;
-        match network_str.trim().to_lowercase().as_str() {
+impl FromStr for Network {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
             "mainnet" => Ok(Network::Mainnet),
             "testnet3" => Ok(Network::Testnet3),
             "testnet4" => Ok(Network::Testnet4),
             "regtest" => Ok(Network::Regtest),
             "signet" => Ok(Network::Signet),
-            _ => Err(PyErr::new::<PyValueError, _>(
-                "'network' must be either 'mainnet', 'testnet', 'testnet4' or 'signet'",
-            )),
+            _ => Err(Error::Config(format!(
+                "unrecognized network '{}', expected one of 'mainnet', 'testnet3', 'testnet4', 'regtest' or 'signet'",
+                s
+            ))),
         }
     }
 }
 
+impl<'source> FromPyObject<'source> for Network {
+    fn extract(obj: &'source PyAny) -> PyResult<Self> {
+        let network_str: String = obj.extract()?;
+        network_str.parse().map_err(|_| {
+            PyErr::new::<PyValueError, _>(
+                "'network' must be either 'mainnet', 'testnet', 'testnet4' or 'signet'",
+            )
+        })
+    }
+}
+
 impl Display for Network {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let s = match self {
@@ -86,6 +148,19 @@ impl Display for Network {
     }
 }
 
+impl Network {
+    // The `chain` bitcoind reports in `getblockchaininfo` for this network.
+    pub fn chain_name(&self) -> &'static str {
+        match self {
+            Network::Mainnet => "main",
+            Network::Testnet3 => "test",
+            Network::Testnet4 => "testnet4",
+            Network::Regtest => "regtest",
+            Network::Signet => "signet",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Heights {
     pub segwit: u32,
@@ -95,6 +170,25 @@ pub struct Heights {
     pub multisig_addresses: u32,
     pub taproot_support: u32,
     pub fix_is_segwit: u32,
+    pub future_segwit: u32,
+    pub p2wsh_data: u32,
+    pub taproot_annex: u32,
+    pub flexible_output_order: u32,
+    pub plaintext_op_return: u32,
+    pub scriptsig_data_start: u32,
+    pub scriptsig_data_end: u32,
+    pub non_push_op_return: u32,
+    pub p2pk_destinations: u32,
+    pub cbor_inscription_body: u32,
+    pub extended_multisig: u32,
+    pub op_return_anchor: u32,
+    pub segwit_v0_program_length: u32,
+    pub matched_envelope_endif: u32,
+    pub envelope_body_separator: u32,
+    pub batch_inscriptions: u32,
+    pub empty_op_return: u32,
+    pub taproot_program_length: u32,
+    pub message_type_id_range: u32,
 }
 
 impl Heights {
@@ -108,6 +202,25 @@ impl Heights {
                 multisig_addresses: 333500,
                 taproot_support: 902000,
                 fix_is_segwit: 902000,
+                future_segwit: u32::MAX,
+                p2wsh_data: u32::MAX,
+                taproot_annex: u32::MAX,
+                flexible_output_order: u32::MAX,
+                plaintext_op_return: u32::MAX,
+                scriptsig_data_start: u32::MAX,
+                scriptsig_data_end: u32::MAX,
+                non_push_op_return: u32::MAX,
+                p2pk_destinations: u32::MAX,
+                cbor_inscription_body: u32::MAX,
+                extended_multisig: u32::MAX,
+                op_return_anchor: u32::MAX,
+                segwit_v0_program_length: u32::MAX,
+                matched_envelope_endif: u32::MAX,
+                envelope_body_separator: u32::MAX,
+                batch_inscriptions: u32::MAX,
+                empty_op_return: u32::MAX,
+                taproot_program_length: u32::MAX,
+                message_type_id_range: u32::MAX,
             },
             Network::Testnet3 => Heights {
                 segwit: 1440200,
@@ -117,6 +230,25 @@ impl Heights {
                 multisig_addresses: 0,
                 taproot_support: 4410000,
                 fix_is_segwit: 4410000,
+                future_segwit: u32::MAX,
+                p2wsh_data: u32::MAX,
+                taproot_annex: u32::MAX,
+                flexible_output_order: u32::MAX,
+                plaintext_op_return: u32::MAX,
+                scriptsig_data_start: u32::MAX,
+                scriptsig_data_end: u32::MAX,
+                non_push_op_return: u32::MAX,
+                p2pk_destinations: u32::MAX,
+                cbor_inscription_body: u32::MAX,
+                extended_multisig: u32::MAX,
+                op_return_anchor: u32::MAX,
+                segwit_v0_program_length: u32::MAX,
+                matched_envelope_endif: u32::MAX,
+                envelope_body_separator: u32::MAX,
+                batch_inscriptions: u32::MAX,
+                empty_op_return: u32::MAX,
+                taproot_program_length: u32::MAX,
+                message_type_id_range: u32::MAX,
             },
             Network::Testnet4 => Heights {
                 segwit: 0,
@@ -126,6 +258,25 @@ impl Heights {
                 multisig_addresses: 0,
                 taproot_support: 85000,
                 fix_is_segwit: 85000,
+                future_segwit: u32::MAX,
+                p2wsh_data: u32::MAX,
+                taproot_annex: u32::MAX,
+                flexible_output_order: u32::MAX,
+                plaintext_op_return: u32::MAX,
+                scriptsig_data_start: u32::MAX,
+                scriptsig_data_end: u32::MAX,
+                non_push_op_return: u32::MAX,
+                p2pk_destinations: u32::MAX,
+                cbor_inscription_body: u32::MAX,
+                extended_multisig: u32::MAX,
+                op_return_anchor: u32::MAX,
+                segwit_v0_program_length: u32::MAX,
+                matched_envelope_endif: u32::MAX,
+                envelope_body_separator: u32::MAX,
+                batch_inscriptions: u32::MAX,
+                empty_op_return: u32::MAX,
+                taproot_program_length: u32::MAX,
+                message_type_id_range: u32::MAX,
             },
             Network::Regtest => Heights {
                 segwit: 0,
@@ -135,6 +286,25 @@ impl Heights {
                 multisig_addresses: 0,
                 taproot_support: 0,
                 fix_is_segwit: 0,
+                future_segwit: 0,
+                p2wsh_data: 0,
+                taproot_annex: 0,
+                flexible_output_order: 0,
+                plaintext_op_return: 0,
+                scriptsig_data_start: 0,
+                scriptsig_data_end: u32::MAX,
+                non_push_op_return: 0,
+                p2pk_destinations: 0,
+                cbor_inscription_body: 0,
+                extended_multisig: 0,
+                op_return_anchor: 0,
+                segwit_v0_program_length: 0,
+                matched_envelope_endif: 0,
+                envelope_body_separator: 0,
+                batch_inscriptions: 0,
+                empty_op_return: 0,
+                taproot_program_length: 0,
+                message_type_id_range: 0,
             },
             Network::Signet => Heights {
                 segwit: 0,
@@ -144,14 +314,116 @@ impl Heights {
                 multisig_addresses: 0,
                 taproot_support: 0,
                 fix_is_segwit: 0,
+                future_segwit: 0,
+                p2wsh_data: 0,
+                taproot_annex: 0,
+                flexible_output_order: 0,
+                plaintext_op_return: 0,
+                scriptsig_data_start: 0,
+                scriptsig_data_end: u32::MAX,
+                non_push_op_return: 0,
+                p2pk_destinations: 0,
+                cbor_inscription_body: 0,
+                extended_multisig: 0,
+                op_return_anchor: 0,
+                segwit_v0_program_length: 0,
+                matched_envelope_endif: 0,
+                envelope_body_separator: 0,
+                batch_inscriptions: 0,
+                empty_op_return: 0,
+                taproot_program_length: 0,
+                message_type_id_range: 0,
             },
         }
     }
 }
 
+// Address encoding parameters of a network: the bech32 (segwit v0) and bech32m (v1+)
+// human readable parts and the base58 version bytes. `Config` takes its default address
+// versions from here.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkParams {
+    pub hrp: String,
+    pub p2pkh_version: u8,
+    pub p2sh_version: u8,
+    pub bech32m_hrp: String,
+}
+
+impl NetworkParams {
+    pub fn new(network: Network) -> Self {
+        let (hrp, p2pkh_version, p2sh_version) = match network {
+            Network::Mainnet => ("bc", 0x00, 0x05),
+            Network::Testnet3 | Network::Testnet4 | Network::Signet => ("tb", 0x6F, 0xC4),
+            Network::Regtest => ("bcrt", 0x6F, 0xC4),
+        };
+        NetworkParams {
+            hrp: hrp.into(),
+            p2pkh_version,
+            p2sh_version,
+            bech32m_hrp: hrp.into(),
+        }
+    }
+}
+
+// Rewrites each destination address `parse_vout` produces into a canonical form.
+#[derive(Clone)]
+pub struct AddressNormalizer(pub Arc<dyn Fn(&str) -> String + Send + Sync>);
+
+impl AddressNormalizer {
+    // Wraps a Python callable taking and returning an address string. It runs on parser
+    // threads, so if it raises the error is logged and the address is kept as encoded.
+    pub fn from_py(callable: PyObject) -> Self {
+        AddressNormalizer(Arc::new(move |address| {
+            let normalized = Python::with_gil(|py| callable.call1(py, (address,))?.extract(py));
+            normalized.unwrap_or_else(|e| {
+                error!("address_normalizer callback failed for {}: {}", address, e);
+                address.to_string()
+            })
+        }))
+    }
+
+    pub fn normalize(&self, address: &str) -> String {
+        (self.0)(address)
+    }
+}
+
+impl std::fmt::Debug for AddressNormalizer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("AddressNormalizer")
+    }
+}
+
+// Told of a reorg as it is detected, before rolling back: the height of the stored block
+// the node no longer has, the hash stored for it, and the node's hash at that height.
+#[derive(Clone)]
+pub struct OnReorg(pub Arc<dyn Fn(u32, BlockHash, BlockHash) + Send + Sync>);
+
+impl OnReorg {
+    // Wraps a Python callable taking `(height, expected_hash, actual_hash)`, hashes as hex.
+    // It runs on an indexer thread, so an exception it raises is logged, not propagated.
+    pub fn from_py(callable: PyObject) -> Self {
+        OnReorg(Arc::new(move |height, expected_hash, actual_hash| {
+            let args = (height, expected_hash.to_string(), actual_hash.to_string());
+            if let Err(e) = Python::with_gil(|py| callable.call1(py, args)) {
+                error!("on_reorg callback failed: {}", e);
+            }
+        }))
+    }
+
+    pub fn call(&self, height: u32, expected_hash: BlockHash, actual_hash: BlockHash) {
+        (self.0)(height, expected_hash, actual_hash)
+    }
+}
+
+impl std::fmt::Debug for OnReorg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("OnReorg")
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
-    pub rpc_address: String,
+    pub rpc_addresses: Vec<String>,
     pub rpc_user: String,
     pub rpc_password: String,
     pub log_file: String,
@@ -160,64 +432,306 @@ pub struct Config {
     pub consume_blocks: bool,
     pub start_height: Option<u32>,
     pub mode: Mode,
-    pub prefix: Vec<u8>,
+    // Accepted in order; more than one only during a prefix migration.
+    pub prefixes: Vec<Vec<u8>>,
     pub address_version: Vec<u8>,
     pub p2sh_address_version: Vec<u8>,
+    // Used for the member addresses of a bare multisig destination.
+    pub multisig_member_address_version: Vec<u8>,
     pub network: Network,
     pub heights: Heights,
     pub json_format: bool,
     pub only_write_in_reorg_window: bool,
     pub enable_all_protocol_changes: bool,
+    pub prev_tx_cache_size: usize,
+    pub block_cache_size: usize,
+    pub verbose_parse_errors: bool,
+    pub fetch_window: usize,
+    pub max_outputs_parsed: Option<usize>,
+    pub verify_merkle_root: bool,
+    pub min_confirmations: u32,
+    pub electrum_scripthash_index: bool,
+    // Records every output that could fund a dispenser, with its destination and value.
+    pub potential_dispenser_index: bool,
+    pub max_parse_errors_per_block: Option<usize>,
+    pub counterparty_start_height: Option<u32>,
+    pub request_queue_policy: RequestQueuePolicy,
+    // Bounds each previous transaction fetch made while parsing.
+    pub rpc_timeout: Duration,
+    // Previous transaction fetches above this many txids are split into several batches.
+    pub max_rpc_batch_size: usize,
+    // When off, coinbase transactions are recorded without parsing their outputs.
+    pub parse_coinbase: bool,
+    // Keeps each block's raw bytes so it can be reparsed without refetching it.
+    pub store_raw_blocks: bool,
+    // Splits a transaction's data into the messages batched in it.
+    pub message_separator: Option<Vec<u8>>,
+    pub address_normalizer: Option<AddressNormalizer>,
+    pub on_reorg: Option<OnReorg>,
+}
+
+// `unix://` addresses are only served with the unix-socket feature on Unix platforms.
+fn check_rpc_addresses(addresses: &[String]) -> Result<(), Error> {
+    if cfg!(all(unix, feature = "unix-socket")) {
+        return Ok(());
+    }
+    match addresses.iter().find(|a| a.starts_with(UNIX_SOCKET_SCHEME)) {
+        Some(address) => Err(Error::Config(format!(
+            "'{}': unix socket RPC needs the unix-socket feature on a Unix platform",
+            address
+        ))),
+        None => Ok(()),
+    }
+}
+
+// Each network is only accepted when its feature is compiled in, so a build can be
+// pinned to the networks it is deployed for.
+fn check_network(network: &Network) -> Result<(), Error> {
+    let compiled_in = match network {
+        Network::Mainnet => cfg!(feature = "mainnet"),
+        Network::Testnet3 | Network::Testnet4 => cfg!(feature = "testnet"),
+        Network::Regtest => cfg!(feature = "regtest"),
+        Network::Signet => cfg!(feature = "signet"),
+    };
+    if compiled_in {
+        Ok(())
+    } else {
+        Err(Error::Config(format!(
+            "'{}': network not supported by this build",
+            network
+        )))
+    }
+}
+
+fn required_env(name: &str) -> Result<String, Error> {
+    env::var(name).map_err(|_| Error::Config(format!("'{}' is required", name)))
 }
 
 impl Config {
-    pub fn segwit_supported(&self, height: u32) -> bool {
-        height >= self.heights.segwit || self.enable_all_protocol_changes
+    // Builds a config from COUNTERPARTY_* environment variables for containerized
+    // deployments. Credentials are read from the cookie file when COUNTERPARTY_RPC_COOKIE
+    // is set; everything not covered by a variable takes the same default as the dict path.
+    pub fn from_env() -> Result<Self, Error> {
+        let rpc_addresses = vec![required_env("COUNTERPARTY_RPC_URL")?];
+        check_rpc_addresses(&rpc_addresses)?;
+        let network: Network = required_env("COUNTERPARTY_NETWORK")?.parse()?;
+        check_network(&network)?;
+
+        let (rpc_user, rpc_password) = match env::var("COUNTERPARTY_RPC_COOKIE") {
+            Ok(path) => {
+                let cookie = fs::read_to_string(&path).map_err(|e| {
+                    Error::Config(format!("failed to read rpc cookie '{}': {}", path, e))
+                })?;
+                let (user, password) = cookie.trim().split_once(':').ok_or_else(|| {
+                    Error::Config(format!(
+                        "rpc cookie '{}' is not in 'user:password' form",
+                        path
+                    ))
+                })?;
+                (user.to_string(), password.to_string())
+            }
+            Err(_) => (
+                required_env("COUNTERPARTY_RPC_USER")?,
+                required_env("COUNTERPARTY_RPC_PASSWORD")?,
+            ),
+        };
+
+        Ok(Config {
+            rpc_addresses,
+            rpc_user,
+            rpc_password,
+            log_file: env::var("COUNTERPARTY_LOG_FILE").unwrap_or_default(),
+            db_dir: env::var("COUNTERPARTY_DB_DIR").unwrap_or_default(),
+            ..Config::with_defaults(network)
+        })
+    }
+
+    // The dict path's defaults for `network`, with no RPC endpoint, database or log file.
+    pub fn with_defaults(network: Network) -> Self {
+        let params = NetworkParams::new(network.clone());
+        let address_version = vec![params.p2pkh_version];
+
+        Config {
+            rpc_addresses: vec![],
+            rpc_user: "".into(),
+            rpc_password: "".into(),
+            log_file: "".into(),
+            log_level: LogLevel::default(),
+            db_dir: "".into(),
+            consume_blocks: false,
+            start_height: None,
+            mode: Mode::Fetcher,
+            prefixes: vec![b"CNTRPRTY".to_vec()],
+            multisig_member_address_version: address_version.clone(),
+            address_version,
+            p2sh_address_version: vec![params.p2sh_version],
+            heights: Heights::new(network.clone()),
+            network,
+            json_format: false,
+            only_write_in_reorg_window: false,
+            enable_all_protocol_changes: false,
+            prev_tx_cache_size: 10_000,
+            block_cache_size: 0,
+            verbose_parse_errors: false,
+            fetch_window: 64,
+            max_outputs_parsed: None,
+            verify_merkle_root: false,
+            min_confirmations: 0,
+            electrum_scripthash_index: false,
+            potential_dispenser_index: false,
+            max_parse_errors_per_block: None,
+            counterparty_start_height: None,
+            request_queue_policy: RequestQueuePolicy::Block,
+            rpc_timeout: Duration::from_secs(30),
+            max_rpc_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            parse_coinbase: true,
+            store_raw_blocks: false,
+            message_separator: None,
+            address_normalizer: None,
+            on_reorg: None,
+        }
+    }
+
+    pub fn network_params(&self) -> NetworkParams {
+        NetworkParams::new(self.network.clone())
+    }
+
+    pub fn segwit_supported(&self, height: Height) -> bool {
+        height.0 >= self.heights.segwit || self.enable_all_protocol_changes
     }
 
-    pub fn p2sh_address_supported(&self, height: u32) -> bool {
-        height >= self.heights.p2sh_addresses || self.enable_all_protocol_changes
+    pub fn p2sh_address_supported(&self, height: Height) -> bool {
+        height.0 >= self.heights.p2sh_addresses || self.enable_all_protocol_changes
     }
 
-    pub fn p2sh_dispensers_supported(&self, height: u32) -> bool {
-        height >= self.heights.p2sh_dispensers || self.enable_all_protocol_changes
+    pub fn p2sh_dispensers_supported(&self, height: Height) -> bool {
+        height.0 >= self.heights.p2sh_dispensers || self.enable_all_protocol_changes
     }
 
-    pub fn correct_segwit_txids_enabled(&self, height: u32) -> bool {
-        height >= self.heights.correct_segwit_txids || self.enable_all_protocol_changes
+    pub fn correct_segwit_txids_enabled(&self, height: Height) -> bool {
+        height.0 >= self.heights.correct_segwit_txids || self.enable_all_protocol_changes
     }
 
-    pub fn multisig_addresses_enabled(&self, height: u32) -> bool {
-        height >= self.heights.multisig_addresses || self.enable_all_protocol_changes
+    pub fn multisig_addresses_enabled(&self, height: Height) -> bool {
+        height.0 >= self.heights.multisig_addresses || self.enable_all_protocol_changes
     }
 
-    pub fn taproot_support_enabled(&self, height: u32) -> bool {
-        height >= self.heights.taproot_support || self.enable_all_protocol_changes
+    pub fn taproot_support_enabled(&self, height: Height) -> bool {
+        height.0 >= self.heights.taproot_support || self.enable_all_protocol_changes
     }
 
-    pub fn fix_is_segwit_enabled(&self, height: u32) -> bool {
-        height >= self.heights.fix_is_segwit || self.enable_all_protocol_changes
+    pub fn fix_is_segwit_enabled(&self, height: Height) -> bool {
+        height.0 >= self.heights.fix_is_segwit || self.enable_all_protocol_changes
     }
 
+    pub fn future_segwit_enabled(&self, height: Height) -> bool {
+        height.0 >= self.heights.future_segwit || self.enable_all_protocol_changes
+    }
+
+    pub fn p2wsh_data_enabled(&self, height: Height) -> bool {
+        height.0 >= self.heights.p2wsh_data || self.enable_all_protocol_changes
+    }
+
+    pub fn taproot_annex_enabled(&self, height: Height) -> bool {
+        height.0 >= self.heights.taproot_annex || self.enable_all_protocol_changes
+    }
+
+    pub fn flexible_output_order_enabled(&self, height: Height) -> bool {
+        height.0 >= self.heights.flexible_output_order || self.enable_all_protocol_changes
+    }
+
+    pub fn plaintext_op_return_enabled(&self, height: Height) -> bool {
+        height.0 >= self.heights.plaintext_op_return || self.enable_all_protocol_changes
+    }
+
+    pub fn non_push_op_return_enabled(&self, height: Height) -> bool {
+        height.0 >= self.heights.non_push_op_return || self.enable_all_protocol_changes
+    }
+
+    pub fn p2pk_destinations_enabled(&self, height: Height) -> bool {
+        height.0 >= self.heights.p2pk_destinations || self.enable_all_protocol_changes
+    }
+
+    pub fn cbor_inscription_body_enabled(&self, height: Height) -> bool {
+        height.0 >= self.heights.cbor_inscription_body || self.enable_all_protocol_changes
+    }
+
+    pub fn extended_multisig_enabled(&self, height: Height) -> bool {
+        height.0 >= self.heights.extended_multisig || self.enable_all_protocol_changes
+    }
+
+    pub fn op_return_anchor_enabled(&self, height: Height) -> bool {
+        height.0 >= self.heights.op_return_anchor || self.enable_all_protocol_changes
+    }
+
+    pub fn segwit_v0_program_length_enabled(&self, height: Height) -> bool {
+        height.0 >= self.heights.segwit_v0_program_length || self.enable_all_protocol_changes
+    }
+
+    pub fn matched_envelope_endif_enabled(&self, height: Height) -> bool {
+        height.0 >= self.heights.matched_envelope_endif || self.enable_all_protocol_changes
+    }
+
+    pub fn envelope_body_separator_enabled(&self, height: Height) -> bool {
+        height.0 >= self.heights.envelope_body_separator || self.enable_all_protocol_changes
+    }
+
+    pub fn batch_inscriptions_enabled(&self, height: Height) -> bool {
+        height.0 >= self.heights.batch_inscriptions || self.enable_all_protocol_changes
+    }
+
+    pub fn empty_op_return_enabled(&self, height: Height) -> bool {
+        height.0 >= self.heights.empty_op_return || self.enable_all_protocol_changes
+    }
+
+    pub fn taproot_program_length_enabled(&self, height: Height) -> bool {
+        height.0 >= self.heights.taproot_program_length || self.enable_all_protocol_changes
+    }
+
+    pub fn message_type_id_range_enabled(&self, height: Height) -> bool {
+        height.0 >= self.heights.message_type_id_range || self.enable_all_protocol_changes
+    }
+
+    // Unlike the other protocol changes, scriptSig data is only read inside a bounded
+    // range of blocks: [scriptsig_data_start, scriptsig_data_end).
+    pub fn scriptsig_data_enabled(&self, height: Height) -> bool {
+        (self.heights.scriptsig_data_start..self.heights.scriptsig_data_end).contains(&height.0)
+            || self.enable_all_protocol_changes
+    }
+
+    pub fn before_counterparty_start(&self, height: Height) -> bool {
+        self.counterparty_start_height
+            .is_some_and(|start| height.0 < start)
+    }
+
+    // Normalized like any other destination, so comparisons against parsed outputs hold.
     pub fn unspendable(&self) -> String {
-        match self.network {
+        let address = match self.network {
             Network::Mainnet => "1CounterpartyXXXXXXXXXXXXXXXUWLpVr",
             Network::Testnet3 => "mvCounterpartyXXXXXXXXXXXXXXW24Hef",
             Network::Testnet4 => "mvCounterpartyXXXXXXXXXXXXXXW24Hef",
             Network::Regtest => "mvCounterpartyXXXXXXXXXXXXXXW24Hef",
             Network::Signet => "mvCounterpartyXXXXXXXXXXXXXXW24Hef",
+        };
+        match &self.address_normalizer {
+            Some(normalizer) => normalizer.normalize(address),
+            None => address.into(),
         }
-        .into()
     }
 }
 
 impl<'source> FromPyObject<'source> for Config {
     fn extract(obj: &'source PyAny) -> PyResult<Self> {
         let dict = obj.downcast::<PyDict>()?;
-        let rpc_address: String = dict
-            .get_item("rpc_address")?
-            .ok_or(PyErr::new::<PyValueError, _>("'rpc_address' is required"))?
-            .extract()?;
+        // `rpc_addresses` lists every node to balance across; `rpc_address` names just one.
+        let rpc_addresses = match dict.get_item("rpc_addresses") {
+            Ok(Some(item)) => item.extract::<Vec<String>>()?,
+            _ => vec![dict
+                .get_item("rpc_address")?
+                .ok_or(PyErr::new::<PyValueError, _>("'rpc_address' is required"))?
+                .extract()?],
+        };
+        check_rpc_addresses(&rpc_addresses)?;
         let rpc_user: String = dict
             .get_item("rpc_user")?
             .ok_or(PyErr::new::<PyValueError, _>("'rpc_user' is required"))?
@@ -252,7 +766,7 @@ impl<'source> FromPyObject<'source> for Config {
 
         let log_level = match dict.get_item("log_level") {
             Ok(Some(item)) => item.extract()?,
-            _ => LogLevel(LevelFilter::INFO),
+            _ => LogLevel::default(),
         };
 
         let json_format = match dict.get_item("json_format") {
@@ -265,47 +779,165 @@ impl<'source> FromPyObject<'source> for Config {
             _ => false,
         };
 
-        let prefix = match dict.get_item("prefix") {
-            Ok(Some(item)) => item.extract::<Vec<u8>>()?,
-            _ => b"CNTRPRTY".to_vec(),
+        let prefixes = match (dict.get_item("prefixes"), dict.get_item("prefix")) {
+            (Ok(Some(item)), _) => item.extract::<Vec<Vec<u8>>>()?,
+            (_, Ok(Some(item))) => vec![item.extract::<Vec<u8>>()?],
+            _ => vec![b"CNTRPRTY".to_vec()],
         };
+        if prefixes.is_empty() {
+            return Err(PyErr::new::<PyValueError, _>("prefixes must not be empty"));
+        }
 
         let network = match dict.get_item("network") {
             Ok(Some(item)) => item.extract()?,
             _ => Network::Mainnet, // Default to Mainnet if not provided or in case of an error
         };
+        check_network(&network)?;
 
         let enable_all_protocol_changes = match dict.get_item("enable_all_protocol_changes") {
             Ok(Some(item)) => item.extract()?,
             _ => false,
         };
 
+        let prev_tx_cache_size = match dict.get_item("prev_tx_cache_size") {
+            Ok(Some(item)) => item.extract()?,
+            _ => 10_000,
+        };
+
+        // Counts whole deserialized blocks, so it is off unless asked for.
+        let block_cache_size = match dict.get_item("block_cache_size") {
+            Ok(Some(item)) => item.extract()?,
+            _ => 0,
+        };
+
+        let verbose_parse_errors = match dict.get_item("verbose_parse_errors") {
+            Ok(Some(item)) => item.extract()?,
+            _ => false,
+        };
+
+        let fetch_window = match dict.get_item("fetch_window") {
+            Ok(Some(item)) => item.extract()?,
+            _ => 64,
+        };
+
+        // Off by default: a limit makes oversized transactions unparseable, which must
+        // match across every node.
+        let max_outputs_parsed = match dict.get_item("max_outputs_parsed") {
+            Ok(Some(item)) => item.extract::<Option<usize>>()?,
+            _ => None,
+        };
+
+        // Blocks from bitcoind are trusted; enable when parsing blocks from other sources.
+        let verify_merkle_root = match dict.get_item("verify_merkle_root") {
+            Ok(Some(item)) => item.extract()?,
+            _ => false,
+        };
+
+        // Blocks within this many of the tip are left for a later pass.
+        let min_confirmations = match dict.get_item("min_confirmations") {
+            Ok(Some(item)) => item.extract()?,
+            _ => 0,
+        };
+
+        // Adds a second script hash lookup keyed the way Electrum servers expect.
+        let electrum_scripthash_index = match dict.get_item("electrum_scripthash_index") {
+            Ok(Some(item)) => item.extract()?,
+            _ => false,
+        };
+
+        let potential_dispenser_index = match dict.get_item("potential_dispenser_index") {
+            Ok(Some(item)) => item.extract()?,
+            _ => false,
+        };
+
+        // A block where most transactions fail to parse usually means mismatched
+        // activation heights, so abort instead of indexing it.
+        let max_parse_errors_per_block = match dict.get_item("max_parse_errors_per_block") {
+            Ok(Some(item)) => item.extract::<Option<usize>>()?,
+            _ => None,
+        };
+
+        // Only safe up to the protocol's first block: vouts below it are never parsed, so
+        // a Counterparty transaction there would be missed.
+        let counterparty_start_height = match dict.get_item("counterparty_start_height") {
+            Ok(Some(item)) => item.extract::<Option<u32>>()?,
+            _ => None,
+        };
+
+        let request_queue_policy = match dict.get_item("request_queue_policy") {
+            Ok(Some(item)) => item.extract()?,
+            _ => RequestQueuePolicy::Block,
+        };
+
+        let rpc_timeout = match dict.get_item("rpc_timeout") {
+            Ok(Some(item)) => Duration::from_secs(item.extract()?),
+            _ => Duration::from_secs(30),
+        };
+
+        let max_rpc_batch_size = match dict.get_item("max_rpc_batch_size") {
+            Ok(Some(item)) => item.extract()?,
+            _ => DEFAULT_MAX_BATCH_SIZE,
+        };
+
+        let parse_coinbase = match dict.get_item("parse_coinbase") {
+            Ok(Some(item)) => item.extract()?,
+            _ => true,
+        };
+
+        let store_raw_blocks = match dict.get_item("store_raw_blocks") {
+            Ok(Some(item)) => item.extract()?,
+            _ => false,
+        };
+
+        let message_separator = match dict.get_item("message_separator") {
+            Ok(Some(item)) => item.extract::<Option<Vec<u8>>>()?,
+            _ => None,
+        };
+
+        let address_normalizer = match dict.get_item("address_normalizer") {
+            Ok(Some(item)) if !item.is_none() => {
+                if !item.is_callable() {
+                    return Err(PyErr::new::<PyValueError, _>(
+                        "address_normalizer must be callable",
+                    ));
+                }
+                Some(AddressNormalizer::from_py(item.into()))
+            }
+            _ => None,
+        };
+
+        let on_reorg = match dict.get_item("on_reorg") {
+            Ok(Some(item)) if !item.is_none() => {
+                if !item.is_callable() {
+                    return Err(PyErr::new::<PyValueError, _>("on_reorg must be callable"));
+                }
+                Some(OnReorg::from_py(item.into()))
+            }
+            _ => None,
+        };
+
         let heights = Heights::new(network.clone());
 
+        let params = NetworkParams::new(network.clone());
+
         let address_version = match dict.get_item("address_version") {
             Ok(Some(item)) => item.extract::<Vec<u8>>()?,
-            _ => match network {
-                Network::Mainnet => vec![0x00],
-                Network::Testnet3 => vec![0x6F],
-                Network::Testnet4 => vec![0x6F],
-                Network::Regtest => vec![0x6F],
-                Network::Signet => vec![0x6F],
-            },
+            _ => vec![params.p2pkh_version],
         };
 
         let p2sh_address_version = match dict.get_item("p2sh_address_version") {
             Ok(Some(item)) => item.extract::<Vec<u8>>()?,
-            _ => match network {
-                Network::Mainnet => vec![0x05],
-                Network::Testnet3 => vec![0xC4],
-                Network::Testnet4 => vec![0xC4],
-                Network::Regtest => vec![0xC4],
-                Network::Signet => vec![0xC4],
-            },
+            _ => vec![params.p2sh_version],
+        };
+
+        let multisig_member_address_version = match dict.get_item("multisig_member_address_version")
+        {
+            Ok(Some(item)) => item.extract::<Vec<u8>>()?,
+            _ => address_version.clone(),
         };
 
         Ok(Config {
-            rpc_address,
+            rpc_addresses,
             rpc_user,
             rpc_password,
             log_file,
@@ -314,14 +946,243 @@ impl<'source> FromPyObject<'source> for Config {
             consume_blocks,
             start_height,
             mode,
-            prefix,
+            prefixes,
             address_version,
             p2sh_address_version,
+            multisig_member_address_version,
             network,
             heights,
             json_format,
             only_write_in_reorg_window,
             enable_all_protocol_changes,
+            prev_tx_cache_size,
+            block_cache_size,
+            verbose_parse_errors,
+            fetch_window,
+            max_outputs_parsed,
+            verify_merkle_root,
+            min_confirmations,
+            electrum_scripthash_index,
+            potential_dispenser_index,
+            max_parse_errors_per_block,
+            counterparty_start_height,
+            request_queue_policy,
+            rpc_timeout,
+            max_rpc_batch_size,
+            parse_coinbase,
+            store_raw_blocks,
+            message_separator,
+            address_normalizer,
+            on_reorg,
         })
     }
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::sync::{Mutex, MutexGuard};
+
+    use uuid::Uuid;
+
+    use crate::{
+        indexer::rpc_client::{BatchRpcClient, BatchRpcError},
+        utils::script_to_address,
+    };
+
+    use super::*;
+
+    const ENV_VARS: [&str; 7] = [
+        "COUNTERPARTY_RPC_URL",
+        "COUNTERPARTY_RPC_USER",
+        "COUNTERPARTY_RPC_PASSWORD",
+        "COUNTERPARTY_NETWORK",
+        "COUNTERPARTY_RPC_COOKIE",
+        "COUNTERPARTY_DB_DIR",
+        "COUNTERPARTY_LOG_FILE",
+    ];
+
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    // Serializes tests touching the process environment and restores the previous
+    // values on drop.
+    struct EnvGuard {
+        saved: Vec<(&'static str, Option<String>)>,
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    impl EnvGuard {
+        fn new(vars: &[(&str, &str)]) -> Self {
+            let lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            let saved = ENV_VARS.iter().map(|k| (*k, env::var(k).ok())).collect();
+            for k in ENV_VARS {
+                env::remove_var(k);
+            }
+            for (k, v) in vars {
+                env::set_var(k, v);
+            }
+            EnvGuard { saved, _lock: lock }
+        }
+    }
+
+    impl Drop for EnvGuard {
+        fn drop(&mut self) {
+            for (k, v) in &self.saved {
+                match v {
+                    Some(v) => env::set_var(k, v),
+                    None => env::remove_var(k),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_network_params() {
+        let cases = [
+            (Network::Mainnet, "bc", 0x00, 0x05),
+            (Network::Testnet3, "tb", 0x6F, 0xC4),
+            (Network::Testnet4, "tb", 0x6F, 0xC4),
+            (Network::Regtest, "bcrt", 0x6F, 0xC4),
+            (Network::Signet, "tb", 0x6F, 0xC4),
+        ];
+        for (network, hrp, p2pkh_version, p2sh_version) in cases {
+            let config = Config::with_defaults(network.clone());
+            let params = config.network_params();
+            assert_eq!(
+                params,
+                NetworkParams {
+                    hrp: hrp.into(),
+                    p2pkh_version,
+                    p2sh_version,
+                    bech32m_hrp: hrp.into(),
+                }
+            );
+            assert_eq!(config.address_version, vec![p2pkh_version]);
+            assert_eq!(config.p2sh_address_version, vec![p2sh_version]);
+
+            // Agrees with the segwit addresses `script_to_address` encodes.
+            let v0 = [[0x00, 0x20].as_slice(), &[0; 32]].concat();
+            let v1 = [[0x51, 0x20].as_slice(), &[0; 32]].concat();
+            for (script, hrp) in [(v0, &params.hrp), (v1, &params.bech32m_hrp)] {
+                let address = script_to_address(script, &network.to_string()).unwrap();
+                assert!(address.starts_with(&format!("{}1", hrp)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_env() {
+        let _guard = EnvGuard::new(&[
+            ("COUNTERPARTY_RPC_URL", "http://localhost:18443"),
+            ("COUNTERPARTY_RPC_USER", "rpc"),
+            ("COUNTERPARTY_RPC_PASSWORD", "secret"),
+            ("COUNTERPARTY_NETWORK", "regtest"),
+        ]);
+        let config = Config::from_env().unwrap();
+        assert_eq!(
+            config.rpc_addresses,
+            vec!["http://localhost:18443".to_string()]
+        );
+        assert_eq!(config.rpc_user, "rpc");
+        assert_eq!(config.rpc_password, "secret");
+        assert!(matches!(config.network, Network::Regtest));
+        assert_eq!(config.address_version, vec![0x6F]);
+        assert_eq!(config.p2sh_address_version, vec![0xC4]);
+        assert_eq!(config.heights.taproot_support, 0);
+    }
+
+    #[test]
+    fn test_from_env_cookie() {
+        let path = env::temp_dir().join(format!("cookie_{}", Uuid::new_v4()));
+        fs::write(&path, "__cookie__:abc123\n").unwrap();
+        let _guard = EnvGuard::new(&[
+            ("COUNTERPARTY_RPC_URL", "http://localhost:8332"),
+            ("COUNTERPARTY_NETWORK", "mainnet"),
+            ("COUNTERPARTY_RPC_COOKIE", path.to_str().unwrap()),
+        ]);
+        let config = Config::from_env();
+        fs::remove_file(&path).unwrap();
+        let config = config.unwrap();
+        assert_eq!(config.rpc_user, "__cookie__");
+        assert_eq!(config.rpc_password, "abc123");
+        assert!(matches!(config.network, Network::Mainnet));
+        assert_eq!(config.address_version, vec![0x00]);
+    }
+
+    #[test]
+    fn test_with_defaults_has_no_rpc_endpoint() {
+        let config = Config::with_defaults(Network::Regtest);
+        assert!(config.rpc_addresses.is_empty());
+        assert!(matches!(
+            BatchRpcClient::new(
+                config.rpc_addresses,
+                config.rpc_user,
+                config.rpc_password,
+                1
+            ),
+            Err(BatchRpcError::NoEndpoints)
+        ));
+    }
+
+    #[test]
+    fn test_from_env_missing_var() {
+        let _guard = EnvGuard::new(&[
+            ("COUNTERPARTY_RPC_URL", "http://localhost:8332"),
+            ("COUNTERPARTY_NETWORK", "mainnet"),
+            ("COUNTERPARTY_RPC_USER", "rpc"),
+        ]);
+        let err = Config::from_env().unwrap_err();
+        assert!(matches!(err, Error::Config(_)));
+        assert!(err.to_string().contains("COUNTERPARTY_RPC_PASSWORD"));
+    }
+
+    #[test]
+    fn test_from_env_unrecognized_network() {
+        let _guard = EnvGuard::new(&[
+            ("COUNTERPARTY_RPC_URL", "http://localhost:8332"),
+            ("COUNTERPARTY_NETWORK", "testnet5"),
+            ("COUNTERPARTY_RPC_USER", "rpc"),
+            ("COUNTERPARTY_RPC_PASSWORD", "secret"),
+        ]);
+        let err = Config::from_env().unwrap_err();
+        assert!(matches!(err, Error::Config(_)));
+        assert!(err.to_string().contains("testnet5"));
+    }
+
+    #[test]
+    fn test_check_rpc_addresses() {
+        let addresses = vec![
+            "http://localhost:8332".to_string(),
+            "unix:///run/bitcoind/rpc.sock".to_string(),
+        ];
+        let result = check_rpc_addresses(&addresses);
+        if cfg!(all(unix, feature = "unix-socket")) {
+            assert!(result.is_ok());
+        } else {
+            assert!(matches!(result, Err(Error::Config(_))));
+        }
+        assert!(check_rpc_addresses(&addresses[..1]).is_ok());
+    }
+
+    #[test]
+    fn test_check_network() {
+        let cases = [
+            (Network::Mainnet, cfg!(feature = "mainnet")),
+            (Network::Testnet3, cfg!(feature = "testnet")),
+            (Network::Testnet4, cfg!(feature = "testnet")),
+            (Network::Regtest, cfg!(feature = "regtest")),
+            (Network::Signet, cfg!(feature = "signet")),
+        ];
+        for (network, compiled_in) in cases {
+            let result = check_network(&network);
+            if compiled_in {
+                assert!(result.is_ok());
+            } else {
+                assert!(matches!(
+                    result,
+                    Err(Error::Config(message)) if message.contains(&network.to_string())
+                ));
+            }
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{collections::BTreeSet, ops::RangeInclusive, sync::Arc};
 
 use rocksdb::{
     ColumnFamily, ColumnFamilyDescriptor, Direction, IteratorMode, Options, WriteBatch,
@@ -10,8 +10,8 @@ use crate::indexer::{constants::CP_HEIGHT, types::entry::BlockAtHeightSpentOutpu
 use super::types::{
     entry::{
         get_cf_index_names, get_cf_names, make_key, to_cf_name, BlockAtHeightHasHash, Entry,
-        FromEntry, ScriptHashHasOutputsInBlockAtHeight, ToEntry, TxidVoutPrefix,
-        CF_INDEX_PREFIX_LENGTHS, CF_PREFIX_LENGTHS, INDEX_CF_NAME_SUFFIX,
+        FromEntry, RawBlockBytesAtHeight, ScriptHashHasOutputsInBlockAtHeight, ToEntry,
+        TxidVoutPrefix, CF_INDEX_PREFIX_LENGTHS, CF_PREFIX_LENGTHS, INDEX_CF_NAME_SUFFIX,
     },
     error::Error,
 };
@@ -48,8 +48,11 @@ pub trait DatabaseOps: Clone + Send + 'static {
         f: F,
     ) -> Result<(), Error>;
     fn block_at_height_has_hash(&self, height: u32) -> Result<Option<Vec<u8>>, Error>;
+    fn raw_block_bytes_at_height(&self, height: u32) -> Result<Option<Vec<u8>>, Error>;
     fn rollback_to_height(&self, batch: &mut WriteBatch, height: u32) -> Result<(), Error>;
     fn delete_below_height(&self, batch: &mut WriteBatch, height: u32) -> Result<(), Error>;
+    fn indexed_heights(&self) -> Result<BTreeSet<u32>, Error>;
+    fn find_gaps(&self, start: u32, tip: u32) -> Result<Vec<RangeInclusive<u32>>, Error>;
 }
 
 #[derive(Clone)]
@@ -177,6 +180,8 @@ impl DatabaseOps for Database {
         Ok(())
     }
 
+    // Entries only go into `batch`, never straight to the database, so a block written
+    // through `write_batch` is either all there or, if anything fails part way, not at all.
     fn put_entries(
         &self,
         batch: &mut WriteBatch,
@@ -185,11 +190,10 @@ impl DatabaseOps for Database {
     ) -> Result<(), Error> {
         for entry in entries {
             let (key, value) = entry.to_entry();
-            self.db.put_cf(self.cf(entry.cf_name())?, key, value)?;
+            batch.put_cf(self.cf(entry.cf_name())?, key, value);
             if min_index_height.is_some() {
                 let (key, value) = entry.to_index();
-                self.db
-                    .put_cf(self.cf(entry.cf_name() + INDEX_CF_NAME_SUFFIX)?, key, value)?;
+                batch.put_cf(self.cf(entry.cf_name() + INDEX_CF_NAME_SUFFIX)?, key, value);
             }
         }
 
@@ -227,6 +231,13 @@ impl DatabaseOps for Database {
         )?)
     }
 
+    fn raw_block_bytes_at_height(&self, height: u32) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.db.get_cf(
+            self.cf(to_cf_name::<RawBlockBytesAtHeight>())?,
+            height.to_be_bytes(),
+        )?)
+    }
+
     fn rollback_to_height(&self, batch: &mut WriteBatch, height: u32) -> Result<(), Error> {
         for cf_name in get_cf_names() {
             let entry_cf = self.cf(cf_name.clone())?;
@@ -277,6 +288,37 @@ impl DatabaseOps for Database {
         }
         Ok(())
     }
+
+    fn indexed_heights(&self) -> Result<BTreeSet<u32>, Error> {
+        let mut heights = BTreeSet::new();
+        let iter = self.db.iterator_cf(
+            self.cf(to_cf_name::<BlockAtHeightHasHash>())?,
+            IteratorMode::Start,
+        );
+        for result in iter {
+            let (key, value) = result?;
+            let entry = BlockAtHeightHasHash::from_entry((key.to_vec(), value.to_vec()))?;
+            heights.insert(entry.height);
+        }
+        Ok(heights)
+    }
+
+    // Returns the missing ranges of [start, tip]. `next` is None once u32::MAX is indexed.
+    fn find_gaps(&self, start: u32, tip: u32) -> Result<Vec<RangeInclusive<u32>>, Error> {
+        let heights = self.indexed_heights()?;
+        let mut gaps = Vec::new();
+        let mut next = Some(start);
+        for &height in heights.range(start..=tip) {
+            if let Some(next) = next.filter(|&next| height > next) {
+                gaps.push(next..=height - 1);
+            }
+            next = height.checked_add(1);
+        }
+        if let Some(next) = next.filter(|&next| next <= tip) {
+            gaps.push(next..=tip);
+        }
+        Ok(gaps)
+    }
 }
 
 #[cfg(test)]
@@ -335,6 +377,92 @@ mod tests {
         });
     }
 
+    #[derive(Debug)]
+    struct UnwritableEntry;
+
+    impl ToEntry for UnwritableEntry {
+        fn to_entry(&self) -> Entry {
+            (vec![0], vec![0])
+        }
+
+        fn to_index(&self) -> Entry {
+            (vec![0], vec![0])
+        }
+
+        fn cf_name(&self) -> String {
+            "missing_cf".into()
+        }
+
+        fn height(&self) -> u32 {
+            1
+        }
+    }
+
+    #[test]
+    fn test_put_entries_failure_writes_nothing() {
+        let db = new_test_db!().unwrap();
+        let tx_entry = TxInBlockAtHeight {
+            txid: test_sha256_hash(1),
+            height: 1,
+        };
+        let entries: Vec<Box<dyn ToEntry>> = vec![
+            Box::new(tx_entry.clone()),
+            Box::new(UnwritableEntry),
+            Box::new(BlockAtHeightHasHash {
+                height: 1,
+                hash: test_sha256_hash(1),
+            }),
+        ];
+
+        assert!(matches!(
+            db.write_batch(|batch| db.put_entries(batch, None, &entries)),
+            Err(Error::RocksDBColumnFamily(_))
+        ));
+        assert_eq!(
+            db.db
+                .get_cf(
+                    db.cf(to_cf_name::<TxInBlockAtHeight>()).unwrap(),
+                    tx_entry.to_entry().0
+                )
+                .unwrap(),
+            None
+        );
+        assert_eq!(db.block_at_height_has_hash(1).unwrap(), None);
+    }
+
+    #[test]
+    fn test_find_gaps() {
+        let db = new_test_db!().unwrap();
+        let entries = [1, 2, 4, 5, u32::MAX]
+            .into_iter()
+            .map(|height| -> Box<dyn ToEntry> {
+                Box::new(BlockAtHeightHasHash {
+                    height,
+                    hash: test_sha256_hash(height),
+                })
+            })
+            .collect();
+        db.write_batch(|batch| db.put_entries(batch, None, &entries))
+            .unwrap();
+
+        assert_eq!(
+            db.indexed_heights()
+                .unwrap()
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![1, 2, 4, 5, u32::MAX]
+        );
+        assert_eq!(db.find_gaps(1, 5).unwrap(), vec![3..=3]);
+        assert_eq!(db.find_gaps(0, 7).unwrap(), vec![0..=0, 3..=3, 6..=7]);
+        assert_eq!(db.find_gaps(4, 5).unwrap(), vec![]);
+        // No overflow at the top of the height range.
+        assert_eq!(
+            db.find_gaps(u32::MAX - 2, u32::MAX).unwrap(),
+            vec![u32::MAX - 2..=u32::MAX - 1]
+        );
+        assert_eq!(db.find_gaps(u32::MAX, u32::MAX).unwrap(), vec![]);
+    }
+
     #[test]
     fn test_get_funding_block_heights_found() {
         let db = new_test_db!().unwrap();
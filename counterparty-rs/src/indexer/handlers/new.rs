@@ -4,8 +4,14 @@ use crossbeam_channel::bounded;
 use tracing::{debug, info};
 
 use crate::indexer::{
-    bitcoin_client::BitcoinClient, config::Config, database::Database, logging::setup_logging,
-    stopper::Stopper, types::error::Error, Indexer,
+    bitcoin_client::BitcoinClient,
+    config::Config,
+    database::Database,
+    logging::setup_logging,
+    rpc_client::{BatchRpcClient, BATCH_CLIENT},
+    stopper::Stopper,
+    types::error::Error,
+    Indexer,
 };
 
 pub fn new(config: Config) -> Result<Indexer, Error> {
@@ -14,6 +20,19 @@ pub fn new(config: Config) -> Result<Indexer, Error> {
     info!("Indexer initializing...");
     let parallelism = std::thread::available_parallelism()?;
     let stopper = Stopper::new();
+    // Prev-tx fetches made while parsing go through the shared batch client, which
+    // answers to this stopper from here on.
+    let batch_client = BatchRpcClient::new(
+        config.rpc_addresses.clone(),
+        config.rpc_user.clone(),
+        config.rpc_password.clone(),
+        config.prev_tx_cache_size,
+    )
+    .map_err(|e| Error::BitcoinRpc(format!("Failed to create BatchRpcClient: {:#?}", e)))?
+    .with_timeout(config.rpc_timeout)
+    .with_max_batch_size(config.max_rpc_batch_size)
+    .with_stopper(stopper.clone());
+    *BATCH_CLIENT.lock()? = Some(batch_client);
     let client = BitcoinClient::new(&config, stopper.clone(), parallelism.into())?;
     let handles = client.start()?;
     debug!("Connecting to database: {}", config.db_dir);
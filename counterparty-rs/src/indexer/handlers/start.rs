@@ -2,12 +2,14 @@ use std::{thread::JoinHandle, time::Instant};
 
 use crate::indexer::{
     bitcoin_client::{BitcoinClient, BitcoinRpc},
-    config::Config,
+    config::{Config, Mode},
     database::DatabaseOps,
     stopper::Stopper,
     types::{error::Error, pipeline::ChanOut},
     utils::timed,
-    workers::{consumer, extractor, fetcher, new_worker_pool, orderer, producer, reporter, writer},
+    workers::{
+        consumer, extractor, fetcher, headers, new_worker_pool, orderer, producer, reporter, writer,
+    },
 };
 use crossbeam_channel::{bounded, unbounded};
 use tracing::{debug, info};
@@ -52,6 +54,17 @@ where
         "First Bitcoin client op: GetBlockchainHeight".into(),
         || client.get_blockchain_height(),
     )?;
+    client.verify_network_matches(&config)?;
+    match client.get_network_info() {
+        Ok(network_info) => info!(
+            "Bitcoin node {} (version {}, protocol {}, {} connections)",
+            network_info.subversion,
+            network_info.version,
+            network_info.protocol_version,
+            network_info.connections
+        ),
+        Err(e) => debug!("Failed to get network info: {}", e),
+    }
     debug!("Starting at block height: {}", start_height);
     debug!("Targeting block height: {}", target_block);
 
@@ -61,9 +74,30 @@ where
         rx_start,
         tx_c1,
         stopper.clone(),
-        producer::new(client.clone(), db.clone(), start_height, reorg_window),
+        producer::new(
+            client.clone(),
+            db.clone(),
+            start_height,
+            reorg_window,
+            config.min_confirmations,
+            config.on_reorg.clone(),
+        ),
     )?);
 
+    if config.mode == Mode::Headers {
+        let (tx_headers, _) = unbounded();
+        handles.append(&mut new_worker_pool(
+            "Headers".into(),
+            1,
+            rx_c1,
+            tx_headers,
+            stopper.clone(),
+            headers::new(client.clone(), db.clone(), reorg_window),
+        )?);
+        info!("{:?} started", config.mode);
+        return Ok(handles);
+    }
+
     handles.append(&mut new_worker_pool(
         "Fetcher".into(),
         parallelism / 2,
@@ -15,20 +15,40 @@ mod types;
 mod utils;
 mod workers;
 
+// Entry points for the criterion benchmarks in `benches/`, which link against the rlib.
+#[doc(hidden)]
+pub mod bench {
+    pub use super::bitcoin_client::{
+        parse_block, parse_block_streaming, parse_block_with_prev_txs,
+    };
+    pub use super::config::{Config, Network};
+    pub use super::stopper::Stopper;
+    pub use super::types::height::Height;
+    pub use super::workers::parser::ParseWorker;
+}
+
+use std::collections::HashSet;
+use std::str::FromStr;
 use std::thread::JoinHandle;
 
 use bitcoin;
 use bitcoin::consensus::deserialize;
-use bitcoin::{blockdata::transaction::Transaction, Block};
+use bitcoin::{
+    blockdata::transaction::Transaction, Amount, Block, BlockHash, Script, ScriptBuf, TxOut,
+};
 
-use pyo3::prelude::*;
+use pyo3::{
+    prelude::*,
+    types::{PyBytes, PyDict},
+};
 use types::pipeline::ChanOut;
 
 use self::{
     bitcoin_client::BitcoinClient,
     config::Config,
-    database::Database,
+    database::{Database, DatabaseOps},
     handlers::{get_block, new, start, stop},
+    rpc_client::BATCH_CLIENT,
     stopper::Stopper,
     types::error::Error,
 };
@@ -51,6 +71,13 @@ impl Indexer {
         Ok(new::new(config)?)
     }
 
+    // For containerized deployments, configured by COUNTERPARTY_* environment variables
+    // rather than a dict.
+    #[staticmethod]
+    pub fn from_env() -> PyResult<Self> {
+        Ok(new::new(Config::from_env()?)?)
+    }
+
     pub fn start(&mut self) -> PyResult<()> {
         self.handles.append(&mut start::new(
             self.parallelism,
@@ -63,6 +90,10 @@ impl Indexer {
         Ok(())
     }
 
+    pub fn get_block_cache_stats(&self, py: Python<'_>) -> PyResult<PyObject> {
+        Ok(self.client.block_cache_stats().into_py(py))
+    }
+
     pub fn stop(&mut self) -> PyResult<()> {
         Ok(stop::new(
             &mut self.handles,
@@ -83,6 +114,72 @@ impl Indexer {
         Ok(block.map(|b| b.into_py(py)).into_py(py))
     }
 
+    pub fn fetch_and_parse(
+        &self,
+        height: u32,
+        parse_vouts: bool,
+        py: Python<'_>,
+    ) -> PyResult<PyObject> {
+        let (client, config) = (self.client.clone(), self.config.clone());
+        let block =
+            py.allow_threads(|| client.fetch_and_parse(height.into(), &config, parse_vouts))?;
+        Ok(block.into_py(py))
+    }
+
+    // Without `height`, it is read from the coinbase (BIP34).
+    #[pyo3(signature = (hash, parse_vouts, height=None))]
+    pub fn fetch_and_parse_by_hash(
+        &self,
+        hash: &str,
+        parse_vouts: bool,
+        height: Option<u32>,
+        py: Python<'_>,
+    ) -> PyResult<PyObject> {
+        let hash = BlockHash::from_str(hash).map_err(|_| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>("Failed to decode block hash")
+        })?;
+        let (client, config) = (self.client.clone(), self.config.clone());
+        let block = py.allow_threads(|| {
+            client.fetch_and_parse_by_hash(&hash, height.map(Into::into), &config, parse_vouts)
+        })?;
+        Ok(block.into_py(py))
+    }
+
+    // Blocks `start` to `end` inclusive, fetched and parsed across the client's workers and
+    // returned in height order. The first error fails the whole range.
+    pub fn fetch_and_parse_range(
+        &self,
+        start: u32,
+        end: u32,
+        py: Python<'_>,
+    ) -> PyResult<PyObject> {
+        let (client, config) = (self.client.clone(), self.config.clone());
+        let blocks = py.allow_threads(|| {
+            client
+                .fetch_and_parse_range(start, end, &config)?
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+        Ok(blocks.into_py(py))
+    }
+
+    // Heights in [start, tip] with no block written, as inclusive `(start, end)` ranges,
+    // for finding the holes a crash mid-sync left behind.
+    pub fn find_gaps(&self, start: u32, tip: u32, py: Python<'_>) -> PyResult<Vec<(u32, u32)>> {
+        let db = self.db.clone();
+        let gaps = py.allow_threads(|| db.find_gaps(start, tip))?;
+        Ok(gaps
+            .into_iter()
+            .map(|gap| (*gap.start(), *gap.end()))
+            .collect())
+    }
+
+    pub fn reparse_height(&self, height: u32, py: Python<'_>) -> PyResult<PyObject> {
+        let (config, db) = (self.config.clone(), self.db.clone());
+        let block =
+            py.allow_threads(|| self::bitcoin_client::reparse_height(height.into(), &config, &db))?;
+        Ok(block.into_py(py))
+    }
+
     pub fn get_version(&self) -> PyResult<String> {
         Ok(env!("CARGO_PKG_VERSION").to_string())
     }
@@ -117,12 +214,27 @@ impl Deserializer {
         let deserialized_transaction = self::bitcoin_client::parse_transaction(
             &transaction,
             &self.config,
-            height,
+            height.into(),
             parse_vouts,
         );
         return Ok(deserialized_transaction.into_py(py));
     }
 
+    pub fn counterparty_tx_hash(&self, tx_hex: &str, height: u32) -> PyResult<String> {
+        let decoded_tx = hex::decode(tx_hex).map_err(|_| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>("Failed to decode hex transaction")
+        })?;
+        let transaction: Transaction = deserialize(&decoded_tx).map_err(|_| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>("Failed to deserialize transaction")
+        })?;
+
+        Ok(self::bitcoin_client::counterparty_tx_hash(
+            &transaction,
+            &self.config,
+            height.into(),
+        ))
+    }
+
     pub fn parse_block(
         &self,
         block_hex: &str,
@@ -138,9 +250,136 @@ impl Deserializer {
         })?;
 
         let deserialized_block =
-            self::bitcoin_client::parse_block(block, &self.config, height, parse_vouts);
+            self::bitcoin_client::parse_block(block, &self.config, height.into(), parse_vouts);
         return Ok(deserialized_block?.into_py(py));
     }
+
+    pub fn block_has_counterparty_data(&self, block_hex: &str, height: u32) -> PyResult<bool> {
+        let decoded_block = hex::decode(block_hex).map_err(|_| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>("Failed to decode hex block")
+        })?;
+        let block: Block = deserialize(&decoded_block).map_err(|_| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>("Failed to deserialize block")
+        })?;
+
+        Ok(self::bitcoin_client::block_has_counterparty_data(
+            &block,
+            &self.config,
+            height.into(),
+        ))
+    }
+
+    pub fn parse_block_filtered(
+        &self,
+        block_hex: &str,
+        height: u32,
+        addresses: HashSet<String>,
+        py: Python<'_>,
+    ) -> PyResult<PyObject> {
+        let decoded_block = hex::decode(block_hex).map_err(|_| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>("Failed to decode hex block")
+        })?;
+        let block: Block = deserialize(&decoded_block).map_err(|_| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>("Failed to deserialize block")
+        })?;
+
+        let deserialized_block = self::bitcoin_client::parse_block_filtered(
+            block,
+            &self.config,
+            height.into(),
+            &addresses,
+        );
+        Ok(deserialized_block?.into_py(py))
+    }
+
+    pub fn parse_block_verbose(
+        &self,
+        block_hex: &str,
+        height: u32,
+        py: Python<'_>,
+    ) -> PyResult<PyObject> {
+        let decoded_block = hex::decode(block_hex).map_err(|_| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>("Failed to decode hex block")
+        })?;
+        let block: Block = deserialize(&decoded_block).map_err(|_| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>("Failed to deserialize block")
+        })?;
+
+        let deserialized_block =
+            self::bitcoin_client::parse_block_verbose(block, &self.config, height.into());
+        Ok(deserialized_block?.into_py(py))
+    }
+
+    // Each output is a `(value, script_hex)` pair. Outputs that don't parse come back as
+    // `{"type": "error", "error": ...}`.
+    pub fn classify_outputs(
+        &self,
+        outputs: Vec<(u64, String)>,
+        height: u32,
+        py: Python<'_>,
+    ) -> PyResult<PyObject> {
+        let outputs = outputs
+            .into_iter()
+            .map(|(value, script_hex)| {
+                let script_pubkey = hex::decode(script_hex).map_err(|_| {
+                    PyErr::new::<pyo3::exceptions::PyValueError, _>("Failed to decode hex script")
+                })?;
+                Ok(TxOut {
+                    value: Amount::from_sat(value),
+                    script_pubkey: ScriptBuf::from_bytes(script_pubkey),
+                })
+            })
+            .collect::<PyResult<Vec<_>>>()?;
+
+        let classified =
+            self::bitcoin_client::classify_outputs(&self.config, height.into(), &outputs)
+                .into_iter()
+                .map(|result| match result {
+                    Ok(output) => Ok(output.into_py(py)),
+                    Err(e) => {
+                        let dict = PyDict::new_bound(py);
+                        dict.set_item("type", "error")?;
+                        dict.set_item("error", e.to_string())?;
+                        Ok(dict.unbind().into())
+                    }
+                })
+                .collect::<PyResult<Vec<PyObject>>>()?;
+        Ok(classified.into_py(py))
+    }
+
+    pub fn extract_all_inscriptions(&self, script_hex: &str, py: Python<'_>) -> PyResult<PyObject> {
+        let script = hex::decode(script_hex).map_err(|_| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>("Failed to decode hex script")
+        })?;
+        let inscriptions =
+            self::bitcoin_client::extract_all_inscriptions(Script::from_bytes(&script))
+                .into_iter()
+                .map(|inscription| PyBytes::new_bound(py, &inscription))
+                .collect::<Vec<_>>();
+        Ok(inscriptions.into_py(py))
+    }
+
+    #[cfg(feature = "debug-tools")]
+    pub fn debug_classify(&self, script_hex: &str, height: u32) -> String {
+        self::bitcoin_client::debug_classify(script_hex, &self.config, height.into())
+    }
+
+    pub fn get_prev_tx_cache_stats(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let stats = BATCH_CLIENT
+            .lock()
+            .map_err(Error::from)?
+            .as_ref()
+            .map(|client| client.cache_stats());
+        Ok(stats.into_py(py))
+    }
+
+    pub fn get_last_rpc_endpoint(&self) -> PyResult<Option<String>> {
+        Ok(BATCH_CLIENT
+            .lock()
+            .map_err(Error::from)?
+            .as_ref()
+            .and_then(|client| client.last_endpoint()))
+    }
 }
 
 pub fn register_indexer_module(parent_module: &Bound<'_, PyModule>) -> PyResult<()> {
@@ -1,26 +1,126 @@
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
 use bitcoin::Transaction;
 use bitcoin::Txid;
-use bitcoin::{Block, BlockHash};
+use bitcoin::{block::Header, Amount, Block, BlockHash, ScriptBuf, TxOut};
+use crossbeam_channel::{after, bounded, select};
 use lazy_static::lazy_static;
-use reqwest::blocking::Client as HttpClient;
+use lru::LruCache;
+use pyo3::{
+    types::{PyAnyMethods, PyDict},
+    IntoPy, PyObject, Python,
+};
+use reqwest::blocking::{Client as HttpClient, Response};
 use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
-use serde::{Deserialize, Serialize};
+use reqwest::StatusCode;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::collections::HashMap;
+use std::num::NonZeroUsize;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+use super::stopper::Stopper;
 
 lazy_static! {
     pub(crate) static ref BATCH_CLIENT: Mutex<Option<BatchRpcClient>> = Mutex::new(None);
 }
 
+const ENDPOINT_BACKOFF: Duration = Duration::from_secs(30);
+
+// Matches the default timeout of the blocking reqwest client.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 1000;
+
+pub const UNIX_SOCKET_SCHEME: &str = "unix://";
+
 #[derive(Debug, Clone)]
 pub struct BatchRpcClient {
     client: Arc<HttpClient>,
-    url: String,
+    endpoints: Arc<Vec<Endpoint>>,
+    last_endpoint: Arc<Mutex<Option<String>>>,
     auth: String,
-    cache: Arc<Mutex<HashMap<Txid, Option<Transaction>>>>,
+    cache: Arc<Mutex<PrevTxCache>>,
+    timeout: Duration,
+    // Larger `getrawtransaction` batches are split into several sequential requests.
+    max_batch_size: usize,
+    // When set, stopping it abandons a previous transaction fetch in flight.
+    stopper: Option<Stopper>,
+}
+
+#[derive(Debug)]
+struct Endpoint {
+    url: String,
+    dead_until: Mutex<Option<Instant>>,
+}
+
+impl Endpoint {
+    fn new(url: String) -> Self {
+        Endpoint {
+            url,
+            dead_until: Mutex::new(None),
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        let dead_until = self.dead_until.lock().unwrap_or_else(|e| e.into_inner());
+        !matches!(*dead_until, Some(until) if Instant::now() < until)
+    }
+
+    fn set_dead_until(&self, until: Option<Instant>) {
+        *self.dead_until.lock().unwrap_or_else(|e| e.into_inner()) = until;
+    }
+}
+
+#[derive(Debug)]
+struct PrevTxCache {
+    entries: LruCache<Txid, Option<Transaction>>,
+    hits: u64,
+    misses: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub len: usize,
+    pub capacity: usize,
+}
+
+impl CacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / lookups as f64
+    }
+}
+
+impl IntoPy<PyObject> for CacheStats {
+    #[allow(clippy::unwrap_used)]
+    fn into_py(self, py: Python<'_>) -> PyObject {
+        let dict = PyDict::new_bound(py);
+        dict.set_item("hits", self.hits).unwrap();
+        dict.set_item("misses", self.misses).unwrap();
+        dict.set_item("len", self.len).unwrap();
+        dict.set_item("capacity", self.capacity).unwrap();
+        dict.set_item("hit_rate", self.hit_rate()).unwrap();
+        dict.unbind().into()
+    }
+}
+
+// Subset of bitcoind's `getnetworkinfo` result.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct NetworkInfo {
+    pub version: u64,
+    pub subversion: String,
+    #[serde(rename = "protocolversion")]
+    pub protocol_version: u64,
+    pub connections: u32,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -31,16 +131,23 @@ struct RpcRequest {
     params: Vec<Value>,
 }
 
+// bitcoind answers a request it could not parse with a null id, and may leave out
+// `result` or `error` entirely, so every field is optional.
 #[derive(Debug, Serialize, Deserialize)]
 struct RpcResponse {
+    #[serde(default)]
     result: Option<Value>,
+    #[serde(default)]
     error: Option<RpcError>,
-    id: u64,
+    #[serde(default)]
+    id: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct RpcError {
+    #[serde(default)]
     code: i32,
+    #[serde(default)]
     message: String,
 }
 
@@ -48,9 +155,16 @@ struct RpcError {
 #[derive(Debug)]
 pub enum BatchRpcError {
     Http(reqwest::Error),
+    Io(std::io::Error),
+    // The endpoint's socket could not be connected to.
+    Unreachable(std::io::Error),
+    Unsupported(String),
     Rpc(String),
     Parse(serde_json::Error),
     InvalidResponse(String),
+    NoEndpoints,
+    Timeout(Duration),
+    Stopped,
 }
 
 impl From<reqwest::Error> for BatchRpcError {
@@ -65,8 +179,147 @@ impl From<serde_json::Error> for BatchRpcError {
     }
 }
 
+impl From<std::io::Error> for BatchRpcError {
+    fn from(err: std::io::Error) -> Self {
+        BatchRpcError::Io(err)
+    }
+}
+
+// Looks up a nested field of a result object, naming the field when it is missing.
+fn result_field<'a>(value: &'a Value, path: &[&str]) -> Result<&'a Value, BatchRpcError> {
+    path.iter()
+        .try_fold(value, |value, key| value.get(key))
+        .ok_or_else(|| {
+            BatchRpcError::InvalidResponse(format!("Missing field `{}`", path.join(".")))
+        })
+}
+
+impl BatchRpcError {
+    // The endpoint could not be reached at all, so another one may be tried.
+    fn is_unavailable(&self) -> bool {
+        match self {
+            BatchRpcError::Http(e) => e.is_connect() || e.is_timeout(),
+            BatchRpcError::Unreachable(_) => true,
+            _ => false,
+        }
+    }
+}
+
+// A response body from either transport. HTTP bodies are only read when decoded.
+enum ResponseBody {
+    Http(Response),
+    #[cfg_attr(not(all(unix, feature = "unix-socket")), allow(dead_code))]
+    Buffered(Vec<u8>),
+}
+
+impl ResponseBody {
+    fn json<T: DeserializeOwned>(self) -> Result<T, BatchRpcError> {
+        match self {
+            ResponseBody::Http(response) => Ok(response.json()?),
+            ResponseBody::Buffered(bytes) => Ok(serde_json::from_slice(&bytes)?),
+        }
+    }
+}
+
+// Speaks just enough HTTP/1.1 for one JSON-RPC exchange with bitcoind, closing the
+// connection afterwards.
+#[cfg(all(unix, feature = "unix-socket"))]
+fn post_unix_socket(
+    path: &str,
+    auth: &str,
+    body: &[u8],
+    timeout: Duration,
+) -> Result<(StatusCode, ResponseBody), BatchRpcError> {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    let invalid = |message: &str| BatchRpcError::InvalidResponse(message.into());
+
+    let mut stream = UnixStream::connect(path).map_err(BatchRpcError::Unreachable)?;
+    stream.set_read_timeout(Some(timeout))?;
+    write!(
+        stream,
+        "POST / HTTP/1.1\r\nHost: localhost\r\nAuthorization: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        auth,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .and_then(|code| StatusCode::from_u16(code).ok())
+        .ok_or_else(|| invalid("Malformed HTTP status line"))?;
+
+    let mut content_length = None;
+    let mut chunked = false;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse::<usize>().ok();
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.trim().eq_ignore_ascii_case("chunked");
+            }
+        }
+    }
+
+    let mut response = Vec::new();
+    if chunked {
+        loop {
+            let mut size = String::new();
+            reader.read_line(&mut size)?;
+            let size = size.split(';').next().unwrap_or_default().trim();
+            let size =
+                usize::from_str_radix(size, 16).map_err(|_| invalid("Malformed chunk size"))?;
+            if size == 0 {
+                break;
+            }
+            let start = response.len();
+            response.resize(start + size, 0);
+            reader.read_exact(&mut response[start..])?;
+            reader.read_exact(&mut [0; 2])?;
+        }
+    } else if let Some(len) = content_length {
+        response.resize(len, 0);
+        reader.read_exact(&mut response)?;
+    } else {
+        reader.read_to_end(&mut response)?;
+    }
+    Ok((status, ResponseBody::Buffered(response)))
+}
+
+#[cfg(not(all(unix, feature = "unix-socket")))]
+fn post_unix_socket(
+    path: &str,
+    _: &str,
+    _: &[u8],
+    _: Duration,
+) -> Result<(StatusCode, ResponseBody), BatchRpcError> {
+    Err(BatchRpcError::Unsupported(format!(
+        "{}{} needs the unix-socket feature on a Unix platform",
+        UNIX_SOCKET_SCHEME, path
+    )))
+}
+
 impl BatchRpcClient {
-    pub fn new(url: String, user: String, password: String) -> Result<Self, BatchRpcError> {
+    pub fn new(
+        urls: Vec<String>,
+        user: String,
+        password: String,
+        cache_size: usize,
+    ) -> Result<Self, BatchRpcError> {
+        if urls.is_empty() {
+            return Err(BatchRpcError::NoEndpoints);
+        }
+
         let mut headers = HeaderMap::new();
         headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
 
@@ -83,13 +336,121 @@ impl BatchRpcClient {
 
         Ok(BatchRpcClient {
             client: Arc::new(client),
-            url,
+            endpoints: Arc::new(urls.into_iter().map(Endpoint::new).collect()),
+            last_endpoint: Arc::new(Mutex::new(None)),
             auth,
-            cache: Arc::new(Mutex::new(HashMap::new())),
+            cache: Arc::new(Mutex::new(PrevTxCache {
+                entries: LruCache::new(NonZeroUsize::new(cache_size).unwrap_or(NonZeroUsize::MIN)),
+                hits: 0,
+                misses: 0,
+            })),
+            timeout: DEFAULT_TIMEOUT,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            stopper: None,
         })
     }
 
-    // Le reste du code reste inchangé...
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_max_batch_size(mut self, max_batch_size: usize) -> Self {
+        self.max_batch_size = max_batch_size.max(1);
+        self
+    }
+
+    pub fn with_stopper(mut self, stopper: Stopper) -> Self {
+        self.stopper = Some(stopper);
+        self
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        let cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        CacheStats {
+            hits: cache.hits,
+            misses: cache.misses,
+            len: cache.entries.len(),
+            capacity: cache.entries.cap().get(),
+        }
+    }
+
+    /// The endpoint that served the most recent successful request.
+    pub fn last_endpoint(&self) -> Option<String> {
+        self.last_endpoint
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    // Live endpoints are tried in order, then the ones still backing off, so a request
+    // only fails once every endpoint has refused the connection.
+    fn post<T: Serialize + ?Sized>(&self, body: &T) -> Result<ResponseBody, BatchRpcError> {
+        let mut headers = HeaderMap::new();
+        headers.insert("Authorization", HeaderValue::from_str(&self.auth).unwrap());
+
+        let (alive, dead): (Vec<_>, Vec<_>) = self.endpoints.iter().partition(|e| e.is_alive());
+        let mut last_error = None;
+        for endpoint in alive.iter().chain(&dead) {
+            let response = match endpoint.url.strip_prefix(UNIX_SOCKET_SCHEME) {
+                Some(path) => {
+                    post_unix_socket(path, &self.auth, &serde_json::to_vec(body)?, self.timeout)
+                }
+                None => self
+                    .client
+                    .post(&endpoint.url)
+                    .timeout(self.timeout)
+                    .headers(headers.clone())
+                    .json(body)
+                    .send()
+                    .map(|response| (response.status(), ResponseBody::Http(response)))
+                    .map_err(BatchRpcError::Http),
+            };
+            match response {
+                Ok((status, response)) => {
+                    endpoint.set_dead_until(None);
+                    debug!("RPC request served by {}", endpoint.url);
+
+                    if !status.is_success() {
+                        return Err(BatchRpcError::Rpc(format!("HTTP error: {}", status)));
+                    }
+                    *self.last_endpoint.lock().unwrap_or_else(|e| e.into_inner()) =
+                        Some(endpoint.url.clone());
+                    return Ok(response);
+                }
+                Err(e) if e.is_unavailable() => {
+                    warn!("RPC endpoint {} unavailable: {:?}", endpoint.url, e);
+                    endpoint.set_dead_until(Some(Instant::now() + ENDPOINT_BACKOFF));
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(last_error.unwrap_or(BatchRpcError::NoEndpoints))
+    }
+
+    // `post` on another thread, given up on once the timeout passes or the stopper
+    // fires. The abandoned request still ends within the transport timeout.
+    fn post_cancellable(&self, body: Value) -> Result<ResponseBody, BatchRpcError> {
+        let Some(stopper) = &self.stopper else {
+            return self.post(&body);
+        };
+        let (id, done) = stopper.subscribe().map_err(|_| BatchRpcError::Stopped)?;
+        let (tx, rx) = bounded(1);
+        let client = self.clone();
+        thread::spawn(move || tx.send(client.post(&body)).ok());
+        let result = select! {
+            recv(rx) -> result => result.unwrap_or_else(|_| {
+                Err(BatchRpcError::InvalidResponse("Request thread exited".into()))
+            }),
+            recv(done) -> _ => Err(BatchRpcError::Stopped),
+            recv(after(self.timeout)) -> _ => Err(BatchRpcError::Timeout(self.timeout)),
+        };
+        stopper.unsubscribe(id).ok();
+        result
+    }
+
     pub fn get_transactions(
         &self,
         txids: &[Txid],
@@ -98,15 +459,17 @@ impl BatchRpcClient {
             return Ok(vec![]);
         }
 
-        let mut cache = self.cache.lock().unwrap();
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
         let mut uncached_txids = Vec::new();
         let mut result_map = HashMap::new();
 
         for txid in txids {
-            if let Some(tx) = cache.get(txid) {
+            if let Some(tx) = cache.entries.get(txid) {
                 result_map.insert(*txid, tx.clone());
+                cache.hits += 1;
             } else {
                 uncached_txids.push(*txid);
+                cache.misses += 1;
             }
         }
 
@@ -117,7 +480,25 @@ impl BatchRpcClient {
                 .collect());
         }
 
-        let requests: Vec<RpcRequest> = uncached_txids
+        for chunk in uncached_txids.chunks(self.max_batch_size) {
+            for (txid, tx) in chunk.iter().zip(self.fetch_transactions(chunk)?) {
+                cache.entries.put(*txid, tx.clone());
+                result_map.insert(*txid, tx);
+            }
+        }
+
+        Ok(txids
+            .iter()
+            .map(|txid| result_map.get(txid).cloned().flatten())
+            .collect())
+    }
+
+    // Sends one `getrawtransaction` batch, answering in the order of `txids`.
+    fn fetch_transactions(
+        &self,
+        txids: &[Txid],
+    ) -> Result<Vec<Option<Transaction>>, BatchRpcError> {
+        let requests: Vec<RpcRequest> = txids
             .iter()
             .enumerate()
             .map(|(i, txid)| RpcRequest {
@@ -128,26 +509,32 @@ impl BatchRpcClient {
             })
             .collect();
 
-        let mut headers = HeaderMap::new();
-        headers.insert("Authorization", HeaderValue::from_str(&self.auth).unwrap());
-
-        let response = self
-            .client
-            .post(&self.url)
-            .headers(headers)
-            .json(&requests)
-            .send()?;
-
-        if !response.status().is_success() {
-            return Err(BatchRpcError::Rpc(format!(
-                "HTTP error: {}",
-                response.status()
-            )));
-        }
+        let response = self.post_cancellable(serde_json::to_value(&requests)?)?;
 
-        let responses: Vec<RpcResponse> = response.json()?;
+        // A batch bitcoind rejects as a whole is answered with a single error object.
+        let responses: Vec<RpcResponse> = match response.json()? {
+            Value::Array(responses) => responses
+                .into_iter()
+                .map(serde_json::from_value)
+                .collect::<Result<_, _>>()?,
+            value => {
+                let response: RpcResponse = serde_json::from_value(value)?;
+                return Err(match response.error {
+                    Some(error) => BatchRpcError::Rpc(error.message),
+                    None => BatchRpcError::InvalidResponse("Expected batch response".into()),
+                });
+            }
+        };
+        let mut responses: HashMap<u64, RpcResponse> = responses
+            .into_iter()
+            .filter_map(|response| Some((response.id?, response)))
+            .collect();
 
-        for (txid, response) in uncached_txids.iter().zip(responses.into_iter()) {
+        let mut transactions = Vec::with_capacity(txids.len());
+        for (i, txid) in txids.iter().enumerate() {
+            let response = responses.remove(&(i as u64)).ok_or_else(|| {
+                BatchRpcError::InvalidResponse(format!("Missing response for {}", txid))
+            })?;
             let tx = match response {
                 RpcResponse {
                     result: Some(value),
@@ -175,14 +562,10 @@ impl BatchRpcClient {
                 _ => None,
             };
 
-            cache.insert(*txid, tx.clone());
-            result_map.insert(*txid, tx);
+            transactions.push(tx);
         }
 
-        Ok(txids
-            .iter()
-            .map(|txid| result_map.get(txid).cloned().flatten())
-            .collect())
+        Ok(transactions)
     }
 
     pub fn get_block_hash(&self, height: u32) -> Result<BlockHash, BatchRpcError> {
@@ -193,22 +576,7 @@ impl BatchRpcClient {
             params: vec![json!(height)],
         };
 
-        let mut headers = HeaderMap::new();
-        headers.insert("Authorization", HeaderValue::from_str(&self.auth).unwrap());
-
-        let response = self
-            .client
-            .post(&self.url)
-            .headers(headers)
-            .json(&request)
-            .send()?;
-
-        if !response.status().is_success() {
-            return Err(BatchRpcError::Rpc(format!(
-                "HTTP error: {}",
-                response.status()
-            )));
-        }
+        let response = self.post(&request)?;
 
         let response: RpcResponse = response.json()?;
 
@@ -241,22 +609,42 @@ impl BatchRpcClient {
             params: vec![json!(hash.to_string()), json!(0)],
         };
 
-        let mut headers = HeaderMap::new();
-        headers.insert("Authorization", HeaderValue::from_str(&self.auth).unwrap());
+        let response = self.post(&request)?;
 
-        let response = self
-            .client
-            .post(&self.url)
-            .headers(headers)
-            .json(&request)
-            .send()?;
-
-        if !response.status().is_success() {
-            return Err(BatchRpcError::Rpc(format!(
-                "HTTP error: {}",
-                response.status()
-            )));
+        let response: RpcResponse = response.json()?;
+
+        match response {
+            RpcResponse {
+                result: Some(value),
+                error: None,
+                ..
+            } => {
+                let hex = value.as_str().ok_or_else(|| {
+                    BatchRpcError::InvalidResponse("Expected block hex string".into())
+                })?;
+                let bytes =
+                    hex::decode(hex).map_err(|e| BatchRpcError::InvalidResponse(e.to_string()))?;
+                bitcoin::consensus::deserialize(&bytes)
+                    .map_err(|e| BatchRpcError::InvalidResponse(e.to_string()))
+            }
+            RpcResponse {
+                error: Some(error), ..
+            } => Err(BatchRpcError::Rpc(error.message)),
+            _ => Err(BatchRpcError::InvalidResponse(
+                "Invalid response format".into(),
+            )),
         }
+    }
+
+    pub fn get_block_header(&self, hash: &BlockHash) -> Result<Header, BatchRpcError> {
+        let request = RpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: 0,
+            method: "getblockheader".to_string(),
+            params: vec![json!(hash.to_string()), json!(false)],
+        };
+
+        let response = self.post(&request)?;
 
         let response: RpcResponse = response.json()?;
 
@@ -267,7 +655,7 @@ impl BatchRpcClient {
                 ..
             } => {
                 let hex = value.as_str().ok_or_else(|| {
-                    BatchRpcError::InvalidResponse("Expected block hex string".into())
+                    BatchRpcError::InvalidResponse("Expected block header hex string".into())
                 })?;
                 let bytes =
                     hex::decode(hex).map_err(|e| BatchRpcError::InvalidResponse(e.to_string()))?;
@@ -283,30 +671,90 @@ impl BatchRpcClient {
         }
     }
 
-    pub fn get_blockchain_info(&self) -> Result<Value, BatchRpcError> {
+    /// Returns `None` when the output is spent or unknown.
+    pub fn get_tx_out(
+        &self,
+        txid: &Txid,
+        vout: u32,
+        include_mempool: bool,
+    ) -> Result<Option<TxOut>, BatchRpcError> {
         let request = RpcRequest {
             jsonrpc: "2.0".to_string(),
             id: 0,
-            method: "getblockchaininfo".to_string(),
+            method: "gettxout".to_string(),
+            params: vec![json!(txid.to_string()), json!(vout), json!(include_mempool)],
+        };
+
+        let response = self.post(&request)?;
+
+        let response: RpcResponse = response.json()?;
+
+        match response {
+            RpcResponse {
+                error: Some(error), ..
+            } => Err(BatchRpcError::Rpc(error.message)),
+            RpcResponse {
+                result: Some(value),
+                ..
+            } => {
+                let btc = result_field(&value, &["value"])?.as_f64().ok_or_else(|| {
+                    BatchRpcError::InvalidResponse("Expected number in field `value`".into())
+                })?;
+                let hex = result_field(&value, &["scriptPubKey", "hex"])?
+                    .as_str()
+                    .ok_or_else(|| {
+                        BatchRpcError::InvalidResponse(
+                            "Expected string in field `scriptPubKey.hex`".into(),
+                        )
+                    })?;
+                let script =
+                    hex::decode(hex).map_err(|e| BatchRpcError::InvalidResponse(e.to_string()))?;
+                Ok(Some(TxOut {
+                    value: Amount::from_btc(btc)
+                        .map_err(|e| BatchRpcError::InvalidResponse(e.to_string()))?,
+                    script_pubkey: ScriptBuf::from_bytes(script),
+                }))
+            }
+            RpcResponse { result: None, .. } => Ok(None),
+        }
+    }
+
+    pub fn get_network_info(&self) -> Result<NetworkInfo, BatchRpcError> {
+        let request = RpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: 0,
+            method: "getnetworkinfo".to_string(),
             params: vec![],
         };
 
-        let mut headers = HeaderMap::new();
-        headers.insert("Authorization", HeaderValue::from_str(&self.auth).unwrap());
+        let response = self.post(&request)?;
+
+        let response: RpcResponse = response.json()?;
 
-        let response = self
-            .client
-            .post(&self.url)
-            .headers(headers)
-            .json(&request)
-            .send()?;
-
-        if !response.status().is_success() {
-            return Err(BatchRpcError::Rpc(format!(
-                "HTTP error: {}",
-                response.status()
-            )));
+        match response {
+            RpcResponse {
+                result: Some(value),
+                error: None,
+                ..
+            } => Ok(serde_json::from_value(value)?),
+            RpcResponse {
+                error: Some(error), ..
+            } => Err(BatchRpcError::Rpc(error.message)),
+            _ => Err(BatchRpcError::InvalidResponse(
+                "Invalid response format".into(),
+            )),
         }
+    }
+
+    pub fn get_blockchain_info(&self) -> Result<Value, BatchRpcError> {
+        let request = RpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: 0,
+            method: "getblockchaininfo".to_string(),
+            params: vec![],
+        };
+
+        let response = self.post(&request)?;
 
         let response: RpcResponse = response.json()?;
 
@@ -325,3 +773,352 @@ impl BatchRpcClient {
         }
     }
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use bitcoin::{absolute::LockTime, consensus::serialize, hashes::Hash, transaction::Version};
+    use crossbeam_channel::Sender;
+
+    use crate::indexer::test_utils::test_rpc_server;
+    #[cfg(all(unix, feature = "unix-socket"))]
+    use crate::indexer::test_utils::test_unix_rpc_server;
+
+    use super::*;
+
+    fn test_transaction() -> Transaction {
+        Transaction {
+            version: Version::ONE,
+            lock_time: LockTime::ZERO,
+            input: vec![],
+            output: vec![TxOut {
+                value: Amount::from_sat(1),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_get_transactions_cache_hit() {
+        let tx = test_transaction();
+        let tx_hex = hex::encode(serialize(&tx));
+        let server = test_rpc_server(move |request| {
+            let responses: Vec<Value> = request
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|r| json!({"result": tx_hex, "error": null, "id": r["id"]}))
+                .collect();
+            json!(responses)
+        });
+        let client =
+            BatchRpcClient::new(vec![server.url.clone()], "".into(), "".into(), 10).unwrap();
+        let txid = tx.compute_txid();
+
+        let first = client.get_transactions(&[txid]).unwrap();
+        assert_eq!(first, vec![Some(tx.clone())]);
+        let second = client.get_transactions(&[txid]).unwrap();
+        assert_eq!(second, vec![Some(tx)]);
+
+        assert_eq!(server.requests(), 1);
+        let stats = client.cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.len), (1, 1, 1));
+        assert_eq!(stats.hit_rate(), 0.5);
+    }
+
+    #[test]
+    fn test_get_transactions_split_into_batches() {
+        let txs: Vec<Transaction> = (0..5)
+            .map(|i| Transaction {
+                lock_time: LockTime::from_consensus(i),
+                ..test_transaction()
+            })
+            .collect();
+        let by_txid: HashMap<String, String> = txs
+            .iter()
+            .map(|tx| (tx.compute_txid().to_string(), hex::encode(serialize(tx))))
+            .collect();
+        let server = test_rpc_server(move |request| {
+            let requests = request.as_array().unwrap();
+            assert!(requests.len() <= 2);
+            let responses: Vec<Value> = requests
+                .iter()
+                .map(|r| {
+                    let tx_hex = &by_txid[r["params"][0].as_str().unwrap()];
+                    json!({"result": tx_hex, "error": null, "id": r["id"]})
+                })
+                .collect();
+            json!(responses)
+        });
+        let client = BatchRpcClient::new(vec![server.url.clone()], "".into(), "".into(), 10)
+            .unwrap()
+            .with_max_batch_size(2);
+
+        let txids: Vec<Txid> = txs.iter().rev().map(|tx| tx.compute_txid()).collect();
+        let fetched = client.get_transactions(&txids).unwrap();
+        assert_eq!(fetched, txs.into_iter().rev().map(Some).collect::<Vec<_>>());
+        assert_eq!(server.requests(), 3);
+    }
+
+    #[test]
+    fn test_get_transactions_cache_evicts_least_recently_used() {
+        let client = BatchRpcClient::new(vec!["".into()], "".into(), "".into(), 1).unwrap();
+        let tx = test_transaction();
+        {
+            let mut cache = client.cache.lock().unwrap();
+            cache.entries.put(tx.compute_txid(), Some(tx.clone()));
+            cache.entries.put(Txid::all_zeros(), None);
+        }
+        let stats = client.cache_stats();
+        assert_eq!((stats.len, stats.capacity), (1, 1));
+        assert!(!client
+            .cache
+            .lock()
+            .unwrap()
+            .entries
+            .contains(&tx.compute_txid()));
+    }
+
+    fn unreachable_url() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    }
+
+    #[test]
+    fn test_failover_to_second_endpoint() {
+        let hash = BlockHash::all_zeros();
+        let server =
+            test_rpc_server(move |_| json!({"result": hash.to_string(), "error": null, "id": 0}));
+        let down = unreachable_url();
+        let client = BatchRpcClient::new(
+            vec![down.clone(), server.url.clone()],
+            "".into(),
+            "".into(),
+            10,
+        )
+        .unwrap();
+
+        assert_eq!(client.get_block_hash(1).unwrap(), hash);
+        assert_eq!(client.last_endpoint(), Some(server.url.clone()));
+        assert!(!client.endpoints[0].is_alive());
+
+        // the dead endpoint is skipped while it backs off
+        assert_eq!(client.get_block_hash(2).unwrap(), hash);
+        assert_eq!(server.requests(), 2);
+    }
+
+    #[test]
+    fn test_failover_all_endpoints_down() {
+        let client = BatchRpcClient::new(
+            vec![unreachable_url(), unreachable_url()],
+            "".into(),
+            "".into(),
+            10,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            client.get_block_hash(1),
+            Err(BatchRpcError::Http(_))
+        ));
+        assert!(client.endpoints.iter().all(|e| !e.is_alive()));
+        assert_eq!(client.last_endpoint(), None);
+    }
+
+    #[test]
+    fn test_get_network_info() {
+        let server = test_rpc_server(|request| {
+            assert_eq!(request["method"], "getnetworkinfo");
+            json!({
+                "result": {
+                    "version": 280100,
+                    "subversion": "/Satoshi:28.1.0/",
+                    "protocolversion": 70016,
+                    "connections": 10,
+                    "localrelay": true
+                },
+                "error": null,
+                "id": 0
+            })
+        });
+        let client =
+            BatchRpcClient::new(vec![server.url.clone()], "".into(), "".into(), 10).unwrap();
+
+        assert_eq!(
+            client.get_network_info().unwrap(),
+            NetworkInfo {
+                version: 280100,
+                subversion: "/Satoshi:28.1.0/".into(),
+                protocol_version: 70016,
+                connections: 10,
+            }
+        );
+    }
+
+    #[cfg(all(unix, feature = "unix-socket"))]
+    #[test]
+    fn test_unix_socket_endpoint() {
+        let path = std::env::temp_dir().join(format!("{}.sock", uuid::Uuid::new_v4()));
+        let server = test_unix_rpc_server(&path, |request| {
+            assert_eq!(request["method"], "getblockhash");
+            json!({"result": BlockHash::all_zeros().to_string(), "error": null, "id": 0})
+        });
+        let client =
+            BatchRpcClient::new(vec![server.url.clone()], "".into(), "".into(), 10).unwrap();
+
+        assert_eq!(client.get_block_hash(0).unwrap(), BlockHash::all_zeros());
+        assert_eq!(server.requests(), 1);
+        assert_eq!(client.last_endpoint(), Some(server.url.clone()));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(all(unix, feature = "unix-socket"))]
+    #[test]
+    fn test_unix_socket_failover() {
+        let path = std::env::temp_dir().join(format!("{}.sock", uuid::Uuid::new_v4()));
+        let server = test_rpc_server(
+            |_| json!({"result": BlockHash::all_zeros().to_string(), "error": null, "id": 0}),
+        );
+        let client = BatchRpcClient::new(
+            vec![
+                format!("{}{}", UNIX_SOCKET_SCHEME, path.display()),
+                server.url.clone(),
+            ],
+            "".into(),
+            "".into(),
+            10,
+        )
+        .unwrap();
+
+        assert_eq!(client.get_block_hash(0).unwrap(), BlockHash::all_zeros());
+        assert_eq!(client.last_endpoint(), Some(server.url.clone()));
+    }
+
+    #[test]
+    fn test_get_tx_out_unspent() {
+        let script = ScriptBuf::from_bytes(vec![0x51]);
+        let script_hex = script.to_hex_string();
+        let server = test_rpc_server(move |request| {
+            assert_eq!(request["method"], "gettxout");
+            assert_eq!(request["params"][1], 1);
+            json!({
+                "result": {"value": 0.0001, "scriptPubKey": {"hex": script_hex}},
+                "error": null,
+                "id": 0
+            })
+        });
+        let client =
+            BatchRpcClient::new(vec![server.url.clone()], "".into(), "".into(), 10).unwrap();
+
+        let tx_out = client.get_tx_out(&Txid::all_zeros(), 1, true).unwrap();
+        assert_eq!(
+            tx_out,
+            Some(TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey: script,
+            })
+        );
+    }
+
+    #[test]
+    fn test_get_transactions_batch_error_object() {
+        let server = test_rpc_server(
+            |_| json!({"result": null, "error": {"code": -32700, "message": "Parse error"}, "id": null}),
+        );
+        let client =
+            BatchRpcClient::new(vec![server.url.clone()], "".into(), "".into(), 10).unwrap();
+
+        assert!(matches!(
+            client.get_transactions(&[Txid::all_zeros()]),
+            Err(BatchRpcError::Rpc(message)) if message == "Parse error"
+        ));
+    }
+
+    #[test]
+    fn test_get_transactions_missing_response() {
+        let tx = test_transaction();
+        let tx_hex = hex::encode(serialize(&tx));
+        // Answers only the first request of the batch.
+        let server = test_rpc_server(
+            move |request| json!([{"result": tx_hex, "error": null, "id": request[0]["id"]}]),
+        );
+        let client =
+            BatchRpcClient::new(vec![server.url.clone()], "".into(), "".into(), 10).unwrap();
+
+        let missing = Txid::from_byte_array([1; 32]);
+        assert!(matches!(
+            client.get_transactions(&[tx.compute_txid(), missing]),
+            Err(BatchRpcError::InvalidResponse(message)) if message.contains(&missing.to_string())
+        ));
+    }
+
+    // Serves a batch that never gets answered, until the returned sender is dropped.
+    fn blocking_rpc_server() -> (crate::indexer::test_utils::TestRpcServer, Sender<()>) {
+        let (release, blocked) = bounded::<()>(0);
+        let server = test_rpc_server(move |_| {
+            blocked.recv().ok();
+            json!([])
+        });
+        (server, release)
+    }
+
+    #[test]
+    fn test_get_transactions_stopped() {
+        let (server, _release) = blocking_rpc_server();
+        let stopper = Stopper::new();
+        let client = BatchRpcClient::new(vec![server.url.clone()], "".into(), "".into(), 10)
+            .unwrap()
+            .with_stopper(stopper.clone());
+
+        let handle = thread::spawn(move || client.get_transactions(&[Txid::all_zeros()]));
+        while server.requests() == 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        stopper.stop().unwrap();
+        assert!(matches!(
+            handle.join().unwrap(),
+            Err(BatchRpcError::Stopped)
+        ));
+    }
+
+    #[test]
+    fn test_get_transactions_timeout() {
+        let (server, _release) = blocking_rpc_server();
+        let timeout = Duration::from_millis(100);
+        let client = BatchRpcClient::new(vec![server.url.clone()], "".into(), "".into(), 10)
+            .unwrap()
+            .with_timeout(timeout)
+            .with_stopper(Stopper::new());
+
+        let start = Instant::now();
+        assert!(matches!(
+            client.get_transactions(&[Txid::all_zeros()]),
+            Err(BatchRpcError::Timeout(t)) if t == timeout
+        ));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_get_tx_out_missing_field() {
+        let server = test_rpc_server(|_| json!({"result": {"value": 0.0001}, "id": 0}));
+        let client =
+            BatchRpcClient::new(vec![server.url.clone()], "".into(), "".into(), 10).unwrap();
+
+        assert!(matches!(
+            client.get_tx_out(&Txid::all_zeros(), 0, false),
+            Err(BatchRpcError::InvalidResponse(message)) if message.contains("`scriptPubKey.hex`")
+        ));
+    }
+
+    #[test]
+    fn test_get_tx_out_spent() {
+        let server = test_rpc_server(|_| json!({"result": null, "error": null, "id": 0}));
+        let client =
+            BatchRpcClient::new(vec![server.url.clone()], "".into(), "".into(), 10).unwrap();
+
+        assert_eq!(
+            client.get_tx_out(&Txid::all_zeros(), 0, false).unwrap(),
+            None
+        );
+    }
+}
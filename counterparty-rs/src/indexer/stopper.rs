@@ -5,12 +5,18 @@ use uuid::Uuid;
 
 pub type Done = Receiver<()>;
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Stopper {
     broadcaster: Broadcaster<()>,
     is_stopped: Arc<Mutex<bool>>,
 }
 
+impl Default for Stopper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Stopper {
     pub fn new() -> Self {
         Stopper {
@@ -1,10 +1,28 @@
 #![allow(clippy::expect_used, clippy::unwrap_used)]
-use std::thread;
-
-use bitcoin::{hashes::Hash, BlockHash};
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use bitcoin::{
+    block::{self, Header},
+    hashes::Hash,
+    Block, BlockHash, CompactTarget, Transaction, TxMerkleNode,
+};
 use crossbeam_channel::{unbounded, Receiver, Sender};
+use serde_json::Value;
 
-use super::{stopper::Stopper, types::error::Error};
+use super::{
+    config::{Config, Heights, LogLevel, Mode, Network, RequestQueuePolicy},
+    stopper::Stopper,
+    types::error::Error,
+};
 
 pub fn test_worker<T, U, F, I>(mut worker_fn: F, input_data: I) -> Vec<U>
 where
@@ -58,6 +76,148 @@ pub fn test_block_hash(i: u32) -> BlockHash {
     BlockHash::from_slice(&test_sha256_hash(i)).unwrap()
 }
 
+pub fn test_block(txdata: Vec<Transaction>) -> Block {
+    Block {
+        header: Header {
+            version: block::Version::ONE,
+            prev_blockhash: test_block_hash(1),
+            merkle_root: TxMerkleNode::all_zeros(),
+            time: 1234567890,
+            bits: CompactTarget::default(),
+            nonce: 0,
+        },
+        txdata,
+    }
+}
+
+pub fn test_config() -> Config {
+    Config {
+        rpc_addresses: vec!["".into()],
+        rpc_user: "".into(),
+        rpc_password: "".into(),
+        log_file: "".into(),
+        log_level: LogLevel::default(),
+        db_dir: "".into(),
+        consume_blocks: false,
+        start_height: None,
+        mode: Mode::Indexer,
+        prefixes: vec![b"CNTRPRTY".to_vec()],
+        address_version: vec![0x00],
+        p2sh_address_version: vec![0x05],
+        multisig_member_address_version: vec![0x00],
+        network: Network::Mainnet,
+        heights: Heights::new(Network::Mainnet),
+        json_format: false,
+        only_write_in_reorg_window: false,
+        enable_all_protocol_changes: false,
+        prev_tx_cache_size: 10,
+        block_cache_size: 10,
+        verbose_parse_errors: false,
+        fetch_window: 8,
+        max_outputs_parsed: None,
+        verify_merkle_root: false,
+        min_confirmations: 0,
+        electrum_scripthash_index: false,
+        potential_dispenser_index: false,
+        max_parse_errors_per_block: None,
+        counterparty_start_height: None,
+        request_queue_policy: RequestQueuePolicy::Block,
+        rpc_timeout: Duration::from_secs(30),
+        max_rpc_batch_size: 1000,
+        parse_coinbase: true,
+        store_raw_blocks: false,
+        message_separator: None,
+        address_normalizer: None,
+        on_reorg: None,
+    }
+}
+
+pub struct TestRpcServer {
+    pub url: String,
+    requests: Arc<AtomicUsize>,
+}
+
+impl TestRpcServer {
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+}
+
+/// Serves JSON-RPC over HTTP on a random local port, answering every request body
+/// with the value returned by `handler`. Each HTTP request is counted.
+pub fn test_rpc_server<F>(handler: F) -> TestRpcServer
+where
+    F: Fn(&Value) -> Value + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test rpc server");
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(AtomicUsize::new(0));
+    let requests_clone = requests.clone();
+
+    thread::spawn(move || serve_rpc(listener.incoming(), handler, requests_clone));
+
+    TestRpcServer { url, requests }
+}
+
+/// Like `test_rpc_server`, listening on a Unix socket at `path`.
+#[cfg(all(unix, feature = "unix-socket"))]
+pub fn test_unix_rpc_server<F>(path: &std::path::Path, handler: F) -> TestRpcServer
+where
+    F: Fn(&Value) -> Value + Send + 'static,
+{
+    let listener =
+        std::os::unix::net::UnixListener::bind(path).expect("Failed to bind test rpc socket");
+    let url = format!("unix://{}", path.display());
+    let requests = Arc::new(AtomicUsize::new(0));
+    let requests_clone = requests.clone();
+
+    thread::spawn(move || serve_rpc(listener.incoming(), handler, requests_clone));
+
+    TestRpcServer { url, requests }
+}
+
+fn serve_rpc<S, F>(
+    incoming: impl Iterator<Item = std::io::Result<S>>,
+    handler: F,
+    requests: Arc<AtomicUsize>,
+) where
+    for<'a> &'a S: Read + Write,
+    F: Fn(&Value) -> Value,
+{
+    for stream in incoming {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        let mut reader = BufReader::new(&stream);
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+                break;
+            }
+            if let Some(len) = line.to_lowercase().strip_prefix("content-length:") {
+                content_length = len.trim().parse().unwrap_or(0);
+            }
+        }
+        let mut body = vec![0; content_length];
+        if reader.read_exact(&mut body).is_err() {
+            continue;
+        }
+        requests.fetch_add(1, Ordering::SeqCst);
+
+        let request: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+        let response = handler(&request).to_string();
+        write!(
+                &stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                response.len(),
+                response
+            )
+            .ok();
+    }
+}
+
 #[macro_export]
 macro_rules! new_test_db {
     () => {{
@@ -17,24 +17,32 @@ pub fn to_cf_name<T>() -> String {
     })
 }
 
-pub fn get_cf_names() -> [String; 4] {
+pub fn get_cf_names() -> [String; 8] {
     [
         to_cf_name::<ScriptHashHasOutputsInBlockAtHeight>(),
         to_cf_name::<BlockAtHeightSpentOutputInTx>(),
         to_cf_name::<TxInBlockAtHeight>(),
         to_cf_name::<BlockAtHeightHasHash>(),
+        to_cf_name::<RevealCommitsToTxInBlockAtHeight>(),
+        to_cf_name::<ElectrumScriptHashHasOutputsInBlockAtHeight>(),
+        to_cf_name::<PotentialDispenserAtOutput>(),
+        to_cf_name::<RawBlockBytesAtHeight>(),
     ]
 }
-pub const CF_PREFIX_LENGTHS: [usize; 4] = [20, 36, 32, 4];
-pub fn get_cf_index_names() -> [String; 4] {
+pub const CF_PREFIX_LENGTHS: [usize; 8] = [20, 36, 32, 4, 32, 32, 36, 4];
+pub fn get_cf_index_names() -> [String; 8] {
     [
         to_cf_name::<ScriptHashHasOutputsInBlockAtHeight>() + INDEX_CF_NAME_SUFFIX,
         to_cf_name::<BlockAtHeightSpentOutputInTx>() + INDEX_CF_NAME_SUFFIX,
         to_cf_name::<TxInBlockAtHeight>() + INDEX_CF_NAME_SUFFIX,
         to_cf_name::<BlockAtHeightHasHash>() + INDEX_CF_NAME_SUFFIX,
+        to_cf_name::<RevealCommitsToTxInBlockAtHeight>() + INDEX_CF_NAME_SUFFIX,
+        to_cf_name::<ElectrumScriptHashHasOutputsInBlockAtHeight>() + INDEX_CF_NAME_SUFFIX,
+        to_cf_name::<PotentialDispenserAtOutput>() + INDEX_CF_NAME_SUFFIX,
+        to_cf_name::<RawBlockBytesAtHeight>() + INDEX_CF_NAME_SUFFIX,
     ]
 }
-pub const CF_INDEX_PREFIX_LENGTHS: [usize; 4] = [4, 4, 4, 4];
+pub const CF_INDEX_PREFIX_LENGTHS: [usize; 8] = [4, 4, 4, 4, 4, 4, 4, 4];
 pub const INDEX_CF_NAME_SUFFIX: &str = "_index";
 
 pub fn make_key(parts: &[Vec<u8>]) -> Vec<u8> {
@@ -154,6 +162,71 @@ impl FromEntry for ScriptHashHasOutputsInBlockAtHeight {
     }
 }
 
+// Keyed by the Electrum protocol scripthash (reversed single SHA256 of the scriptPubKey)
+// rather than the P2SH-style hash above.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ElectrumScriptHashHasOutputsInBlockAtHeight {
+    pub script_hash: [u8; 32],
+    pub height: u32,
+}
+
+impl ToEntry for ElectrumScriptHashHasOutputsInBlockAtHeight {
+    // [script_hash (32 bytes)][height (4 bytes)]
+    fn to_entry(&self) -> (Vec<u8>, Vec<u8>) {
+        let key = make_key(&[
+            self.script_hash.to_vec(),
+            self.height.to_be_bytes().to_vec(),
+        ]);
+        (key, Vec::new())
+    }
+
+    fn to_index(&self) -> (Vec<u8>, Vec<u8>) {
+        let key = make_key(&[
+            self.height.to_be_bytes().to_vec(),
+            self.script_hash.to_vec(),
+        ]);
+        (key, Vec::new())
+    }
+
+    fn cf_name(&self) -> String {
+        to_cf_name::<Self>()
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+impl FromEntry for ElectrumScriptHashHasOutputsInBlockAtHeight {
+    fn from_entry((key, _): Entry) -> Result<Self, Error> {
+        if key.len() != 36 {
+            return Err(Error::KeyParse(
+                "ElectrumScriptHashHasOutputsInBlockAtHeight entry".into(),
+            ));
+        }
+        let script_hash = <[u8; 32]>::try_from(&key[0..32])?;
+        let height = u32::from_be_bytes(key[32..36].try_into()?);
+        Ok(ElectrumScriptHashHasOutputsInBlockAtHeight {
+            script_hash,
+            height,
+        })
+    }
+
+    fn from_index((key, _): Entry) -> Result<Self, Error> {
+        if key.len() != 36 {
+            return Err(Error::KeyParse(
+                "ElectrumScriptHashHasOutputsInBlockAtHeight index".into(),
+            ));
+        }
+        let height = u32::from_be_bytes(key[0..4].try_into()?);
+        let script_hash = <[u8; 32]>::try_from(&key[4..36])?;
+        Ok(ElectrumScriptHashHasOutputsInBlockAtHeight {
+            script_hash,
+            height,
+        })
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TxidVoutPrefix {
     pub txid: [u8; 32],
@@ -331,6 +404,219 @@ impl FromEntry for BlockAtHeightHasHash {
     }
 }
 
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RevealCommitsToTxInBlockAtHeight {
+    pub reveal_txid: [u8; 32],
+    pub commit_txid: [u8; 32],
+    pub height: u32,
+}
+
+impl ToEntry for RevealCommitsToTxInBlockAtHeight {
+    // [reveal_txid (32 bytes)][height (4 bytes)] -> [commit_txid (32 bytes)]
+    fn to_entry(&self) -> (Vec<u8>, Vec<u8>) {
+        let key = make_key(&[
+            self.reveal_txid.to_vec(),
+            self.height.to_be_bytes().to_vec(),
+        ]);
+        (key, self.commit_txid.to_vec())
+    }
+
+    fn to_index(&self) -> (Vec<u8>, Vec<u8>) {
+        let key = make_key(&[
+            self.height.to_be_bytes().to_vec(),
+            self.reveal_txid.to_vec(),
+        ]);
+        (key, self.commit_txid.to_vec())
+    }
+
+    fn cf_name(&self) -> String {
+        to_cf_name::<Self>()
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+impl FromEntry for RevealCommitsToTxInBlockAtHeight {
+    fn from_entry((key, value): Entry) -> Result<Self, Error> {
+        if key.len() != 36 {
+            return Err(Error::KeyParse(
+                "RevealCommitsToTxInBlockAtHeight entry".into(),
+            ));
+        }
+
+        if value.len() != 32 {
+            return Err(Error::ValueParse("RevealCommitsToTxInBlockAtHeight".into()));
+        }
+
+        let reveal_txid = <[u8; 32]>::try_from(&key[0..32])?;
+        let height = u32::from_be_bytes(key[32..36].try_into()?);
+        let commit_txid = <[u8; 32]>::try_from(&value[..])?;
+        Ok(RevealCommitsToTxInBlockAtHeight {
+            reveal_txid,
+            commit_txid,
+            height,
+        })
+    }
+
+    fn from_index((key, value): Entry) -> Result<Self, Error> {
+        if key.len() != 36 {
+            return Err(Error::KeyParse(
+                "RevealCommitsToTxInBlockAtHeight index".into(),
+            ));
+        }
+
+        if value.len() != 32 {
+            return Err(Error::ValueParse("RevealCommitsToTxInBlockAtHeight".into()));
+        }
+
+        let height = u32::from_be_bytes(key[0..4].try_into()?);
+        let reveal_txid = <[u8; 32]>::try_from(&key[4..36])?;
+        let commit_txid = <[u8; 32]>::try_from(&value[..])?;
+        Ok(RevealCommitsToTxInBlockAtHeight {
+            reveal_txid,
+            commit_txid,
+            height,
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PotentialDispenserAtOutput {
+    pub txid: [u8; 32],
+    pub vout: u32,
+    pub destination: String,
+    pub value: u64,
+    pub height: u32,
+}
+
+impl PotentialDispenserAtOutput {
+    fn value_bytes(&self) -> Vec<u8> {
+        make_key(&[
+            self.value.to_be_bytes().to_vec(),
+            self.destination.as_bytes().to_vec(),
+        ])
+    }
+
+    fn parse_value(value: &[u8]) -> Result<(u64, String), Error> {
+        if value.len() < 8 {
+            return Err(Error::ValueParse("PotentialDispenserAtOutput".into()));
+        }
+        let destination = String::from_utf8(value[8..].to_vec())
+            .map_err(|_| Error::ValueParse("PotentialDispenserAtOutput destination".into()))?;
+        Ok((u64::from_be_bytes(value[0..8].try_into()?), destination))
+    }
+}
+
+impl ToEntry for PotentialDispenserAtOutput {
+    // [txid (32 bytes)][vout (4 bytes)][height (4 bytes)] -> [value (8 bytes)][destination]
+    fn to_entry(&self) -> (Vec<u8>, Vec<u8>) {
+        let key = make_key(&[
+            self.txid.to_vec(),
+            self.vout.to_be_bytes().to_vec(),
+            self.height.to_be_bytes().to_vec(),
+        ]);
+        (key, self.value_bytes())
+    }
+
+    fn to_index(&self) -> (Vec<u8>, Vec<u8>) {
+        let key = make_key(&[
+            self.height.to_be_bytes().to_vec(),
+            self.txid.to_vec(),
+            self.vout.to_be_bytes().to_vec(),
+        ]);
+        (key, self.value_bytes())
+    }
+
+    fn cf_name(&self) -> String {
+        to_cf_name::<Self>()
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+impl FromEntry for PotentialDispenserAtOutput {
+    fn from_entry((key, value): Entry) -> Result<Self, Error> {
+        if key.len() != 40 {
+            return Err(Error::KeyParse("PotentialDispenserAtOutput entry".into()));
+        }
+        let txid = <[u8; 32]>::try_from(&key[0..32])?;
+        let vout = u32::from_be_bytes(key[32..36].try_into()?);
+        let height = u32::from_be_bytes(key[36..40].try_into()?);
+        let (value, destination) = Self::parse_value(&value)?;
+        Ok(PotentialDispenserAtOutput {
+            txid,
+            vout,
+            destination,
+            value,
+            height,
+        })
+    }
+
+    fn from_index((key, value): Entry) -> Result<Self, Error> {
+        if key.len() != 40 {
+            return Err(Error::KeyParse("PotentialDispenserAtOutput index".into()));
+        }
+        let height = u32::from_be_bytes(key[0..4].try_into()?);
+        let txid = <[u8; 32]>::try_from(&key[4..36])?;
+        let vout = u32::from_be_bytes(key[36..40].try_into()?);
+        let (value, destination) = Self::parse_value(&value)?;
+        Ok(PotentialDispenserAtOutput {
+            txid,
+            vout,
+            destination,
+            value,
+            height,
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RawBlockBytesAtHeight {
+    pub height: u32,
+    pub bytes: Vec<u8>,
+}
+
+impl ToEntry for RawBlockBytesAtHeight {
+    // [height (4 bytes)] -> [consensus encoded block]
+    fn to_entry(&self) -> (Vec<u8>, Vec<u8>) {
+        let key = make_key(&[self.height.to_be_bytes().to_vec()]);
+        (key, self.bytes.clone())
+    }
+
+    fn to_index(&self) -> (Vec<u8>, Vec<u8>) {
+        self.to_entry()
+    }
+
+    fn cf_name(&self) -> String {
+        to_cf_name::<Self>()
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+impl FromEntry for RawBlockBytesAtHeight {
+    fn from_entry((key, value): Entry) -> Result<Self, Error> {
+        if key.len() != 4 {
+            return Err(Error::KeyParse("RawBlockBytesAtHeight entry".into()));
+        }
+        let height = u32::from_be_bytes(key.as_slice().try_into()?);
+        Ok(RawBlockBytesAtHeight {
+            height,
+            bytes: value,
+        })
+    }
+
+    fn from_index(entry: Entry) -> Result<Self, Error> {
+        Self::from_entry(entry)
+    }
+}
+
 #[cfg(test)]
 #[allow(clippy::unwrap_used)]
 mod tests {
@@ -365,6 +651,33 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_electrum_script_hash_has_outputs_in_block_at_height() {
+        let original = ElectrumScriptHashHasOutputsInBlockAtHeight {
+            script_hash: test_sha256_hash(7),
+            height: 12345,
+        };
+
+        let entry = original.to_entry();
+        assert!(entry.1.is_empty());
+        assert_eq!(
+            original,
+            ElectrumScriptHashHasOutputsInBlockAtHeight::from_entry(entry).unwrap()
+        );
+
+        let index = original.to_index();
+        assert!(index.1.is_empty());
+        assert_eq!(
+            original,
+            ElectrumScriptHashHasOutputsInBlockAtHeight::from_index(index).unwrap()
+        );
+
+        assert_eq!(
+            original.cf_name(),
+            "electrum_script_hash_has_outputs_in_block_at_height"
+        );
+    }
+
     #[test]
     fn test_block_at_height_spent_output_in_tx() {
         let original = BlockAtHeightSpentOutputInTx {
@@ -423,4 +736,72 @@ mod tests {
 
         assert_eq!(original.cf_name(), "block_at_height_has_hash")
     }
+
+    #[test]
+    fn test_raw_block_bytes_at_height() {
+        let original = RawBlockBytesAtHeight {
+            height: 123,
+            bytes: vec![1, 2, 3],
+        };
+
+        let entry = original.to_entry();
+        assert_eq!(original, RawBlockBytesAtHeight::from_entry(entry).unwrap());
+
+        let index = original.to_index();
+        assert_eq!(original, RawBlockBytesAtHeight::from_index(index).unwrap());
+
+        assert_eq!(original.cf_name(), "raw_block_bytes_at_height")
+    }
+
+    #[test]
+    fn test_reveal_commits_to_tx_in_block_at_height() {
+        let original = RevealCommitsToTxInBlockAtHeight {
+            reveal_txid: test_sha256_hash(5),
+            commit_txid: test_sha256_hash(6),
+            height: 890,
+        };
+
+        let entry = original.to_entry();
+        assert_eq!(entry.1, test_sha256_hash(6).to_vec());
+        assert_eq!(
+            original,
+            RevealCommitsToTxInBlockAtHeight::from_entry(entry).unwrap()
+        );
+
+        let index = original.to_index();
+        assert_eq!(
+            original,
+            RevealCommitsToTxInBlockAtHeight::from_index(index).unwrap()
+        );
+
+        assert_eq!(
+            original.cf_name(),
+            "reveal_commits_to_tx_in_block_at_height"
+        )
+    }
+
+    #[test]
+    fn test_potential_dispenser_at_output() {
+        let original = PotentialDispenserAtOutput {
+            txid: test_sha256_hash(3),
+            vout: 2,
+            destination: "3P14159f73E4gFr7JterCCQh9QjiTjiZrG".into(),
+            value: 5000,
+            height: 900000,
+        };
+
+        let entry = original.to_entry();
+        assert_eq!(
+            original,
+            PotentialDispenserAtOutput::from_entry(entry).unwrap()
+        );
+
+        let index = original.to_index();
+        assert_eq!(
+            original,
+            PotentialDispenserAtOutput::from_index(index).unwrap()
+        );
+
+        assert_eq!(original.cf_name(), "potential_dispenser_at_output")
+    }
 }
@@ -50,6 +50,24 @@ pub enum Error {
     Database(String),
     #[error("System error: {0}")]
     System(String),
+    #[error("Config error: {0}")]
+    Config(String),
+    #[error("Merkle root mismatch for block {0}")]
+    MerkleRootMismatch(String),
+    #[error("Request queue is full")]
+    Busy,
+    #[error("Too many parse errors in block {height}: {count}")]
+    TooManyParseErrors { height: u32, count: usize },
+    #[error("Network mismatch: config expects {expected}, node is on {actual}")]
+    NetworkMismatch { expected: String, actual: String },
+    #[error("CBOR error: {0}")]
+    Cbor(#[from] serde_cbor::Error),
+    #[error("Block hash mismatch: requested {requested}, got {got}")]
+    BlockHashMismatch { requested: String, got: String },
+    #[error("Unknown height for block {0}")]
+    UnknownHeight(String),
+    #[error("Consensus decode error: {0}")]
+    ConsensusDecode(#[from] bitcoin::consensus::encode::Error),
 }
 
 impl<T> From<SendError<T>> for Error {
@@ -0,0 +1,24 @@
+use std::fmt;
+
+// A block height. The parsing API takes this rather than a bare u32 so a height can't
+// be passed where a vout index, sequence or count is expected, or the other way round.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Height(pub u32);
+
+impl From<u32> for Height {
+    fn from(height: u32) -> Self {
+        Height(height)
+    }
+}
+
+impl From<Height> for u32 {
+    fn from(height: Height) -> Self {
+        height.0
+    }
+}
+
+impl fmt::Display for Height {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
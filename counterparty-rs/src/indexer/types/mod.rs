@@ -1,3 +1,4 @@
 pub mod entry;
 pub mod error;
+pub mod height;
 pub mod pipeline;
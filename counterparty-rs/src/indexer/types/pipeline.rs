@@ -2,18 +2,17 @@ use bitcoin::BlockHash;
 use crossbeam_channel::{Receiver, Sender};
 
 use crate::indexer::block::{Block, ToBlock};
-use crate::indexer::config::{Config, Mode};
+use crate::indexer::config::Config;
 
-use super::{entry::ToEntry, error::Error};
+use super::{entry::ToEntry, error::Error, height::Height};
 
 pub type ChanOut = (Sender<Box<Block>>, Receiver<Box<Block>>);
 
 pub trait BlockHasEntries {
-    fn get_entries(&self, mode: Mode, height: u32) -> Vec<Box<dyn ToEntry>>;
-}
-
-pub trait BlockHasPrevBlockHash {
-    fn get_prev_block_hash(&self) -> &BlockHash;
+    // `parsed` is this block as returned by `ToBlock::to_block`, for entries that
+    // depend on how its outputs parse.
+    fn get_entries(&self, config: &Config, height: Height, parsed: &Block)
+        -> Vec<Box<dyn ToEntry>>;
 }
 
 pub trait HasHeight {
@@ -36,6 +35,20 @@ pub struct PipelineDataInitial {
     pub rollback_height: Option<u32>,
 }
 
+impl HasHeight for Block {
+    fn get_height(&self) -> u32 {
+        self.height
+    }
+
+    fn get_target_height(&self) -> u32 {
+        self.height
+    }
+
+    fn get_rollback_height(&self) -> Option<u32> {
+        None
+    }
+}
+
 impl HasHeight for PipelineDataInitial {
     fn get_height(&self) -> u32 {
         self.height
@@ -101,9 +114,9 @@ impl<B: BlockHasEntries + ToBlock> Transition<Box<PipelineDataWithEntries<B>>, C
         self: Box<Self>,
         config: Config,
     ) -> Result<((), Box<PipelineDataWithEntries<B>>), Error> {
-        let height = self.get_height();
-        let entries = self.block.get_entries(config.mode, height);
-        let block = self.block.to_block(config, height);
+        let height = Height(self.get_height());
+        let block = self.block.to_block(config.clone(), height)?;
+        let entries = self.block.get_entries(&config, height, &block);
         Ok((
             (),
             Box::new(PipelineDataWithEntries {
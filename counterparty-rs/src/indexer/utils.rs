@@ -5,6 +5,10 @@ use std::{
     time::{Duration, Instant},
 };
 
+use bitcoin::{
+    hashes::{sha256, Hash},
+    Script,
+};
 use crossbeam_channel::{after, select, unbounded, Receiver, Sender};
 use rand::{thread_rng, Rng};
 use tracing::{debug, error};
@@ -103,11 +107,18 @@ where
     result
 }
 
+// Electrum protocol scripthash: the single SHA256 of the scriptPubKey, byte-reversed.
+pub fn electrum_scripthash(script: &Script) -> [u8; 32] {
+    let mut hash = sha256::Hash::hash(script.as_bytes()).to_byte_array();
+    hash.reverse();
+    hash
+}
+
 pub fn in_reorg_window(height: u32, target_height: u32, reorg_window: u32) -> bool {
     height >= target_height - reorg_window
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Broadcaster<T> {
     subscribers: Arc<Mutex<HashMap<Uuid, Sender<T>>>>,
 }
@@ -144,3 +155,22 @@ where
         Ok(())
     }
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use bitcoin::ScriptBuf;
+
+    use super::*;
+
+    #[test]
+    fn test_electrum_scripthash() {
+        // P2PKH for 1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa, from the Electrum protocol docs.
+        let script =
+            ScriptBuf::from_hex("76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac").unwrap();
+        assert_eq!(
+            hex::encode(electrum_scripthash(&script)),
+            "8b01df4e368ea28f8dc0423bcf7a4923e3a12d307c875e47a0cfbf90b5c39161"
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use bitcoin::hashes::Hash;
+use crossbeam_channel::{select, Receiver, Sender};
+
+use crate::indexer::{
+    bitcoin_client::BitcoinRpc,
+    database::DatabaseOps,
+    stopper::Stopper,
+    types::{
+        entry::{BlockAtHeightHasHash, ToEntry, WritableEntry},
+        error::Error,
+        pipeline::{HasHeight, PipelineDataInitial},
+    },
+    utils::{in_reorg_window, with_retry},
+};
+
+// Populates `BlockAtHeightHasHash` from block headers alone, in place of the
+// fetcher/extractor/writer stages. Run with a single worker so heights are written in order.
+pub fn new<C, B, D>(
+    client: C,
+    db: D,
+    reorg_window: u32,
+) -> impl Fn(Receiver<Box<PipelineDataInitial>>, Sender<()>, Stopper) -> Result<(), Error> + Clone
+where
+    C: BitcoinRpc<B>,
+    D: DatabaseOps,
+{
+    move |rx, _, stopper| {
+        let (_, done) = stopper.subscribe()?;
+        loop {
+            select! {
+              recv(done) -> _ => return Ok(()),
+              recv(rx) -> result => {
+                let data = match result {
+                    Ok(data) => data,
+                    Err(_) => return Ok(()),
+                };
+
+                let height = data.get_height();
+                let hash = with_retry(
+                    stopper.clone(),
+                    || client.get_block_hash(height),
+                    format!("Error fetching block hash for height {}", height),
+                )?;
+
+                let header = with_retry(
+                    stopper.clone(),
+                    || client.get_block_header(&hash),
+                    format!("Error fetching block header for hash {}", &hash),
+                )?;
+                if header.block_hash() != hash {
+                    return Err(Error::BitcoinRpc(format!(
+                        "Header at height {} does not hash to {}",
+                        height, hash
+                    )));
+                }
+
+                let entries: Vec<Box<dyn ToEntry>> =
+                    vec![Box::new(WritableEntry::new(BlockAtHeightHasHash {
+                        height,
+                        hash: hash.as_byte_array().to_owned(),
+                    }))];
+                let min_index_height =
+                    if in_reorg_window(height + 1, data.get_target_height(), reorg_window) {
+                        Some(height.saturating_sub(reorg_window))
+                    } else {
+                        None
+                    };
+                db.write_batch(|batch| {
+                    db.put_entries(batch, min_index_height, &entries)?;
+                    db.put_max_block_height(batch, height)
+                })?;
+              }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use bitcoin::{block::Header, Block, BlockHash, TxOut, Txid};
+    use crossbeam_channel::unbounded;
+
+    use super::*;
+    use crate::{
+        indexer::{
+            database::Database, rpc_client::NetworkInfo, test_utils::test_block,
+            types::entry::get_cf_names,
+        },
+        new_test_db,
+    };
+
+    #[derive(Clone)]
+    struct HeadersOnlyRpc {
+        headers: Arc<Vec<Header>>,
+        get_block_calls: Arc<AtomicUsize>,
+    }
+
+    impl BitcoinRpc<Block> for HeadersOnlyRpc {
+        fn get_block_hash(&self, height: u32) -> Result<BlockHash, Error> {
+            Ok(self.headers[height as usize].block_hash())
+        }
+
+        fn get_block(&self, _: &BlockHash) -> Result<Box<Block>, Error> {
+            self.get_block_calls.fetch_add(1, Ordering::SeqCst);
+            Err(Error::BitcoinRpc("get_block called".into()))
+        }
+
+        fn get_block_header(&self, hash: &BlockHash) -> Result<Header, Error> {
+            Ok(*self
+                .headers
+                .iter()
+                .find(|h| h.block_hash() == *hash)
+                .unwrap())
+        }
+
+        fn get_blockchain_height(&self) -> Result<u32, Error> {
+            Ok(self.headers.len() as u32 - 1)
+        }
+
+        fn get_network_info(&self) -> Result<NetworkInfo, Error> {
+            Err(Error::BitcoinRpc("getnetworkinfo not mocked".into()))
+        }
+
+        fn get_chain(&self) -> Result<String, Error> {
+            Err(Error::BitcoinRpc("getblockchaininfo not mocked".into()))
+        }
+
+        fn get_tx_out(&self, _: &Txid, _: u32, _: bool) -> Result<Option<TxOut>, Error> {
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn test_headers_only_block_hash_entries() {
+        let headers: Vec<Header> = (0..4)
+            .map(|nonce| Header {
+                nonce,
+                ..test_block(vec![]).header
+            })
+            .collect();
+        let client = HeadersOnlyRpc {
+            headers: Arc::new(headers.clone()),
+            get_block_calls: Arc::new(AtomicUsize::new(0)),
+        };
+        let db = new_test_db!().unwrap();
+
+        let (tx, rx) = unbounded();
+        for height in 1..4 {
+            tx.send(Box::new(PipelineDataInitial {
+                height,
+                target_height: 1000,
+                rollback_height: None,
+            }))
+            .unwrap();
+        }
+        drop(tx);
+        let (out, _) = unbounded();
+        new(client.clone(), db.clone(), 50)(rx, out, Stopper::new()).unwrap();
+
+        assert_eq!(client.get_block_calls.load(Ordering::SeqCst), 0);
+        assert_eq!(db.get_max_block_height().unwrap(), 3);
+        for (height, header) in headers.iter().enumerate().skip(1) {
+            assert_eq!(
+                db.block_at_height_has_hash(height as u32).unwrap(),
+                Some(header.block_hash().as_byte_array().to_vec())
+            );
+        }
+        for (cf_name, values) in db.list_all_values().unwrap() {
+            if cf_name != get_cf_names()[3] {
+                assert!(values.is_empty(), "unexpected entries in {}", cf_name);
+            }
+        }
+    }
+}
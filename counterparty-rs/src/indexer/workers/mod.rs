@@ -8,7 +8,9 @@ use super::{stopper::Stopper, types::error::Error};
 pub mod consumer;
 pub mod extractor;
 pub mod fetcher;
+pub mod headers;
 pub mod orderer;
+pub mod parser;
 pub mod producer;
 pub mod reporter;
 pub mod writer;
@@ -0,0 +1,127 @@
+use std::thread::JoinHandle;
+
+use bitcoin::Block;
+use crossbeam_channel::{select, unbounded, Receiver, Sender};
+
+use crate::indexer::{
+    bitcoin_client::parse_block,
+    block::Block as CrateBlock,
+    config::Config,
+    stopper::Stopper,
+    types::{error::Error, height::Height},
+};
+
+use super::{new_worker_pool, orderer};
+
+type BlockReceiver = Receiver<Box<(Height, Block)>>;
+type ParsedSender = Sender<Box<CrateBlock>>;
+
+pub fn new(
+    config: Config,
+) -> impl Fn(BlockReceiver, ParsedSender, Stopper) -> Result<(), Error> + Clone {
+    move |rx, tx, stopper| {
+        let (_, done) = stopper.subscribe()?;
+        loop {
+            select! {
+                recv(done) -> _ => return Ok(()),
+                recv(rx) -> result => {
+                    let (height, block) = match result {
+                        Ok(data) => *data,
+                        Err(_) => return Ok(()),
+                    };
+                    let parsed = parse_block(block, &config, height, true)?;
+                    if tx.send(Box::new(parsed)).is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Parses blocks across a pool and hands them on in height order. Blocks must arrive
+// with consecutive heights starting at `start_height`; a parse error stops the pool.
+pub struct ParseWorker {
+    config: Config,
+    n: usize,
+}
+
+impl ParseWorker {
+    pub fn new(config: Config, n: usize) -> Self {
+        ParseWorker { config, n }
+    }
+
+    pub fn start(
+        self,
+        start_height: u32,
+        rx: BlockReceiver,
+        tx: ParsedSender,
+        stopper: Stopper,
+    ) -> Result<Vec<JoinHandle<Result<(), Error>>>, Error> {
+        let (tx_parsed, rx_parsed) = unbounded();
+        let mut handles = new_worker_pool(
+            "Parser".into(),
+            self.n.max(1),
+            rx,
+            tx_parsed,
+            stopper.clone(),
+            new(self.config),
+        )?;
+        handles.append(&mut new_worker_pool(
+            "ParseOrderer".into(),
+            1,
+            rx_parsed,
+            tx,
+            stopper,
+            orderer::new(start_height),
+        )?);
+        Ok(handles)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crossbeam_channel::bounded;
+
+    use crate::indexer::test_utils::{test_block, test_block_hash, test_config};
+
+    use super::*;
+
+    #[test]
+    fn test_parse_worker_preserves_order() {
+        let blocks: Vec<_> = (0..3)
+            .map(|i| {
+                let mut block = test_block(vec![]);
+                block.header.prev_blockhash = test_block_hash(i);
+                block
+            })
+            .collect();
+        let (tx_in, rx_in) = bounded(3);
+        let (tx_out, rx_out) = bounded(3);
+        let stopper = Stopper::new();
+        let handles = ParseWorker::new(test_config(), 3)
+            .start(10, rx_in, tx_out, stopper.clone())
+            .unwrap();
+
+        // Sent out of order; the orderer puts them back.
+        for i in [2, 0, 1] {
+            tx_in
+                .send(Box::new((Height(10 + i as u32), blocks[i].clone())))
+                .unwrap();
+        }
+        let parsed: Vec<_> = (0..3).map(|_| rx_out.recv().unwrap()).collect();
+        assert_eq!(
+            parsed.iter().map(|b| b.height).collect::<Vec<_>>(),
+            vec![10, 11, 12]
+        );
+        for (parsed, block) in parsed.iter().zip(blocks.iter()) {
+            assert_eq!(parsed.block_hash, block.block_hash().to_string());
+        }
+
+        stopper.stop().unwrap();
+        for handle in handles {
+            handle.join().unwrap().unwrap();
+        }
+    }
+}
@@ -1,36 +1,36 @@
 use std::{thread::sleep, time::Duration};
 
-use bitcoin::hashes::Hash;
+use bitcoin::{hashes::Hash, BlockHash};
 use crossbeam_channel::{Receiver, Sender};
 use tracing::info;
 
 use crate::indexer::{
     bitcoin_client::BitcoinRpc,
+    config::OnReorg,
     database::DatabaseOps,
     stopper::Stopper,
-    types::{
-        error::Error,
-        pipeline::{BlockHasPrevBlockHash, PipelineDataInitial},
-    },
+    types::{error::Error, pipeline::PipelineDataInitial},
     utils::{in_reorg_window, with_retry},
 };
 
+// `on_reorg` hears of the first mismatch only, at the tip.
 fn get_last_matching_height<C, D, B>(
     client: &C,
     db: &D,
     stopper: Stopper,
     start_height: u32,
+    on_reorg: Option<&OnReorg>,
 ) -> Result<u32, Error>
 where
     C: BitcoinRpc<B>,
     D: DatabaseOps,
-    B: BlockHasPrevBlockHash,
 {
     for i in (1..=start_height).rev() {
         let current_block_hash = client.get_block_hash(i)?;
-        let current_block = client.get_block(&current_block_hash)?;
-        let expected_prev_block_hash = current_block
-            .get_prev_block_hash()
+        // Only the header is needed, which keeps headers-only mode free of block downloads.
+        let current_header = client.get_block_header(&current_block_hash)?;
+        let expected_prev_block_hash = current_header
+            .prev_blockhash
             .to_raw_hash()
             .to_byte_array()
             .to_vec();
@@ -48,6 +48,11 @@ where
         if prev_block_hash == expected_prev_block_hash {
             return Ok(j);
         }
+        if let Some(on_reorg) = on_reorg.filter(|_| i == start_height) {
+            if let Ok(stored_hash) = BlockHash::from_slice(&prev_block_hash) {
+                on_reorg.call(j, stored_hash, current_header.prev_blockhash);
+            }
+        }
     }
 
     Err(Error::NoHashMatchFound)
@@ -58,6 +63,8 @@ pub fn new<C, D, B>(
     db: D,
     start_height: u32,
     reorg_window: u32,
+    min_confirmations: u32,
+    on_reorg: Option<OnReorg>,
 ) -> impl Fn(
     Receiver<Box<PipelineDataInitial>>,
     Sender<Box<PipelineDataInitial>>,
@@ -67,7 +74,6 @@ pub fn new<C, D, B>(
 where
     C: BitcoinRpc<B>,
     D: DatabaseOps,
-    B: BlockHasPrevBlockHash,
 {
     move |_, tx, stopper| {
         let mut height = start_height;
@@ -84,7 +90,7 @@ where
             if target_height < height {
                 target_height = with_retry(
                     stopper.clone(),
-                    || client.get_blockchain_height(),
+                    || client.get_safe_height(min_confirmations),
                     "Error fetching blockchain info".into(),
                 )?;
             }
@@ -103,7 +109,13 @@ where
                 let last_matching_height = if height == start_height {
                     last_saved_height
                 } else {
-                    get_last_matching_height(&client, &db, stopper.clone(), height)?
+                    get_last_matching_height(
+                        &client,
+                        &db,
+                        stopper.clone(),
+                        height,
+                        on_reorg.as_ref(),
+                    )?
                 };
                 if last_matching_height < last_saved_height {
                     info!(
@@ -141,13 +153,19 @@ where
 #[cfg(test)]
 #[allow(clippy::unwrap_used)]
 mod tests {
+    use std::sync::{Arc, Mutex};
+
     use super::*;
-    use bitcoin::BlockHash;
+    use bitcoin::{
+        block::{self, Header},
+        BlockHash, CompactTarget, TxMerkleNode, TxOut, Txid,
+    };
 
     use crate::{
         indexer::{
             bitcoin_client::BitcoinRpc,
             database::Database,
+            rpc_client::NetworkInfo,
             test_utils::{test_block_hash, test_sha256_hash},
             types::{entry::BlockAtHeightHasHash, error::Error},
         },
@@ -165,12 +183,6 @@ mod tests {
         }
     }
 
-    impl BlockHasPrevBlockHash for MockBlock {
-        fn get_prev_block_hash(&self) -> &BlockHash {
-            &self.prev_hash
-        }
-    }
-
     #[derive(Clone)]
     struct MockBitcoinRpc {
         blocks_by_height: std::collections::HashMap<u32, BlockHash>, // Map height to block hash
@@ -202,9 +214,40 @@ mod tests {
             Ok(self.blocks_by_hash.get(hash).cloned().unwrap())
         }
 
+        fn get_block_header(&self, hash: &BlockHash) -> Result<Header, Error> {
+            Ok(Header {
+                version: block::Version::ONE,
+                prev_blockhash: self.get_block(hash)?.prev_hash,
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 0,
+                bits: CompactTarget::default(),
+                nonce: 0,
+            })
+        }
+
         fn get_blockchain_height(&self) -> Result<u32, Error> {
             Ok(self.blockchain_height)
         }
+
+        fn get_network_info(&self) -> Result<NetworkInfo, Error> {
+            Err(Error::BitcoinRpc("getnetworkinfo not mocked".into()))
+        }
+
+        fn get_chain(&self) -> Result<String, Error> {
+            Err(Error::BitcoinRpc("getblockchaininfo not mocked".into()))
+        }
+
+        fn get_tx_out(&self, _: &Txid, _: u32, _: bool) -> Result<Option<TxOut>, Error> {
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn test_get_safe_height() {
+        let mock_rpc = MockBitcoinRpc::new(vec![], 100);
+        assert_eq!(mock_rpc.get_safe_height(6).unwrap(), 94);
+        assert_eq!(mock_rpc.get_safe_height(0).unwrap(), 100);
+        assert_eq!(mock_rpc.get_safe_height(101).unwrap(), 0);
     }
 
     #[test]
@@ -242,7 +285,7 @@ mod tests {
         })
         .unwrap();
 
-        let result = get_last_matching_height(&mock_rpc, &db, Stopper::new(), 2).unwrap();
+        let result = get_last_matching_height(&mock_rpc, &db, Stopper::new(), 2, None).unwrap();
         assert_eq!(result, 1);
     }
 
@@ -299,7 +342,21 @@ mod tests {
         })
         .unwrap();
 
-        let result = get_last_matching_height(&mock_rpc, &db, Stopper::new(), 3).unwrap();
+        let reorgs = Arc::new(Mutex::new(Vec::new()));
+        let reorgs_clone = reorgs.clone();
+        let on_reorg = OnReorg(Arc::new(move |height, expected, actual| {
+            reorgs_clone
+                .lock()
+                .unwrap()
+                .push((height, expected, actual))
+        }));
+
+        let result =
+            get_last_matching_height(&mock_rpc, &db, Stopper::new(), 3, Some(&on_reorg)).unwrap();
         assert_eq!(result, 1);
+        assert_eq!(
+            *reorgs.lock().unwrap(),
+            vec![(2, test_block_hash(3), test_block_hash(2))]
+        );
     }
 }
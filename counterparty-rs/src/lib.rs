@@ -4,6 +4,9 @@ mod utils;
 
 use b58::register_b58_module;
 
+#[doc(hidden)]
+pub use indexer::bench;
+
 use indexer::register_indexer_module;
 use pyo3::prelude::*;
 use pyo3::types::PyString;
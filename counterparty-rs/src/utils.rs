@@ -8,6 +8,8 @@ use bitcoin::{
     address::NetworkChecked,
     hashes::Hash,
 };
+use crypto::rc4::Rc4;
+use crypto::symmetriccipher::SynchronousStreamCipher;
 use pyo3::prelude::*;
 use pyo3::types::PyBytes;
 use pyo3::wrap_pyfunction;
@@ -454,6 +456,47 @@ pub fn unpack_address(packed: Vec<u8>, network: &str) -> PyResult<String> {
     Ok(address.to_string())
 }
 
+// Parses the `m_of_n` destination string built by `parse_vout` for bare multisig
+// (`"2_addr1_addr2_addr3_3"`) back into (required, addresses, n).
+#[pyfunction]
+pub fn parse_multisig_address(s: &str) -> Option<(u8, Vec<String>, u8)> {
+    let tokens: Vec<&str> = s.split('_').collect();
+    if tokens.len() < 3 {
+        return None;
+    }
+    let required = tokens[0].parse::<u8>().ok()?;
+    let n = tokens[tokens.len() - 1].parse::<u8>().ok()?;
+    let addresses: Vec<String> = tokens[1..tokens.len() - 1]
+        .iter()
+        .map(|a| a.to_string())
+        .collect();
+    if addresses.len() != n as usize || addresses.iter().any(|a| a.is_empty()) {
+        return None;
+    }
+    Some((required, addresses, n))
+}
+
+// Runs `data` through the RC4 keystream of `key`, so the same call encrypts and
+// decrypts. Counterparty obfuscates data with the txid of the first input as the key.
+// RC4 keys are 1 to 256 bytes; other lengths raise a ValueError.
+#[pyfunction]
+pub fn arc4(key: &[u8], data: &[u8]) -> PyResult<Vec<u8>> {
+    if key.is_empty() || key.len() > 256 {
+        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+            "ARC4 key must be 1 to 256 bytes, got {}",
+            key.len()
+        )));
+    }
+    Ok(rc4(key, data))
+}
+
+// `arc4` without the key check, for callers whose key is always a 32 byte txid.
+pub(crate) fn rc4(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut result = vec![0; data.len()];
+    Rc4::new(key).process(data, &mut result);
+    result
+}
+
 pub fn register_utils_module(parent_module: &Bound<'_, PyModule>) -> PyResult<()> {
     let m = PyModule::new_bound(parent_module.py(), "utils")?;
     m.add_function(wrap_pyfunction!(inverse_hash, &m)?)?;
@@ -462,6 +505,72 @@ pub fn register_utils_module(parent_module: &Bound<'_, PyModule>) -> PyResult<()
     m.add_function(wrap_pyfunction!(script_to_address_legacy, &m)?)?;
     m.add_function(wrap_pyfunction!(pack_address, &m)?)?;
     m.add_function(wrap_pyfunction!(unpack_address, &m)?)?;
+    m.add_function(wrap_pyfunction!(parse_multisig_address, &m)?)?;
+    m.add_function(wrap_pyfunction!(arc4, &m)?)?;
     parent_module.add_submodule(&m)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_multisig_address() {
+        assert_eq!(
+            parse_multisig_address("2_addr1_addr2_addr3_3"),
+            Some((
+                2,
+                vec![
+                    "addr1".to_string(),
+                    "addr2".to_string(),
+                    "addr3".to_string()
+                ],
+                3
+            ))
+        );
+        assert_eq!(
+            parse_multisig_address("1_addr1_1"),
+            Some((1, vec!["addr1".to_string()], 1))
+        );
+    }
+
+    #[test]
+    fn test_parse_multisig_address_malformed() {
+        assert_eq!(parse_multisig_address(""), None);
+        assert_eq!(parse_multisig_address("addr1"), None);
+        assert_eq!(parse_multisig_address("1_3"), None);
+        assert_eq!(parse_multisig_address("x_addr1_addr2_2"), None);
+        assert_eq!(parse_multisig_address("1_addr1_addr2_y"), None);
+        assert_eq!(parse_multisig_address("1_addr1_addr2_3"), None);
+        assert_eq!(parse_multisig_address("1_addr1__2"), None);
+        assert_eq!(parse_multisig_address("300_addr1_1"), None);
+    }
+
+    #[test]
+    fn test_arc4_known_vectors() {
+        for (key, plaintext, ciphertext) in [
+            ("Key", "Plaintext", "bbf316e8d940af0ad3"),
+            ("Wiki", "pedia", "1021bf0420"),
+            ("Secret", "Attack at dawn", "45a01f645fc35b383552544b9bf5"),
+        ] {
+            let encrypted = arc4(key.as_bytes(), plaintext.as_bytes()).unwrap();
+            assert_eq!(hex::encode(&encrypted), ciphertext);
+            assert_eq!(
+                arc4(key.as_bytes(), &encrypted).unwrap(),
+                plaintext.as_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn test_arc4_empty_key() {
+        assert!(arc4(b"", b"data").is_err());
+    }
+
+    #[test]
+    fn test_arc4_key_too_long() {
+        assert!(arc4(&[1; 257], b"data").is_err());
+        assert!(arc4(&[1; 256], b"data").is_ok());
+    }
+}